@@ -0,0 +1,24 @@
+/* C ABI of win32-com-cli, built with
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ * Requests and results are the JSON documents the CLI reads and writes. */
+
+#ifndef WIN32_COM_CLI_H
+#define WIN32_COM_CLI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Runs one request on the calling thread and returns its result, never NULL.
+ * Panics are reported in the result as E_UNEXPECTED errors. The result must be
+ * freed with com_cli_free_string. */
+char *com_cli_execute_json(const char *request);
+
+/* Frees a result returned by com_cli_execute_json. NULL is ignored. */
+void com_cli_free_string(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,503 @@
+use crate::dispatch::to_wide;
+use crate::error::ComError;
+use crate::job;
+use serde::{Deserialize, Serialize};
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+use tracing::{debug, debug_span, field};
+use windows::Win32::Foundation::{E_INVALIDARG, E_POINTER, ERROR_SUCCESS, REGDB_E_CLASSNOTREG};
+use windows::Win32::System::Console::GetConsoleWindow;
+use windows::Win32::System::Ole::{GetActiveObject, IClassFactory2};
+use windows::Win32::System::Registry::{
+    HKEY, HKEY_CLASSES_ROOT, KEY_READ, KEY_WOW64_32KEY, KEY_WOW64_64KEY, RegCloseKey, RegOpenKeyExW,
+};
+use windows::Win32::System::Rpc::{
+    RPC_C_AUTHN_DEFAULT, RPC_C_AUTHZ_DEFAULT, SEC_WINNT_AUTH_IDENTITY_UNICODE,
+    SEC_WINNT_AUTH_IDENTITY_W,
+};
+use windows::{Win32::System::Com::*, core::*};
+
+/// How the object of a request is obtained
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ActivationOptions {
+    /// Attach to an already running instance registered in the Running Object Table
+    /// instead of starting a new one
+    #[serde(default)]
+    pub attach: bool,
+    /// Display name to bind to instead of a ProgID, such as `winmgmts:\\.\root\cimv2`
+    /// or the path of a document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moniker: Option<String>,
+    /// CLSID of the class to create, for servers that register no ProgID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clsid: Option<String>,
+    /// Kind of server allowed to satisfy the activation
+    #[serde(default)]
+    pub clsctx: ClassContext,
+    /// Remote host to activate the object on through DCOM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<String>,
+    /// Create the object in an elevated server through the elevation moniker, showing
+    /// a UAC consent prompt
+    #[serde(default)]
+    pub elevated: bool,
+    /// Security settings applied to the proxy once the object has been obtained
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication: Option<Authentication>,
+    /// Run-time license key of a licensed control, passed to
+    /// `IClassFactory2::CreateInstanceLic`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_key: Option<String>,
+}
+
+/// Security blanket for the proxy of an out-of-process or remote object
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Authentication {
+    #[serde(default)]
+    pub level: AuthenticationLevel,
+    #[serde(default)]
+    pub impersonation: ImpersonationLevel,
+    /// Explicit credentials, used instead of those of the current user when `user` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthenticationLevel {
+    #[default]
+    Default,
+    None,
+    Connect,
+    Call,
+    Packet,
+    PacketIntegrity,
+    PacketPrivacy,
+}
+
+impl From<AuthenticationLevel> for RPC_C_AUTHN_LEVEL {
+    fn from(level: AuthenticationLevel) -> Self {
+        match level {
+            AuthenticationLevel::Default => RPC_C_AUTHN_LEVEL_DEFAULT,
+            AuthenticationLevel::None => RPC_C_AUTHN_LEVEL_NONE,
+            AuthenticationLevel::Connect => RPC_C_AUTHN_LEVEL_CONNECT,
+            AuthenticationLevel::Call => RPC_C_AUTHN_LEVEL_CALL,
+            AuthenticationLevel::Packet => RPC_C_AUTHN_LEVEL_PKT,
+            AuthenticationLevel::PacketIntegrity => RPC_C_AUTHN_LEVEL_PKT_INTEGRITY,
+            AuthenticationLevel::PacketPrivacy => RPC_C_AUTHN_LEVEL_PKT_PRIVACY,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImpersonationLevel {
+    #[default]
+    Default,
+    Anonymous,
+    Identify,
+    Impersonate,
+    Delegate,
+}
+
+impl From<ImpersonationLevel> for RPC_C_IMP_LEVEL {
+    fn from(level: ImpersonationLevel) -> Self {
+        match level {
+            ImpersonationLevel::Default => RPC_C_IMP_LEVEL_DEFAULT,
+            ImpersonationLevel::Anonymous => RPC_C_IMP_LEVEL_ANONYMOUS,
+            ImpersonationLevel::Identify => RPC_C_IMP_LEVEL_IDENTIFY,
+            ImpersonationLevel::Impersonate => RPC_C_IMP_LEVEL_IMPERSONATE,
+            ImpersonationLevel::Delegate => RPC_C_IMP_LEVEL_DELEGATE,
+        }
+    }
+}
+
+/// Explicit credentials a proxy was given, which it keeps referring to for as long as
+/// it lives. They are zeroed once dropped, so they must outlive the proxy.
+pub struct Credentials {
+    user: Vec<u16>,
+    domain: Vec<u16>,
+    password: Vec<u16>,
+    identity: Box<SEC_WINNT_AUTH_IDENTITY_W>,
+}
+
+impl Credentials {
+    fn new(user: &str, domain: &str, password: &str) -> Self {
+        let wide = |text: &str| -> Vec<u16> { text.encode_utf16().collect() };
+        let (mut user, mut domain, mut password) = (wide(user), wide(domain), wide(password));
+        // The buffers of the vectors stay where they are when the vectors are moved
+        let identity = Box::new(SEC_WINNT_AUTH_IDENTITY_W {
+            User: user.as_mut_ptr(),
+            UserLength: user.len() as u32,
+            Domain: domain.as_mut_ptr(),
+            DomainLength: domain.len() as u32,
+            Password: password.as_mut_ptr(),
+            PasswordLength: password.len() as u32,
+            Flags: SEC_WINNT_AUTH_IDENTITY_UNICODE,
+        });
+
+        Self {
+            user,
+            domain,
+            password,
+            identity,
+        }
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        for buffer in [&mut self.user, &mut self.domain, &mut self.password] {
+            for unit in buffer.iter_mut() {
+                // Volatile so that the writes to memory about to be freed are kept
+                unsafe { std::ptr::write_volatile(unit, 0) };
+            }
+        }
+    }
+}
+
+/// Applies the security blanket to the proxy of an object, returning the credentials
+/// it refers to when explicit ones are given
+unsafe fn set_proxy_blanket(
+    object: &IDispatch,
+    authentication: &Authentication,
+) -> Result<Option<Credentials>> {
+    let credentials = authentication.user.as_deref().map(|user| {
+        Credentials::new(
+            user,
+            authentication.domain.as_deref().unwrap_or_default(),
+            authentication.password.as_deref().unwrap_or_default(),
+        )
+    });
+
+    unsafe {
+        CoSetProxyBlanket(
+            object,
+            RPC_C_AUTHN_DEFAULT as u32, // Let COM pick the authentication service
+            RPC_C_AUTHZ_DEFAULT,        // and the authorization service
+            PCWSTR::null(),             // Default server principal name
+            authentication.level.into(), // Authentication level
+            authentication.impersonation.into(), // Impersonation level
+            credentials.as_ref().map(|credentials| {
+                &*credentials.identity as *const SEC_WINNT_AUTH_IDENTITY_W as *const c_void
+            }),
+            EOAC_NONE, // No additional capabilities
+        )?;
+    }
+
+    Ok(credentials)
+}
+
+/// Where a new object may be activated
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassContext {
+    /// Any kind of server, preferring in-process ones
+    #[default]
+    All,
+    /// A DLL loaded into this process
+    #[serde(alias = "inproc")]
+    InprocServer,
+    /// An executable running on this machine, keeping a faulty server out of this process
+    #[serde(alias = "local")]
+    LocalServer,
+    /// A server on another machine
+    #[serde(alias = "remote")]
+    RemoteServer,
+    /// Any out-of-process or in-process server, excluding handlers
+    Server,
+}
+
+impl From<ClassContext> for CLSCTX {
+    fn from(context: ClassContext) -> Self {
+        match context {
+            ClassContext::All => CLSCTX_ALL,
+            ClassContext::InprocServer => CLSCTX_INPROC_SERVER,
+            ClassContext::LocalServer => CLSCTX_LOCAL_SERVER,
+            ClassContext::RemoteServer => CLSCTX_REMOTE_SERVER,
+            ClassContext::Server => CLSCTX_SERVER,
+        }
+    }
+}
+
+/// Parses a CLSID written with or without surrounding braces
+pub fn parse_clsid(text: &str) -> Result<GUID> {
+    let trimmed = text.trim();
+    let bare = trimmed
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(trimmed);
+
+    GUID::try_from(bare).map_err(|_| {
+        Error::new(
+            E_INVALIDARG,
+            format!(
+                "Invalid CLSID '{text}', expected the form \
+                {{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}}"
+            ),
+        )
+    })
+}
+
+/// Obtains the object of a request, either by binding its moniker or from the COM class
+/// given by its CLSID or registered under its ProgID, then applies its proxy security.
+/// The credentials of that security, if any, are returned along with the object.
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread, and the credentials must be
+/// kept until the object is released.
+pub unsafe fn create_object(
+    prog_id: Option<&str>,
+    options: &ActivationOptions,
+) -> Result<(IDispatch, Option<Credentials>)> {
+    let span = debug_span!(
+        "activate",
+        prog_id,
+        clsid = options.clsid.as_deref(),
+        moniker = options.moniker.as_deref(),
+        hresult = field::Empty
+    )
+    .entered();
+
+    unsafe {
+        // The servers of other machines are not processes of this one
+        let running = job::running_processes().filter(|_| options.machine.is_none());
+        let object = activate(prog_id, options).inspect_err(|error| {
+            span.record("hresult", ComError::from(error.clone()).hresult());
+        })?;
+
+        if let Some(running) = running {
+            job::contain(&object, &running);
+        }
+
+        let credentials = match &options.authentication {
+            Some(authentication) => set_proxy_blanket(&object, authentication)?,
+            None => None,
+        };
+
+        Ok((object, credentials))
+    }
+}
+
+unsafe fn activate(prog_id: Option<&str>, options: &ActivationOptions) -> Result<IDispatch> {
+    unsafe {
+        if let Some(moniker) = &options.moniker {
+            return bind_moniker(moniker);
+        }
+
+        let clsid = match (&options.clsid, prog_id) {
+            (Some(clsid), _) => parse_clsid(clsid)?,
+            (None, Some(prog_id)) => {
+                let prog_id = to_wide(prog_id);
+
+                CLSIDFromProgID(PCWSTR::from_raw(prog_id.as_ptr()))?
+            }
+            (None, None) => {
+                return Err(Error::new(
+                    E_INVALIDARG,
+                    "One of prog_id, clsid or moniker is required",
+                ));
+            }
+        };
+
+        if options.attach {
+            return get_active_object(&clsid);
+        }
+
+        if options.elevated {
+            if options.machine.is_some() || options.license_key.is_some() {
+                return Err(Error::new(
+                    E_INVALIDARG,
+                    "elevated cannot be combined with machine or license_key",
+                ));
+            }
+
+            return create_elevated_object(&clsid);
+        }
+
+        if let Some(key) = &options.license_key {
+            return create_licensed_object(&clsid, key, options.machine.as_deref(), options.clsctx);
+        }
+
+        match &options.machine {
+            Some(machine) => create_remote_object(&clsid, machine, options.clsctx),
+            None => create_local_object(&clsid, options.clsctx),
+        }
+    }
+}
+
+/// The bitness of the other view of the registry when it is the only one registering
+/// a DLL for a class, which a process of this bitness cannot load
+fn foreign_dll_bitness(clsid: &GUID) -> Option<u32> {
+    let (bitness, view) = match cfg!(target_pointer_width = "64") {
+        true => (32, KEY_WOW64_32KEY),
+        false => (64, KEY_WOW64_64KEY),
+    };
+    let path = HSTRING::from(format!("CLSID\\{{{clsid:?}}}\\InprocServer32"));
+    let mut key = HKEY::default();
+
+    unsafe {
+        if RegOpenKeyExW(HKEY_CLASSES_ROOT, &path, None, KEY_READ | view, &mut key) != ERROR_SUCCESS
+        {
+            return None;
+        }
+
+        let _ = RegCloseKey(key);
+    }
+
+    Some(bitness)
+}
+
+/// Creates an object on this machine. A class only registered as a DLL of the other
+/// bitness, such as a 32-bit ActiveX control under a 64-bit build, is created in the
+/// DLL surrogate of that bitness instead, where its calls are marshaled to.
+unsafe fn create_local_object(clsid: &GUID, clsctx: ClassContext) -> Result<IDispatch> {
+    let error = match unsafe { CoCreateInstance(clsid, None, CLSCTX::from(clsctx)) } {
+        Err(error) if error.code() == REGDB_E_CLASSNOTREG => error,
+        result => return result,
+    };
+    let in_process = matches!(
+        clsctx,
+        ClassContext::All | ClassContext::InprocServer | ClassContext::Server
+    );
+    let Some(bitness) = foreign_dll_bitness(clsid).filter(|_| in_process) else {
+        return Err(error);
+    };
+    let surrogate = match bitness {
+        32 => CLSCTX_ACTIVATE_32_BIT_SERVER,
+        _ => CLSCTX_ACTIVATE_64_BIT_SERVER,
+    };
+
+    debug!("Activating {{{clsid:?}}} in the {bitness}-bit DLL surrogate");
+
+    unsafe { CoCreateInstance(clsid, None, CLSCTX_LOCAL_SERVER | surrogate) }.map_err(|error| {
+        Error::new(
+            error.code(),
+            format!(
+                "{} (the class is only registered as a {bitness}-bit DLL, which cannot be \
+                loaded into this process, and the {bitness}-bit DLL surrogate failed to \
+                host it; its AppID needs a DllSurrogate value)",
+                error.message()
+            ),
+        )
+    })
+}
+
+/// Activates an object on another machine with CoCreateInstanceEx
+unsafe fn create_remote_object(
+    clsid: &GUID,
+    machine: &str,
+    clsctx: ClassContext,
+) -> Result<IDispatch> {
+    // Without an explicit context, ask for a remote server rather than a local one
+    let clsctx = match clsctx {
+        ClassContext::All => CLSCTX_REMOTE_SERVER,
+        clsctx => CLSCTX::from(clsctx),
+    };
+    let mut machine = to_wide(machine);
+    let server_info = COSERVERINFO {
+        pwszName: PWSTR::from_raw(machine.as_mut_ptr()),
+        ..Default::default()
+    };
+    let mut results = [MULTI_QI {
+        pIID: &IDispatch::IID,
+        ..Default::default()
+    }];
+
+    unsafe {
+        CoCreateInstanceEx(
+            clsid,
+            None::<&IUnknown>,
+            clsctx,
+            Some(&server_info),
+            &mut results,
+        )?;
+
+        let [result] = &mut results;
+
+        result.hr.ok()?;
+        ManuallyDrop::take(&mut result.pItf)
+            .ok_or_else(|| Error::from(E_POINTER))?
+            .cast()
+    }
+}
+
+/// Creates an instance of a licensed class through its IClassFactory2, handing the
+/// factory the run-time license key a design-time license would otherwise stand in for
+unsafe fn create_licensed_object(
+    clsid: &GUID,
+    key: &str,
+    machine: Option<&str>,
+    clsctx: ClassContext,
+) -> Result<IDispatch> {
+    let mut name = machine.map(to_wide);
+    let server_info = name.as_mut().map(|name| COSERVERINFO {
+        pwszName: PWSTR::from_raw(name.as_mut_ptr()),
+        ..Default::default()
+    });
+    let clsctx = match (clsctx, &server_info) {
+        (ClassContext::All, Some(_)) => CLSCTX_REMOTE_SERVER,
+        (clsctx, _) => CLSCTX::from(clsctx),
+    };
+
+    unsafe {
+        let factory: IClassFactory2 = CoGetClassObject(
+            clsid,
+            clsctx,
+            server_info
+                .as_ref()
+                .map(|info| info as *const COSERVERINFO as *const c_void),
+        )?;
+
+        factory.CreateInstanceLic(None, None, &BSTR::from(key))
+    }
+}
+
+/// Creates an object in an elevated local server through the
+/// `Elevation:Administrator!new:{clsid}` moniker. The class must be registered for
+/// elevation, and the consent prompt is owned by the console window.
+unsafe fn create_elevated_object(clsid: &GUID) -> Result<IDispatch> {
+    let display_name = to_wide(&format!("Elevation:Administrator!new:{{{clsid:?}}}"));
+    let bind_options = BIND_OPTS3 {
+        Base: BIND_OPTS2 {
+            Base: BIND_OPTS {
+                cbStruct: size_of::<BIND_OPTS3>() as u32,
+                ..Default::default()
+            },
+            // The elevation moniker only supports local servers
+            dwClassContext: CLSCTX_LOCAL_SERVER.0,
+            ..Default::default()
+        },
+        hwnd: unsafe { GetConsoleWindow() },
+    };
+
+    unsafe {
+        CoGetObject(
+            PCWSTR::from_raw(display_name.as_ptr()),
+            Some(&bind_options.Base.Base),
+        )
+    }
+}
+
+/// Binds to the object named by a moniker display name, like VBScript's `GetObject(name)`
+unsafe fn bind_moniker(display_name: &str) -> Result<IDispatch> {
+    let display_name = to_wide(display_name);
+
+    unsafe { CoGetObject(PCWSTR::from_raw(display_name.as_ptr()), None) }
+}
+
+/// Binds to the running instance of a class, like VBScript's `GetObject(, "ProgID")`
+unsafe fn get_active_object(clsid: &GUID) -> Result<IDispatch> {
+    let mut unknown = None;
+
+    unsafe {
+        GetActiveObject(clsid, None, &mut unknown)?;
+    }
+
+    unknown.ok_or_else(|| Error::from(E_POINTER))?.cast()
+}
@@ -0,0 +1,262 @@
+//! `--audit-log`, which appends a record of every step to a JSON Lines file. Every
+//! record holds the SHA-256 hash of the line before it, so that editing or removing a
+//! record breaks the chain, which `verify-audit-log` checks.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use windows::Win32::Security::Authentication::Identity::{GetUserNameExW, NameSamCompatible};
+use windows::Win32::System::SystemInformation::GetSystemTime;
+use windows::core::PWSTR;
+
+/// Hash the first record of a log refers to
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Result of the steps that succeeded
+const S_OK: &str = "0x00000000";
+
+/// One line of the log
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    /// UTC time the step finished, like `2024-05-01T09:30:00.125Z`
+    time: String,
+    /// Account running the CLI, like `CONTOSO\jdoe`
+    user: &'a str,
+    pid: u32,
+    op: &'a str,
+    /// The class a create step activates, or the member other steps use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<&'a str>,
+    /// The values passed, as JSON cut short
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<&'a str>,
+    /// HRESULT of the step, 0x00000000 when it succeeded
+    result: &'a str,
+    /// SHA-256 of the line before, in hex
+    previous: &'a str,
+}
+
+/// The end of the chain, where the next record is appended
+struct Chain {
+    file: File,
+    previous: String,
+}
+
+/// A layer appending a record to the audit log for every step
+pub struct AuditLog {
+    chain: Mutex<Chain>,
+    user: String,
+}
+
+fn sha256(line: &str) -> String {
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Reads the last line of a file from its end, without reading the records before it
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let mut end = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+
+    while end > 0 {
+        let start = end.saturating_sub(4096);
+        let mut chunk = vec![0; (end - start) as usize];
+
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.append(&mut tail);
+        tail = chunk;
+        end = start;
+
+        // The line is complete once a newline precedes it
+        let text = tail.strip_suffix(b"\n").unwrap_or(&tail);
+
+        if let Some(newline) = text.iter().rposition(|&byte| byte == b'\n') {
+            tail.drain(..=newline);
+            break;
+        }
+    }
+
+    let line = String::from_utf8_lossy(&tail);
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    Ok((!line.is_empty()).then(|| line.to_string()))
+}
+
+/// The account of the process, in `DOMAIN\user` form
+fn user_name() -> String {
+    let mut buffer = [0u16; 512];
+    let mut length = buffer.len() as u32;
+
+    if unsafe {
+        GetUserNameExW(
+            NameSamCompatible,
+            Some(PWSTR(buffer.as_mut_ptr())),
+            &mut length,
+        )
+    } {
+        String::from_utf16_lossy(&buffer[..length as usize])
+    } else {
+        String::from("unknown")
+    }
+}
+
+fn now() -> String {
+    let time = unsafe { GetSystemTime() };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond,
+        time.wMilliseconds
+    )
+}
+
+impl AuditLog {
+    /// Opens the log for appending, continuing the chain of the records it holds
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let previous =
+            last_line(&mut file)?.map_or_else(|| GENESIS.to_string(), |line| sha256(&line));
+
+        Ok(Self {
+            chain: Mutex::new(Chain { file, previous }),
+            user: user_name(),
+        })
+    }
+
+    /// The layer, which only sees steps
+    pub fn layer<S>(self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        self.with_filter(filter_fn(|metadata: &Metadata<'_>| {
+            metadata.name() == "step"
+        }))
+    }
+
+    fn append(&self, step: &StepFields) {
+        let mut chain = self.chain.lock().unwrap_or_else(|error| error.into_inner());
+        let record = AuditRecord {
+            time: now(),
+            user: &self.user,
+            pid: std::process::id(),
+            op: &step.op,
+            subject: step.subject.as_deref(),
+            args: step.args.as_deref(),
+            result: step.hresult.as_deref().unwrap_or(S_OK),
+            previous: &chain.previous,
+        };
+        let line = serde_json::to_string(&record).expect("Records serialize");
+
+        // Written in one piece, so that the line is either complete or missing
+        if let Err(error) = chain.file.write_all(format!("{line}\n").as_bytes()) {
+            eprintln!("Error: Failed to write the audit log: {error}");
+            return;
+        }
+
+        chain.previous = sha256(&line);
+    }
+}
+
+/// Fields of a step span, kept until the span closes
+#[derive(Default)]
+struct StepFields {
+    op: String,
+    subject: Option<String>,
+    args: Option<String>,
+    hresult: Option<String>,
+}
+
+impl Visit for StepFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "op" => self.op = value.to_string(),
+            "subject" => self.subject = Some(value.to_string()),
+            "args" => self.args = Some(value.to_string()),
+            "hresult" => self.hresult = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl<S> Layer<S> for AuditLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        if let Some(span) = context.span(id) {
+            let mut fields = StepFields::default();
+
+            attributes.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        if let Some(span) = context.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<StepFields>()
+        {
+            values.record(fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, context: Context<'_, S>) {
+        if let Some(span) = context.span(&id)
+            && let Some(fields) = span.extensions().get::<StepFields>()
+        {
+            self.append(fields);
+        }
+    }
+}
+
+/// Checks the chain of an audit log, reporting the number of records and the first
+/// line that does not follow from the one before it
+pub fn verify(path: &Path) -> io::Result<Value> {
+    let mut previous = GENESIS.to_string();
+    let mut records = 0;
+
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let linked = serde_json::from_str::<Value>(&line)
+            .ok()
+            .is_some_and(|record| record["previous"] == previous.as_str());
+
+        if !linked {
+            return Ok(json!({
+                "ok": false,
+                "records": records,
+                "line": index + 1,
+                "error": "The record does not hold the hash of the line before it",
+            }));
+        }
+
+        previous = sha256(&line);
+        records += 1;
+    }
+
+    Ok(json!({ "ok": true, "records": records }))
+}
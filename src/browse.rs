@@ -0,0 +1,335 @@
+//! `browse` subcommand, an object browser in the terminal. It lists the properties and
+//! methods an object's type information declares along with the current values of its
+//! properties, and opens the objects properties hold to browse them in turn.
+
+use crate::describe::create;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::io;
+use win32_com_cli::session::RunOptions;
+use win32_com_cli::typeinfo::{Member, MemberKind, describe_type, object_type_info};
+use win32_com_cli::{ComObject, ComSession, Result};
+
+const HELP: &str = "↑↓ select  Enter open  ← back  r reload  q quit";
+
+/// What reading a property gave
+enum Reading {
+    Value(Value),
+    Object(ComObject),
+    Failed(String),
+}
+
+/// A property or method of an object
+struct Entry {
+    /// The property get, or the put of properties that cannot be read
+    member: Member,
+    property: bool,
+    settable: bool,
+    /// The value of properties read without arguments
+    reading: Option<Reading>,
+}
+
+/// An object being browsed, below the one whose property holds it
+struct Level {
+    /// The path of the object from the one the browser was opened on
+    path: String,
+    object: ComObject,
+    interface: String,
+    entries: Vec<Entry>,
+    state: ListState,
+}
+
+pub struct Browser {
+    levels: Vec<Level>,
+    status: Option<String>,
+    // Declared last so that the objects are released before the session
+    _session: ComSession,
+}
+
+impl Entry {
+    /// The properties of an interface sorted by name, followed by its methods
+    fn list(members: Vec<Member>) -> Vec<Self> {
+        let mut entries: Vec<Self> = Vec::new();
+
+        for member in members {
+            let property = member.kind != MemberKind::Method;
+            let settable = matches!(
+                member.kind,
+                MemberKind::PropertyPut | MemberKind::PropertyPutRef
+            );
+            let listed = entries
+                .iter_mut()
+                .find(|entry| property && entry.property && entry.member.name == member.name);
+
+            // Properties are listed once, by their get when they have one
+            match listed {
+                Some(entry) => {
+                    entry.settable |= settable;
+
+                    if member.kind == MemberKind::PropertyGet {
+                        entry.member = member;
+                    }
+                }
+                None => entries.push(Self {
+                    member,
+                    property,
+                    settable,
+                    reading: None,
+                }),
+            }
+        }
+
+        entries.sort_by(|left, right| {
+            (!left.property, &left.member.name).cmp(&(!right.property, &right.member.name))
+        });
+
+        entries
+    }
+
+    fn readable(&self) -> bool {
+        self.member.kind == MemberKind::PropertyGet && self.member.params.is_empty()
+    }
+
+    /// The declaration of the member, like `Open(FileName: string, [ReadOnly: bool]) -> Workbook`
+    fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .member
+            .params
+            .iter()
+            .map(|param| {
+                let text = format!("{}: {}", param.name, param.param_type);
+
+                match param.optional {
+                    true => format!("[{text}]"),
+                    false => text,
+                }
+            })
+            .collect();
+        let mut signature = self.member.name.clone();
+
+        if !self.property || !params.is_empty() {
+            signature = format!("{signature}({})", params.join(", "));
+        }
+
+        match self.member.return_type.as_str() {
+            "void" => signature,
+            return_type => format!("{signature} -> {return_type}"),
+        }
+    }
+
+    /// The value shown next to the name of a property
+    fn summary(&self) -> String {
+        match &self.reading {
+            Some(Reading::Value(value)) => value.to_string(),
+            Some(Reading::Object(_)) => String::from("{object} ▸"),
+            Some(Reading::Failed(error)) => format!("<{error}>"),
+            None if !self.property => String::from("method"),
+            None if self.member.kind != MemberKind::PropertyGet => String::from("<write-only>"),
+            None => String::from("<takes arguments>"),
+        }
+    }
+}
+
+impl Level {
+    fn open(path: String, object: ComObject) -> Result<Self> {
+        let description = unsafe { describe_type(&object_type_info(object.dispatch())?)? };
+        let mut level = Self {
+            path,
+            object,
+            interface: description.name,
+            entries: Entry::list(description.members),
+            state: ListState::default().with_selected(Some(0)),
+        };
+
+        level.read();
+        Ok(level)
+    }
+
+    /// Reads the properties again, as objects change them along the way
+    fn read(&mut self) {
+        for entry in &mut self.entries {
+            if !entry.readable() {
+                continue;
+            }
+
+            let name = &entry.member.name;
+
+            entry.reading = Some(match self.object.get(name) {
+                // Objects are kept by the entry, not under a handle
+                Ok(value) => match self.object.resolve(&value) {
+                    Some(_) => match self.object.take(&value, name) {
+                        Ok(object) => Reading::Object(object),
+                        Err(error) => Reading::Failed(error.to_string()),
+                    },
+                    None => Reading::Value(value),
+                },
+                Err(error) => Reading::Failed(error.to_string()),
+            });
+        }
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.state.selected()?)
+    }
+}
+
+impl Browser {
+    /// Creates the object of a ProgID or CLSID and reads its properties
+    pub fn open(target: &str) -> Result<Self> {
+        let mut session = ComSession::new(&RunOptions::default());
+        let object = create(&mut session, target)?;
+
+        Ok(Self {
+            levels: vec![Level::open(target.to_string(), object)?],
+            status: None,
+            _session: session,
+        })
+    }
+
+    /// Takes over the terminal until the browser is quit
+    pub fn run(mut self) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Consoles report the release of keys too
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            self.status = None;
+
+            let level = self
+                .levels
+                .last_mut()
+                .expect("The first level is never left");
+
+            match key.code {
+                KeyCode::Char('q') => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => level.state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => level.state.select_next(),
+                KeyCode::Home => level.state.select_first(),
+                KeyCode::End => level.state.select_last(),
+                KeyCode::Char('r') => level.read(),
+                KeyCode::Enter | KeyCode::Right => self.enter(),
+                KeyCode::Left | KeyCode::Backspace | KeyCode::Esc if self.levels.len() > 1 => {
+                    self.levels.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Opens the object the selected property holds
+    fn enter(&mut self) {
+        let level = self.levels.last().expect("The first level is never left");
+        let Some(entry) = level.selected() else {
+            return;
+        };
+        let Some(Reading::Object(object)) = &entry.reading else {
+            self.status = Some(format!("{} does not hold an object", entry.member.name));
+            return;
+        };
+        let name = entry.member.name.clone();
+        let path = format!("{}.{name}", level.path);
+
+        match Level::open(path, object.clone()) {
+            Ok(level) => self.levels.push(level),
+            Err(error) => self.status = Some(format!("Failed to open {name}: {error}")),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [members, details] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+        let level = self
+            .levels
+            .last_mut()
+            .expect("The first level is never left");
+        let width = level
+            .entries
+            .iter()
+            .map(|entry| entry.member.name.chars().count())
+            .max()
+            .unwrap_or_default();
+        let items: Vec<ListItem> = level
+            .entries
+            .iter()
+            .map(|entry| {
+                let name = format!("{:width$}  ", entry.member.name);
+                let summary = match entry.reading {
+                    Some(Reading::Value(_) | Reading::Object(_)) => entry.summary().into(),
+                    _ => entry.summary().dark_gray(),
+                };
+
+                ListItem::new(Line::from(vec![Span::from(name).bold(), summary]))
+            })
+            .collect();
+        let title = format!(" {} ({}) ", level.path, level.interface);
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed());
+
+        frame.render_stateful_widget(list, members, &mut level.state);
+
+        let text = level.selected().map(details_of).unwrap_or_default();
+        let paragraph = Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(" Member "));
+
+        frame.render_widget(paragraph, details);
+
+        let status_line = match &self.status {
+            Some(message) => Line::from(message.as_str()).red(),
+            None => Line::from(HELP).dark_gray(),
+        };
+
+        frame.render_widget(status_line, status);
+    }
+}
+
+/// The declaration, DISPID and documentation of a member, and the whole value of a
+/// property
+fn details_of(entry: &Entry) -> Vec<Line<'static>> {
+    let kind = match (entry.property, entry.settable) {
+        (false, _) => "method",
+        (true, true) => "property",
+        (true, false) => "read-only property",
+    };
+    let mut lines = vec![
+        Line::from(entry.signature()).bold(),
+        Line::from(format!("{kind}, DISPID {}", entry.member.dispid)),
+    ];
+
+    if let Some(doc) = &entry.member.doc {
+        lines.extend([Line::default(), Line::from(doc.clone())]);
+    }
+
+    if let Some(Reading::Value(value)) = &entry.reading {
+        let value = serde_json::to_string_pretty(value).expect("Values serialize");
+
+        lines.push(Line::default());
+        lines.extend(value.lines().map(|line| Line::from(line.to_string())));
+    }
+
+    lines
+}
@@ -0,0 +1,383 @@
+//! Command-line arguments. Without a subcommand, requests are read from stdin, which is
+//! how the CLI has always been driven.
+
+use crate::format::Format;
+use clap::error::ErrorKind;
+use clap::{ArgAction, ArgGroup, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use serde_json::{Map, Value, json};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Automates Win32 COM objects through IDispatch, with requests and results as JSON",
+    group(ArgGroup::new("mode").args(["validate", "serve", "listen", "emit_schema", "worker"]))
+)]
+pub struct Cli {
+    /// Encoding of the requests and results
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Writes the results to a file instead of stdout
+    #[arg(long, global = true, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Appends to the file of --output instead of replacing it
+    #[arg(long, global = true, requires = "output")]
+    pub append: bool,
+
+    /// Indents JSON results, which are written one per line by default
+    #[arg(long, global = true, conflicts_with = "compact")]
+    pub pretty: bool,
+
+    /// Writes JSON documents on a single line, including those of describe, resolve,
+    /// list-progids and --emit-schema
+    #[arg(long, global = true)]
+    pub compact: bool,
+
+    /// Reports activations and the time every step and call took too, and with -vv the
+    /// DISPID lookups. Overrides RUST_LOG.
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Reports no progress, such as the properties being set and the methods being
+    /// called, only warnings and the results. Overrides RUST_LOG.
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Reports the steps that fail to the Application event log, for unattended runs
+    #[arg(long, global = true)]
+    pub event_log: bool,
+
+    /// Expands ${env:NAME} placeholders within the strings of requests with environment
+    /// variables
+    #[arg(long, global = true)]
+    pub expand_env: bool,
+
+    /// Replaces ${param:NAME} placeholders within the strings of requests, may be
+    /// repeated
+    #[arg(long, global = true, value_name = "NAME=VALUE", value_parser = parse_parameter)]
+    pub param: Vec<(String, String)>,
+
+    /// Fails the requests passing values that have no faithful VARIANT form, such as
+    /// objects that are no known tag, instead of passing VT_EMPTY
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Terminates the local servers the requests start, such as EXCEL.EXE, when the
+    /// CLI exits or is killed instead of leaving them running
+    #[arg(long, global = true)]
+    pub kill_servers_on_exit: bool,
+
+    /// Appends a hash-chained record of every step to a JSON Lines file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Runs every request, and every session of --serve and --listen, in a worker
+    /// process of its own, so that a crashing in-process server cannot take the CLI down
+    #[arg(long, global = true, conflicts_with = "audit_log")]
+    pub isolate: bool,
+
+    /// Plans the requests read from stdin without invoking anything
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Serves a JSON-RPC session over stdin and stdout
+    #[arg(long)]
+    pub serve: bool,
+
+    /// Serves clients connecting to pipe:<name>, tcp:<host>:<port>, http:<host>:<port>
+    /// or ws:<host>:<port>
+    #[arg(long, value_name = "ADDRESS")]
+    pub listen: Option<String>,
+
+    /// Prints the JSON Schema of requests
+    #[arg(long)]
+    pub emit_schema: bool,
+
+    /// Runs the steps of a session isolated by --isolate, read from stdin
+    #[arg(long, hide = true)]
+    pub worker: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the requests of a file, of --inline or of stdin
+    Run(RunArgs),
+    /// Calls a method described by flags instead of a JSON request
+    Call(CallArgs),
+    /// Prints the members of an object as declared by its type information
+    Describe {
+        /// ProgID, or CLSID in braces
+        #[arg(value_name = "PROG_ID|CLSID")]
+        target: String,
+    },
+    /// Browses the members of an object and the values of its properties in the
+    /// terminal, opening the objects they hold
+    Browse {
+        /// ProgID, or CLSID in braces
+        #[arg(value_name = "PROG_ID|CLSID")]
+        target: String,
+    },
+    /// Prints typed Rust wrappers for the types of a type library
+    Codegen {
+        /// ProgID, CLSID in braces, or path of a type library
+        #[arg(value_name = "PROG_ID|CLSID|TYPE_LIBRARY")]
+        target: String,
+    },
+    /// Lists the registered ProgIDs
+    ListProgids {
+        /// Only lists the ProgIDs containing this text, regardless of case
+        filter: Option<String>,
+    },
+    /// Prints the registration of a class: CLSID, ProgIDs and servers
+    Resolve {
+        /// ProgID, or CLSID in braces
+        #[arg(value_name = "PROG_ID|CLSID")]
+        target: String,
+    },
+    /// Lists the Running Object Table, or binds to one of its objects
+    Rot {
+        #[command(subcommand)]
+        command: RotCommand,
+    },
+    /// Checks that no record of an --audit-log file was edited or removed
+    VerifyAuditLog {
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+    },
+    /// Registers the source --event-log reports under, which takes an elevated prompt
+    RegisterEventSource {
+        /// Removes the registration instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Prints the completion script of a shell, which completes ProgIDs too in
+    /// PowerShell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RotCommand {
+    /// Prints the display name and class of every running object, which the moniker
+    /// of requests can bind to
+    List,
+    /// Binds to a running object and prints its members, like describe
+    Get {
+        /// Display name, as listed by rot list
+        display_name: String,
+    },
+}
+
+#[derive(Args, Default)]
+pub struct RunArgs {
+    /// File holding the requests
+    #[arg(conflicts_with_all = ["stdin", "inline"])]
+    pub file: Option<PathBuf>,
+
+    /// Reads the requests from stdin, the default without a file or --inline
+    #[arg(long, conflicts_with = "inline")]
+    pub stdin: bool,
+
+    /// Runs the requests written as JSON text
+    #[arg(long, value_name = "JSON")]
+    pub inline: Option<String>,
+
+    /// Plans the requests without invoking anything
+    #[arg(long)]
+    pub validate: bool,
+}
+
+/// A single call, translated into the request it stands for. Values are read as JSON
+/// when they parse as such, such as `true` or `42`, and as strings otherwise.
+#[derive(Args)]
+#[command(group(ArgGroup::new("class").args(["prog_id", "clsid", "moniker"]).required(true)))]
+pub struct CallArgs {
+    /// ProgID of the class to create
+    #[arg(long)]
+    pub prog_id: Option<String>,
+
+    /// CLSID of the class to create
+    #[arg(long)]
+    pub clsid: Option<String>,
+
+    /// Display name of the object to bind to
+    #[arg(long)]
+    pub moniker: Option<String>,
+
+    /// Binds to a running instance instead of creating one
+    #[arg(long)]
+    pub attach: bool,
+
+    /// Sets a property before the call, may be repeated
+    #[arg(long, value_name = "PROPERTY=VALUE", value_parser = parse_assignment)]
+    pub set: Vec<(String, Value)>,
+
+    /// Method to call, which may be a path
+    #[arg(long)]
+    pub method: String,
+
+    /// Passes a positional argument, may be repeated
+    #[arg(long, value_name = "VALUE", value_parser = parse_value)]
+    pub arg: Vec<Value>,
+
+    /// Passes an argument by name, may be repeated
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_assignment)]
+    pub named: Vec<(String, Value)>,
+
+    /// Reads a property after the call, may be repeated
+    #[arg(long, value_name = "PROPERTY")]
+    pub get: Vec<String>,
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    Ok(serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())))
+}
+
+fn parse_assignment(text: &str) -> Result<(String, Value), String> {
+    let (name, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VALUE, got '{text}'"))?;
+
+    Ok((name.to_string(), parse_value(value)?))
+}
+
+fn parse_parameter(text: &str) -> Result<(String, String), String> {
+    text.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("Expected NAME=VALUE, got '{text}'"))
+}
+
+impl CallArgs {
+    /// The call request the flags stand for
+    pub fn request(&self) -> Value {
+        let mut request = json!({
+            "version": "1",
+            "method": self.method,
+            "properties": Map::from_iter(self.set.iter().cloned()),
+            "args": self.arg,
+            "named_args": Map::from_iter(self.named.iter().cloned()),
+            "get": self.get,
+            "attach": self.attach,
+        });
+
+        for (field, value) in [
+            ("prog_id", &self.prog_id),
+            ("clsid", &self.clsid),
+            ("moniker", &self.moniker),
+        ] {
+            if let Some(value) = value {
+                request[field] = json!(value);
+            }
+        }
+
+        request
+    }
+}
+
+impl Cli {
+    /// Parses the arguments of the process, exiting with a usage error when they are
+    /// inconsistent
+    pub fn parse_args() -> Self {
+        let cli = Self::parse();
+        let mode =
+            cli.validate || cli.serve || cli.listen.is_some() || cli.emit_schema || cli.worker;
+
+        if mode && cli.command.is_some() {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--validate, --serve, --listen and --emit-schema apply to stdin and \
+                    cannot be combined with a subcommand",
+                )
+                .exit();
+        }
+
+        // Servers answer their clients on stdout or their own transport
+        if cli.output.is_some() && (cli.serve || cli.listen.is_some()) {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--output cannot be combined with --serve or --listen",
+                )
+                .exit();
+        }
+
+        // Clients are not meant to read the environment of the server, and write
+        // their own requests
+        if (cli.expand_env || !cli.param.is_empty()) && (cli.serve || cli.listen.is_some()) {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--expand-env and --param cannot be combined with --serve or --listen",
+                )
+                .exit();
+        }
+
+        cli
+    }
+
+    /// The arguments worker processes are started with under --isolate, which report
+    /// what the CLI reports. Placeholders are filled in before requests reach them.
+    pub fn worker_arguments(&self) -> Vec<OsString> {
+        let mut arguments = Vec::new();
+
+        match (self.quiet, self.verbose) {
+            (true, _) => arguments.push("--quiet".into()),
+            (false, 0) => {}
+            (false, verbose) => arguments.push(format!("-{}", "v".repeat(verbose.into())).into()),
+        }
+
+        if self.event_log {
+            arguments.push("--event-log".into());
+        }
+
+        if self.kill_servers_on_exit {
+            arguments.push("--kill-servers-on-exit".into());
+        }
+
+        arguments
+    }
+
+    /// The layout asked for, `true` for pretty JSON
+    pub fn pretty(&self) -> Option<bool> {
+        match (self.pretty, self.compact) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Which diagnostics are written to stderr: those of `-q` or `-v` when given, else
+    /// those of `RUST_LOG`, else progress and warnings
+    pub fn log_filter(&self) -> EnvFilter {
+        // The browser draws over the terminal, which messages would scramble
+        if matches!(self.command, Some(Command::Browse { .. })) {
+            return EnvFilter::new("off");
+        }
+
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::WARN,
+            (false, 0) => {
+                return EnvFilter::builder()
+                    .with_default_directive(LevelFilter::INFO.into())
+                    .from_env_lossy();
+            }
+            (false, 1) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        };
+
+        // Dependencies only report their warnings
+        EnvFilter::new(format!("warn,win32_com_cli={level}"))
+    }
+}
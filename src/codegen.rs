@@ -0,0 +1,448 @@
+//! `codegen` subcommand, which writes a Rust module of typed wrappers for the dispatch
+//! interfaces of a type library. The wrappers call through `ComObject`, so they need
+//! nothing but this crate and serde_json.
+
+use crate::describe;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+use win32_com_cli::session::RunOptions;
+use win32_com_cli::typeinfo::{
+    Member, MemberKind, Param, TypeDescription, TypeKind, TypeLibrary, containing_library,
+    describe_library, load_library, object_type_info,
+};
+use win32_com_cli::{ComSession, Result};
+
+/// Reads the type library of a ProgID or CLSID, whose object is created to ask it, or
+/// of a `.tlb`, `.olb`, `.dll` or `.exe` file
+pub fn read_library(target: &str) -> Result<TypeLibrary> {
+    // Keeps the apartment entered while the library is read
+    let mut session = ComSession::new(&RunOptions::default());
+
+    unsafe {
+        let library = if Path::new(target).is_file() {
+            load_library(target)?
+        } else {
+            let object = describe::create(&mut session, target)?;
+
+            containing_library(&object_type_info(object.dispatch())?)?
+        };
+
+        describe_library(&library)
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+/// Converts a member name such as `ActiveWorkbook` or `XMLMaps` to snake case
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.trim_start_matches('_').chars().collect();
+    let mut snake = String::new();
+
+    for (index, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let previous = index.checked_sub(1).map(|index| chars[index]);
+            let next = chars.get(index + 1);
+            let starts_word = previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                || (previous.is_some_and(char::is_uppercase)
+                    && next.is_some_and(|n| n.is_lowercase()));
+
+            if starts_word && !snake.ends_with('_') {
+                snake.push('_');
+            }
+
+            snake.extend(c.to_lowercase());
+        } else if c.is_alphanumeric() {
+            snake.push(c);
+        } else if !snake.ends_with('_') {
+            snake.push('_');
+        }
+    }
+
+    identifier(snake)
+}
+
+/// Makes a name usable as an identifier
+fn identifier(name: String) -> String {
+    match name.as_str() {
+        "" => "member".to_string(),
+        "self" | "Self" | "crate" | "super" => format!("{name}_"),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => format!("_{name}"),
+        _ if KEYWORDS.contains(&name.as_str()) => format!("r#{name}"),
+        _ => name,
+    }
+}
+
+/// VARIANT types taken as the Rust type of the same name
+const PLAIN_TYPES: &[&str] = &[
+    "bool", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64",
+];
+
+/// What a parameter or result is in the generated code
+enum RustType {
+    Plain(&'static str),
+    Enum(String),
+    Object(String),
+    Value,
+}
+
+struct Generator<'a> {
+    /// Rust names of the dispatch interfaces, and of the coclasses implementing them
+    objects: HashMap<&'a str, String>,
+    /// Rust names of the enumerations
+    enums: HashMap<&'a str, String>,
+    out: String,
+}
+
+impl<'a> Generator<'a> {
+    fn new(library: &'a TypeLibrary) -> Self {
+        let mut objects = HashMap::new();
+        let mut enums = HashMap::new();
+        let mut used = HashSet::new();
+        let mut type_name = |name: &str| {
+            let stripped = name.trim_start_matches('_');
+            let rust = if !stripped.is_empty() && used.insert(stripped.to_string()) {
+                stripped.to_string()
+            } else {
+                format!("{name}_")
+            };
+
+            identifier(rust)
+        };
+
+        for description in &library.types {
+            match description.kind {
+                TypeKind::Dispatch => {
+                    objects.insert(description.name.as_str(), type_name(&description.name));
+                }
+                TypeKind::Enum => {
+                    enums.insert(description.name.as_str(), type_name(&description.name));
+                }
+                _ => {}
+            }
+        }
+
+        // Properties such as Excel's ActiveWorkbook are declared as returning the coclass
+        for description in &library.types {
+            if description.kind == TypeKind::Coclass
+                && let Some(interface) = &description.default_interface
+                && let Some(rust) = objects.get(interface.as_str()).cloned()
+            {
+                objects.entry(description.name.as_str()).or_insert(rust);
+            }
+        }
+
+        Self {
+            objects,
+            enums,
+            out: String::new(),
+        }
+    }
+
+    fn rust_type(&self, name: &str) -> RustType {
+        if let Some(plain) = PLAIN_TYPES.iter().find(|plain| **plain == name) {
+            return RustType::Plain(plain);
+        }
+
+        match (name, self.objects.get(name), self.enums.get(name)) {
+            ("string", ..) => RustType::Plain("&str"),
+            ("dispatch", ..) => RustType::Object("ComObject".to_string()),
+            (_, Some(object), _) => RustType::Object(object.clone()),
+            (_, _, Some(enumeration)) => RustType::Enum(enumeration.clone()),
+            _ => RustType::Value,
+        }
+    }
+
+    /// The type a parameter is taken as. Objects are passed as JSON values, such as a
+    /// `{"$ref": ...}` to an object of a session.
+    fn param_type(&self, param: &Param) -> String {
+        let rust = match self.rust_type(&param.param_type) {
+            RustType::Plain(rust) => rust.to_string(),
+            RustType::Enum(rust) => rust,
+            RustType::Object(_) | RustType::Value => "Value".to_string(),
+        };
+
+        match param.optional {
+            true => format!("Option<{rust}>"),
+            false => rust,
+        }
+    }
+
+    fn doc(&mut self, indent: &str, doc: Option<&str>) {
+        for line in doc
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            let _ = writeln!(self.out, "{indent}/// {}", line.trim());
+        }
+    }
+
+    fn generate(mut self, library: &TypeLibrary) -> String {
+        let _ = writeln!(
+            self.out,
+            "//! Typed wrappers for the {} type library, generated by `win32-com-cli codegen`.",
+            library.name
+        );
+        let _ = writeln!(self.out, "//!");
+        let _ = writeln!(
+            self.out,
+            "//! Every wrapper holds the [`ComObject`] it calls through. Results that are not"
+        );
+        let _ = writeln!(
+            self.out,
+            "//! objects are returned as JSON values, as the CLI returns them."
+        );
+        self.out.push_str(
+            "\n#![allow(dead_code, non_upper_case_globals)]\n\
+             #![allow(clippy::too_many_arguments, clippy::useless_conversion)]\n\n\
+             use serde_json::Value;\n\
+             use win32_com_cli::activation::ActivationOptions;\n\
+             use win32_com_cli::{ComObject, Result};\n\n\
+             /// Builds the path of a member with index arguments, such as `Item(1)`\n\
+             fn indexed(name: &str, args: &[Value]) -> String {\n    \
+                 let args: Vec<String> = args.iter().map(Value::to_string).collect();\n\n    \
+                 format!(\"{name}({})\", args.join(\",\"))\n\
+             }\n",
+        );
+
+        for description in &library.types {
+            match description.kind {
+                TypeKind::Enum => self.enumeration(description),
+                TypeKind::Dispatch => self.interface(description, library),
+                _ => {}
+            }
+        }
+
+        self.out
+    }
+
+    fn enumeration(&mut self, description: &TypeDescription) {
+        let name = self.enums[description.name.as_str()].clone();
+
+        self.out.push('\n');
+        self.doc("", description.doc.as_deref());
+        let _ = writeln!(
+            self.out,
+            "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]\npub struct {name}(pub i32);\n"
+        );
+        let _ = writeln!(self.out, "impl {name} {{");
+
+        for constant in &description.constants {
+            if let Some(value) = constant
+                .value
+                .as_i64()
+                .and_then(|value| i32::try_from(value).ok())
+            {
+                let _ = writeln!(
+                    self.out,
+                    "    pub const {}: Self = Self({value});",
+                    identifier(constant.name.clone())
+                );
+            }
+        }
+
+        let _ = writeln!(
+            self.out,
+            "}}\n\nimpl From<{name}> for Value {{\n    fn from(value: {name}) -> Self {{\n        \
+             value.0.into()\n    }}\n}}"
+        );
+    }
+
+    fn interface(&mut self, description: &TypeDescription, library: &TypeLibrary) {
+        let name = self.objects[description.name.as_str()].clone();
+        let coclasses: Vec<&TypeDescription> = library
+            .types
+            .iter()
+            .filter(|coclass| {
+                coclass.kind == TypeKind::Coclass
+                    && coclass.default_interface.as_deref() == Some(description.name.as_str())
+            })
+            .collect();
+
+        self.out.push('\n');
+        self.doc("", description.doc.as_deref());
+        let _ = writeln!(
+            self.out,
+            "#[derive(Clone)]\npub struct {name}(pub ComObject);\n\nimpl {name} {{"
+        );
+
+        // Only a single coclass makes for an unambiguous constructor
+        if let [coclass] = coclasses[..] {
+            let _ = writeln!(
+                self.out,
+                "    /// Creates a new {} object\n    pub fn create() -> Result<Self> {{\n        \
+                 let activation = ActivationOptions {{\n            \
+                 clsid: Some({:?}.to_string()),\n            \
+                 ..ActivationOptions::default()\n        }};\n\n        \
+                 ComObject::create_with(None, &activation).map(Self)\n    }}",
+                coclass.name, coclass.guid
+            );
+        }
+
+        let mut used = HashSet::from(["create".to_string()]);
+
+        for member in &description.members {
+            let function = match member.kind {
+                MemberKind::PropertyPut | MemberKind::PropertyPutRef => {
+                    format!("set_{}", snake_case(&member.name).trim_start_matches("r#"))
+                }
+                _ => snake_case(&member.name),
+            };
+
+            // Puts and put-refs of the same property, or overloads, get one wrapper
+            if !used.insert(function.clone()) {
+                continue;
+            }
+
+            self.member(member, &function);
+        }
+
+        let _ = writeln!(self.out, "}}");
+    }
+
+    fn member(&mut self, member: &Member, function: &str) {
+        let is_put = matches!(
+            member.kind,
+            MemberKind::PropertyPut | MemberKind::PropertyPutRef
+        );
+
+        // Indexed puts cannot be expressed as a path
+        if is_put && member.params.len() != 1 {
+            return;
+        }
+
+        let names: Vec<String> = member
+            .params
+            .iter()
+            .map(|param| snake_case(&param.name))
+            .collect();
+        let mut signature = vec!["&self".to_string()];
+        signature.extend(
+            member
+                .params
+                .iter()
+                .zip(&names)
+                .map(|(param, name)| format!("{name}: {}", self.param_type(param))),
+        );
+        let returned = self.rust_type(&member.return_type);
+        let return_type = match (&returned, is_put, member.return_type.as_str()) {
+            (_, true, _) => "()".to_string(),
+            (RustType::Object(object), ..) => object.clone(),
+            (_, _, "void") => "()".to_string(),
+            _ => "Value".to_string(),
+        };
+
+        if !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+
+        self.doc("    ", member.doc.as_deref());
+        let _ = writeln!(
+            self.out,
+            "    pub fn {function}({}) -> Result<{return_type}> {{",
+            signature.join(", ")
+        );
+
+        if is_put {
+            let value = match member.kind {
+                MemberKind::PropertyPutRef => {
+                    format!("serde_json::json!({{ \"$putref\": {} }})", names[0])
+                }
+                _ => names[0].clone(),
+            };
+            let _ = writeln!(
+                self.out,
+                "        self.0.set({:?}, {value}).map(|_| ())\n    }}",
+                member.name
+            );
+            return;
+        }
+
+        let params: Vec<(&Param, &String)> = member.params.iter().zip(&names).collect();
+        let required: Vec<String> = params
+            .iter()
+            .filter(|(param, _)| !param.optional)
+            .map(|(_, name)| format!("Value::from({name})"))
+            .collect();
+        let optional: Vec<&(&Param, &String)> =
+            params.iter().filter(|(param, _)| param.optional).collect();
+
+        if member.kind == MemberKind::Method || !params.is_empty() {
+            let _ = writeln!(
+                self.out,
+                "        let args: Vec<Value> = vec![{}];",
+                required.join(", ")
+            );
+        }
+
+        let call = if member.kind == MemberKind::PropertyGet {
+            // Optional indices can only be left out from the end
+            if !optional.is_empty() {
+                let optional: Vec<String> = optional
+                    .iter()
+                    .map(|(_, name)| format!("{name}.map(Value::from)"))
+                    .collect();
+                let _ = writeln!(
+                    self.out,
+                    "        let args: Vec<Value> = args\n            .into_iter()\n            \
+                     .chain([{}].into_iter().map_while(|value| value))\n            .collect();",
+                    optional.join(", ")
+                );
+            }
+
+            match params.is_empty() {
+                true => format!("self.0.get({:?})", member.name),
+                false => format!("self.0.get(&indexed({:?}, &args))", member.name),
+            }
+        } else if optional.is_empty() {
+            format!(
+                "self.0.call_named({:?}, args, Vec::<(&str, Value)>::new())",
+                member.name
+            )
+        } else {
+            // Optional arguments are passed by name, so that any of them can be left out
+            let _ = writeln!(
+                self.out,
+                "        let mut named: Vec<(&str, Value)> = Vec::new();"
+            );
+
+            for (param, name) in optional {
+                let _ = writeln!(
+                    self.out,
+                    "        if let Some(value) = {name} {{\n            \
+                     named.push(({:?}, value.into()));\n        }}",
+                    param.name
+                );
+            }
+
+            format!("self.0.call_named({:?}, args, named)", member.name)
+        };
+
+        let body = match (&returned, return_type.as_str()) {
+            (RustType::Object(object), _) if object == "ComObject" => {
+                format!(
+                    "let value = {call}?;\n\n        self.0.take(&value, {:?})",
+                    member.name
+                )
+            }
+            (RustType::Object(object), _) => format!(
+                "let value = {call}?;\n\n        self.0.take(&value, {:?}).map({object})",
+                member.name
+            ),
+            (_, "()") => format!("{call}.map(|_| ())"),
+            _ => call,
+        };
+        let _ = writeln!(self.out, "        {body}\n    }}");
+    }
+}
+
+/// Writes the wrapper module of a type library
+pub fn generate(library: &TypeLibrary) -> String {
+    Generator::new(library).generate(library)
+}
@@ -0,0 +1,267 @@
+//! Rust API over the engine, for programs embedding it instead of running the CLI.
+//!
+//! A [`ComObject`] can be created on its own and driven fluently:
+//!
+//! ```no_run
+//! # fn main() -> win32_com_cli::Result<()> {
+//! use win32_com_cli::ComObject;
+//!
+//! ComObject::create("Excel.Application")?
+//!     .set("Visible", true)?
+//!     .call("Workbooks.Open", ["C:\\x.xlsx"])?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A [`ComSession`] adds what the requests of the CLI can do, such as steps with
+//! variables, timeouts and retries of rejected calls. Objects keep the apartment of
+//! their thread alive, and cannot leave that thread, as objects of a single-threaded
+//! apartment cannot be called from others.
+
+use crate::activation::{ActivationOptions, Credentials, create_object};
+use crate::dispatch::{call_method, get_property, set_property};
+use crate::error::Result;
+use crate::objects::ObjectTable;
+use crate::session::{Apartment, Operation, RunOptions, Session, Step};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use windows::Win32::Foundation::{DISP_E_TYPEMISMATCH, E_INVALIDARG};
+use windows::Win32::System::Com::IDispatch;
+use windows::core::Error;
+
+/// The COM apartment of the current thread, along with the objects created in it
+pub struct ComSession {
+    session: Session,
+}
+
+/// An object addressed through IDispatch
+#[derive(Clone)]
+pub struct ComObject {
+    dispatch: IDispatch,
+    /// Where the objects returned by members are registered under their handle
+    objects: Rc<RefCell<ObjectTable>>,
+    /// Locale of the name lookups and calls, that of the session the object came from
+    lcid: u32,
+    /// The credentials the proxy was created with, which it refers to until released
+    _credentials: Option<Rc<Credentials>>,
+    // Declared last so that the objects are released before the apartment is left
+    _apartment: Rc<Apartment>,
+}
+
+impl ComSession {
+    /// Enters a single-threaded apartment on the current thread, set up for `options`
+    pub fn new(options: &RunOptions) -> Self {
+        Self {
+            session: Session::new(options),
+        }
+    }
+
+    /// Creates an object from its ProgID
+    pub fn create(&mut self, prog_id: &str) -> Result<ComObject> {
+        self.create_with(Some(prog_id), &ActivationOptions::default())
+    }
+
+    /// Creates or binds to an object the way a `create` step does, e.g. by CLSID,
+    /// moniker or on another machine
+    pub fn create_with(
+        &mut self,
+        prog_id: Option<&str>,
+        activation: &ActivationOptions,
+    ) -> Result<ComObject> {
+        let step = Step {
+            operation: Operation::Create {
+                prog_id: prog_id.map(str::to_string),
+                name: None,
+                activation: activation.clone(),
+            },
+            save_as: None,
+            timeout_ms: None,
+        };
+        let name = self.execute(&step)?;
+
+        self.object(name.as_str().unwrap_or_default())
+            .ok_or_else(|| Error::new(E_INVALIDARG, "The created object went missing").into())
+    }
+
+    /// Looks up an object by the name it was created under or the `$obj:<n>` handle it
+    /// was returned as
+    pub fn object(&self, name: &str) -> Option<ComObject> {
+        let objects = self.session.objects();
+        let dispatch = objects.borrow().get(name).cloned()?;
+        let credentials = objects.borrow().credentials(name);
+
+        Some(ComObject {
+            dispatch,
+            objects: Rc::clone(objects),
+            lcid: self.session.lcid(),
+            _credentials: credentials,
+            _apartment: Rc::clone(self.session.apartment()),
+        })
+    }
+
+    /// Executes one step of a batch request and returns its result
+    pub fn execute(&mut self, step: &Step) -> Result<Value> {
+        // The session owns the apartment the objects live in
+        unsafe { self.session.execute(step) }
+    }
+
+    /// Takes the events fired since the last time by the objects subscribed to, as
+    /// JSON objects with the `event` name, the `object` and `interface` subscribed to
+    /// and the `args` of the event by parameter name
+    pub fn take_events(&mut self) -> Vec<Value> {
+        self.session.take_events()
+    }
+}
+
+impl ComObject {
+    /// Creates an object from its ProgID, entering a single-threaded apartment on the
+    /// current thread for as long as the object lives
+    pub fn create(prog_id: &str) -> Result<Self> {
+        Self::create_with(Some(prog_id), &ActivationOptions::default())
+    }
+
+    /// Creates or binds to an object the way a `create` step does, e.g. by CLSID,
+    /// moniker or on another machine
+    pub fn create_with(prog_id: Option<&str>, activation: &ActivationOptions) -> Result<Self> {
+        let apartment = Apartment::enter();
+        let (dispatch, credentials) = unsafe { create_object(prog_id, activation)? };
+
+        Ok(Self {
+            dispatch,
+            objects: Rc::default(),
+            lcid: 0,
+            _credentials: credentials.map(Rc::new),
+            _apartment: apartment,
+        })
+    }
+
+    /// The underlying interface, for calls the JSON values cannot express
+    pub fn dispatch(&self) -> &IDispatch {
+        &self.dispatch
+    }
+
+    /// Reads a property by path, such as `ActiveSheet.Range("A1").Value`
+    pub fn get(&self, path: &str) -> Result<Value> {
+        unsafe {
+            get_property(
+                &self.dispatch,
+                path,
+                self.lcid,
+                &mut self.objects.borrow_mut(),
+                None,
+            )
+        }
+    }
+
+    /// Reads a property holding an object, such as `Workbooks`
+    pub fn get_object(&self, path: &str) -> Result<Self> {
+        let value = self.get(path)?;
+
+        self.take(&value, path)
+    }
+
+    /// Sets a property by path, returning the object for further calls
+    pub fn set(&self, path: &str, value: impl Into<Value>) -> Result<&Self> {
+        unsafe {
+            set_property(
+                &self.dispatch,
+                path,
+                &value.into(),
+                self.lcid,
+                &self.objects.borrow(),
+                false,
+                None,
+            )?
+        };
+
+        Ok(self)
+    }
+
+    /// Calls a method by path with positional arguments
+    pub fn call(
+        &self,
+        path: &str,
+        args: impl IntoIterator<Item = impl Into<Value>>,
+    ) -> Result<Value> {
+        self.call_named(path, args, HashMap::<String, Value>::new())
+    }
+
+    /// Calls a method by path with positional and named arguments
+    pub fn call_named(
+        &self,
+        path: &str,
+        args: impl IntoIterator<Item = impl Into<Value>>,
+        named_args: impl IntoIterator<Item = (impl Into<String>, impl Into<Value>)>,
+    ) -> Result<Value> {
+        let args: Vec<Value> = args.into_iter().map(Into::into).collect();
+        let named_args: Vec<(String, Value)> = named_args
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect();
+        let named_args: Vec<(&String, &Value)> = named_args
+            .iter()
+            .map(|(name, value)| (name, value))
+            .collect();
+
+        unsafe {
+            call_method(
+                &self.dispatch,
+                path,
+                &args,
+                &named_args,
+                self.lcid,
+                &mut self.objects.borrow_mut(),
+                false,
+                None,
+            )
+        }
+    }
+
+    /// Calls a method returning an object, such as `Workbooks.Add`
+    pub fn call_object(
+        &self,
+        path: &str,
+        args: impl IntoIterator<Item = impl Into<Value>>,
+    ) -> Result<Self> {
+        let value = self.call(path, args)?;
+
+        self.take(&value, path)
+    }
+
+    /// Turns the handle of an object returned by a call or property into the object
+    pub fn resolve(&self, handle: &Value) -> Option<Self> {
+        let dispatch = self.objects.borrow().get(handle.as_str()?).cloned()?;
+
+        Some(self.with_dispatch(dispatch))
+    }
+
+    /// Turns the result of the member at `path` into the object it holds, and drops its
+    /// handle for objects no other result refers to
+    pub fn take(&self, value: &Value, path: &str) -> Result<Self> {
+        let object = self.resolve(value).ok_or_else(|| {
+            Error::new(
+                DISP_E_TYPEMISMATCH,
+                format!("'{path}' did not return an object"),
+            )
+        })?;
+
+        self.objects
+            .borrow_mut()
+            .remove(value.as_str().unwrap_or_default());
+
+        Ok(object)
+    }
+
+    fn with_dispatch(&self, dispatch: IDispatch) -> Self {
+        Self {
+            dispatch,
+            objects: Rc::clone(&self.objects),
+            lcid: self.lcid,
+            // Members may return the object itself
+            _credentials: self._credentials.clone(),
+            _apartment: Rc::clone(&self._apartment),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! `completions` subcommand, which prints the completion script of a shell. The script
+//! of PowerShell also completes the ProgIDs of `browse`, `describe`, `resolve`,
+//! `codegen` and `call --prog-id` with those `list-progids` finds in the registry.
+
+use crate::cli::Cli;
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+const BIN_NAME: &str = "win32-com-cli";
+
+/// Where the generated PowerShell completer picks the completions of the command line
+const SWITCH: &str = "    $completions = @(switch ($command) {";
+
+/// Completes ProgIDs before the flags and subcommands clap knows of are looked at
+const PROG_IDS: &str = r#"    # ProgIDs are looked up in the registry as they are typed
+    $previous = @($commandElements.Where{ $_.Extent.EndOffset -lt $cursorPosition })[-1]
+    if (($command -match '^win32-com-cli;(browse|describe|resolve|codegen)$' -and
+            -not $wordToComplete.StartsWith('-')) -or "$previous" -eq '--prog-id') {
+        # Windows PowerShell drops empty arguments, which list every ProgID anyway
+        $arguments = @('list-progids', '--compact')
+        if ($wordToComplete) {
+            $arguments += $wordToComplete
+        }
+        $classes = & 'win32-com-cli' @arguments 2>$null | ConvertFrom-Json
+        return $classes | ForEach-Object {
+            [CompletionResult]::new($_.prog_id, $_.prog_id, [CompletionResultType]::ParameterValue, $_.clsid)
+        }
+    }
+
+"#;
+
+/// The completion script of a shell
+pub fn generate(shell: Shell) -> String {
+    let mut script = Vec::new();
+
+    clap_complete::generate(shell, &mut Cli::command(), BIN_NAME, &mut script);
+
+    let script = String::from_utf8(script).expect("Completion scripts are UTF-8");
+
+    match shell {
+        Shell::PowerShell => {
+            let switch = script
+                .find(SWITCH)
+                .expect("The PowerShell completer switches on the command");
+
+            format!("{}{PROG_IDS}{}", &script[..switch], &script[switch..])
+        }
+        _ => script,
+    }
+}
@@ -0,0 +1,145 @@
+//! Conversion between ISO-8601 date/time strings and OLE Automation dates.
+//!
+//! An OLE Automation date is a double counting days since 1899-12-30, with the time
+//! of day stored as the fraction. Dates before the epoch keep a positive time
+//! fraction, so -1.25 is 1899-12-29 06:00.
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Days between 1970-01-01 and the OLE Automation epoch 1899-12-30
+const OLE_EPOCH_OFFSET: i64 = 25_569;
+
+/// Days since 1970-01-01 for a proleptic Gregorian calendar date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Calendar date for a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    days_from_civil(year + month / 12, month % 12 + 1, 1) - days_from_civil(year, month, 1)
+}
+
+fn parse_number(part: &str, digits: usize) -> Option<i64> {
+    if part.len() != digits || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    part.parse().ok()
+}
+
+/// Parses `YYYY-MM-DD` optionally followed by `T` (or a space) and `HH:MM[:SS[.fff]]`.
+/// A trailing `Z` is accepted, other UTC offsets are not since OLE dates carry no zone.
+pub fn parse_iso8601(text: &str) -> Option<f64> {
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(split) => (&text[..split], Some(&text[split + 1..])),
+        None => (text, None),
+    };
+
+    let mut date_parts = date.split('-');
+    let year = parse_number(date_parts.next()?, 4)?;
+    let month = parse_number(date_parts.next()?, 2)?;
+    let day = parse_number(date_parts.next()?, 2)?;
+
+    if date_parts.next().is_some()
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+    {
+        return None;
+    }
+
+    let mut time_ms = 0;
+
+    if let Some(time) = time {
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (time, None),
+        };
+        let mut time_parts = time.split(':');
+        let hour = parse_number(time_parts.next()?, 2)?;
+        let minute = parse_number(time_parts.next()?, 2)?;
+        let second = match time_parts.next() {
+            Some(second) => parse_number(second, 2)?,
+            None => 0,
+        };
+
+        if time_parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        let millis = match fraction {
+            Some(fraction) if !fraction.is_empty() && fraction.len() <= 3 => {
+                parse_number(fraction, fraction.len())? * 10_i64.pow(3 - fraction.len() as u32)
+            }
+            Some(_) => return None,
+            None => 0,
+        };
+
+        time_ms = ((hour * 60 + minute) * 60 + second) * 1000 + millis;
+    }
+
+    let days = days_from_civil(year, month, day) + OLE_EPOCH_OFFSET;
+    let fraction = time_ms as f64 / MS_PER_DAY as f64;
+
+    Some(if days < 0 {
+        days as f64 - fraction
+    } else {
+        days as f64 + fraction
+    })
+}
+
+/// Formats an OLE Automation date as `YYYY-MM-DDTHH:MM:SS`, adding `.fff` when the
+/// time has a millisecond component
+pub fn format_iso8601(date: f64) -> Option<String> {
+    if !date.is_finite() {
+        return None;
+    }
+
+    let mut days = date.trunc() as i64;
+    let mut time_ms = ((date - date.trunc()).abs() * MS_PER_DAY as f64).round() as i64;
+
+    if time_ms >= MS_PER_DAY {
+        days += 1;
+        time_ms -= MS_PER_DAY;
+    }
+
+    let (year, month, day) = civil_from_days(days - OLE_EPOCH_OFFSET);
+    let (hour, minute, second, millis) = (
+        time_ms / 3_600_000,
+        time_ms / 60_000 % 60,
+        time_ms / 1000 % 60,
+        time_ms % 1000,
+    );
+    let mut text = format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}");
+
+    if millis != 0 {
+        text.push_str(&format!(".{millis:03}"));
+    }
+
+    Some(text)
+}
@@ -0,0 +1,30 @@
+//! `describe` subcommand, which lists what an object can be asked for: its methods and
+//! properties with their DISPIDs, parameters and types, as its type information
+//! declares them.
+
+use win32_com_cli::activation::ActivationOptions;
+use win32_com_cli::session::RunOptions;
+use win32_com_cli::typeinfo::{TypeDescription, describe_type, object_type_info};
+use win32_com_cli::{ComObject, ComSession, Result};
+
+/// Creates an object from a ProgID, or from a CLSID when written in braces
+pub fn create(session: &mut ComSession, target: &str) -> Result<ComObject> {
+    if target.starts_with('{') {
+        let activation = ActivationOptions {
+            clsid: Some(target.to_string()),
+            ..ActivationOptions::default()
+        };
+
+        session.create_with(None, &activation)
+    } else {
+        session.create(target)
+    }
+}
+
+/// Creates the object of a ProgID or CLSID and describes its dispatch interface
+pub fn describe(target: &str) -> Result<TypeDescription> {
+    let mut session = ComSession::new(&RunOptions::default());
+    let object = create(&mut session, target)?;
+
+    unsafe { describe_type(&object_type_info(object.dispatch())?) }
+}
@@ -0,0 +1,1045 @@
+use crate::error::{ComError, Exception, Result};
+use crate::objects::ObjectTable;
+use crate::typeinfo::{
+    MemberKind, describe_type, member_kinds, object_type_info, param_record, param_types,
+};
+use crate::variant::{
+    Conversion, change_type, convert, is_hinted, pointer_token, reference_to, variant_to_value,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use tracing::{debug, debug_span, field, info, trace_span};
+use windows::Win32::Foundation::{
+    DISP_E_BADPARAMCOUNT, DISP_E_EXCEPTION, DISP_E_MEMBERNOTFOUND, DISP_E_PARAMNOTFOUND,
+    DISP_E_PARAMNOTOPTIONAL, DISP_E_TYPEMISMATCH, DISP_E_UNKNOWNNAME, E_INVALIDARG, S_OK,
+};
+use windows::Win32::System::Ole::{
+    DISPID_NEWENUM, DISPID_PROPERTYPUT, DISPID_UNKNOWN, DISPID_VALUE, IDispatchEx, IEnumVARIANT,
+    fdexNameCaseInsensitive, fdexNameEnsure,
+};
+use windows::Win32::System::Variant::{VARENUM, VARIANT, VT_EMPTY, VT_NULL, VT_RECORD};
+use windows::{Win32::System::Com::*, core::*};
+
+pub fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// One segment of a member path, such as `Worksheets(1)` with its index arguments
+pub struct PathSegment {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+/// Splits a member path such as `Workbooks("Book1.xlsx").Worksheets(1).Name` into its
+/// segments. Index arguments are written as JSON values separated by commas.
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>> {
+    let invalid =
+        |reason: &str| Error::new(E_INVALIDARG, format!("Invalid path '{path}': {reason}"));
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in path.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                if depth == 0 {
+                    return Err(invalid("unbalanced ')'").into());
+                }
+                depth -= 1;
+            }
+            '.' if !in_string && depth == 0 => {
+                segments.push(&path[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if in_string || depth != 0 {
+        return Err(invalid("unterminated string or index").into());
+    }
+
+    segments.push(&path[start..]);
+
+    segments
+        .into_iter()
+        .map(|segment| {
+            let (name, args) = match segment.split_once('(') {
+                Some((name, rest)) => {
+                    let inner = rest
+                        .strip_suffix(')')
+                        .ok_or_else(|| invalid("text after an index"))?;
+                    let args: Vec<Value> = serde_json::from_str(&format!("[{inner}]"))
+                        .map_err(|error| invalid(&format!("bad index in '{segment}': {error}")))?;
+
+                    (name, args)
+                }
+                None => (segment, Vec::new()),
+            };
+
+            if name.is_empty() {
+                return Err(invalid("empty member name").into());
+            }
+
+            Ok(PathSegment {
+                name: name.to_string(),
+                args,
+            })
+        })
+        .collect()
+}
+
+/// Resolves a member name, optionally followed by its parameter names, to DISPIDs in one
+/// call. Localized servers look the names up in the language of `lcid`.
+pub unsafe fn get_dispids(
+    obj: &IDispatch,
+    names: &[&str],
+    lcid: u32,
+    objects: &ObjectTable,
+) -> Result<Vec<i32>> {
+    // The wide buffers must outlive the GetIDsOfNames call
+    let wide_names: Vec<Vec<u16>> = names.iter().map(|name| to_wide(name)).collect();
+    let name_ptrs: Vec<PCWSTR> = wide_names
+        .iter()
+        .map(|name| PCWSTR::from_raw(name.as_ptr()))
+        .collect();
+    let mut dispids = vec![0; names.len()];
+    let _span = trace_span!("get_dispids", ?names).entered();
+
+    let result = unsafe {
+        obj.GetIDsOfNames(
+            &Default::default(),
+            name_ptrs.as_ptr(),
+            name_ptrs.len() as u32,
+            lcid,
+            dispids.as_mut_ptr(),
+        )
+    };
+
+    match result {
+        Ok(()) => Ok(dispids),
+        Err(error) if error.code() == DISP_E_UNKNOWNNAME => {
+            // Script objects may only know the members added to them through IDispatchEx
+            if let [name] = names
+                && let Some(expando) = unsafe { expando(obj, objects) }
+                && let Ok(dispid) =
+                    unsafe { expando.GetDispID(&BSTR::from(*name), fdexNameCaseInsensitive as u32) }
+            {
+                return Ok(vec![dispid]);
+            }
+
+            Err(unsafe { unknown_name(obj, names, &dispids) }.into())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// The IDispatchEx of an object. Objects are only held under their class once they are
+/// known to lack it, so those of a known class are not asked, which spares out-of-process
+/// servers a round trip on every call.
+unsafe fn expando(obj: &IDispatch, objects: &ObjectTable) -> Option<IDispatchEx> {
+    if objects.class_of(obj).is_some() {
+        return None;
+    }
+
+    obj.cast::<IDispatchEx>().ok()
+}
+
+/// Adds a member to an object of IDispatchEx, such as a JScript object, and returns its
+/// DISPID. `None` for other objects, whose members are fixed.
+unsafe fn ensure_member(obj: &IDispatch, name: &str, objects: &ObjectTable) -> Option<i32> {
+    let expando = unsafe { expando(obj, objects) }?;
+    let flags = fdexNameEnsure | fdexNameCaseInsensitive;
+
+    unsafe { expando.GetDispID(&BSTR::from(name), flags as u32) }.ok()
+}
+
+/// A class, along with the locale and names looked up on its objects
+type DispidKey = (String, u32, Vec<String>);
+
+/// A class, along with the DISPID of one of its methods
+type MethodKey = (String, i32);
+
+thread_local! {
+    /// DISPIDs of the members of the objects created from a ProgID or CLSID, by class,
+    /// locale and names, shared by the sessions of the thread. The objects of a class all map
+    /// a name to the same DISPID, so batches and servers creating an object for every
+    /// request only ask the server once.
+    static DISPIDS: RefCell<HashMap<DispidKey, Vec<i32>>> = RefCell::default();
+
+    /// Types the methods of those objects declare for their parameters, by class and
+    /// DISPID, `None` for methods their type information does not declare
+    static PARAM_TYPES: RefCell<HashMap<MethodKey, Option<Vec<VARENUM>>>> = RefCell::default();
+
+    /// The kinds the members of the objects of a class are declared as, by DISPID
+    static MEMBER_KINDS: RefCell<HashMap<MethodKey, Vec<MemberKind>>> = RefCell::default();
+
+    /// The code the last member invoked on the thread succeeded with, S_OK or one with
+    /// information such as S_FALSE
+    static SUCCESS: Cell<HRESULT> = const { Cell::new(S_OK) };
+}
+
+/// The code the last member invoked since the previous call succeeded with, S_OK when
+/// none was
+pub(crate) fn take_success() -> HRESULT {
+    SUCCESS.replace(S_OK)
+}
+
+/// Resolves names like [`get_dispids`], from the cache for objects of a known class
+unsafe fn cached_dispids(
+    obj: &IDispatch,
+    names: &[&str],
+    lcid: u32,
+    objects: &ObjectTable,
+) -> Result<Vec<i32>> {
+    let Some(class) = objects.class_of(obj) else {
+        return unsafe { get_dispids(obj, names, lcid, objects) };
+    };
+    // Names are looked up regardless of case
+    let key: DispidKey = (
+        class.to_string(),
+        lcid,
+        names.iter().map(|name| name.to_lowercase()).collect(),
+    );
+
+    if let Some(dispids) = DISPIDS.with_borrow(|cache| cache.get(&key).cloned()) {
+        return Ok(dispids);
+    }
+
+    let dispids = unsafe { get_dispids(obj, names, lcid, objects)? };
+
+    DISPIDS.with_borrow_mut(|cache| cache.insert(key, dispids.clone()));
+    Ok(dispids)
+}
+
+/// The error of a name GetIDsOfNames does not know, suggesting the members, or the
+/// parameters of the member, that the type information declares closest to it
+unsafe fn unknown_name(obj: &IDispatch, names: &[&str], dispids: &[i32]) -> Error {
+    // Servers set DISPID_UNKNOWN for the names they do not know, though some leave the
+    // array as it was
+    let index = dispids
+        .iter()
+        .position(|&dispid| dispid == DISPID_UNKNOWN)
+        .unwrap_or_default();
+    let name = names[index];
+    let members = unsafe { object_type_info(obj).and_then(|info| describe_type(&info)) }
+        .map(|description| description.members)
+        .unwrap_or_default();
+    let (message, candidates): (String, Vec<&str>) = match index {
+        0 => (
+            format!("Unknown member '{name}'"),
+            members.iter().map(|member| member.name.as_str()).collect(),
+        ),
+        _ => (
+            format!("Unknown parameter '{name}' of {}", names[0]),
+            members
+                .iter()
+                .filter(|member| member.name.eq_ignore_ascii_case(names[0]))
+                .flat_map(|member| member.params.iter().map(|param| param.name.as_str()))
+                .collect(),
+        ),
+    };
+
+    Error::new(
+        DISP_E_UNKNOWNNAME,
+        format!("{message}{}", did_you_mean(name, &candidates)),
+    )
+}
+
+/// Edit distance between two names regardless of case
+fn distance(left: &str, right: &str) -> usize {
+    let left: Vec<char> = left.to_lowercase().chars().collect();
+    let right: Vec<char> = right.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+
+    for (i, left) in left.iter().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, right) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left != right);
+
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[right.len()]
+}
+
+/// A hint naming the candidates closest to `name`, like ` (did you mean Visible?)`, or
+/// nothing when none comes close
+pub(crate) fn did_you_mean(name: &str, candidates: &[&str]) -> String {
+    // About one typo in every three letters
+    let limit = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = candidates
+        .iter()
+        .map(|candidate| (distance(name, candidate), *candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .collect();
+
+    close.sort_unstable();
+    close.dedup_by(|(_, left), (_, right)| left == right);
+
+    let names: Vec<&str> = close.iter().take(3).map(|&(_, name)| name).collect();
+
+    match names.split_last() {
+        None => String::new(),
+        Some((name, [])) => format!(" (did you mean {name}?)"),
+        Some((last, names)) => format!(" (did you mean {} or {last}?)", names.join(", ")),
+    }
+}
+
+/// Converts argument values, along with the JSON pointers they are found at within the
+/// step, into rgvarg order: named arguments first, in the same order as their DISPIDs,
+/// followed by the positional arguments in reverse order as COM expects
+unsafe fn to_rgvarg(
+    named: &[(String, &Value)],
+    positional: &[(String, &Value)],
+    conversion: &Conversion,
+) -> Result<Vec<VARIANT>> {
+    named
+        .iter()
+        .chain(positional.iter().rev())
+        .map(|(pointer, arg)| unsafe { convert(arg, pointer, conversion) })
+        .collect()
+}
+
+/// The JSON pointers of arguments within the step, in rgvarg order like [`to_rgvarg`]
+fn rgvarg_pointers<'a>(
+    named: &'a [(String, &Value)],
+    positional: &'a [(String, &Value)],
+) -> Vec<&'a str> {
+    named
+        .iter()
+        .chain(positional.iter().rev())
+        .map(|(pointer, _)| pointer.as_str())
+        .collect()
+}
+
+/// The index arguments of a path, which are found at `pointer` within the step
+fn path_args<'a>(args: &'a [Value], pointer: &str) -> Vec<(String, &'a Value)> {
+    args.iter().map(|arg| (pointer.to_string(), arg)).collect()
+}
+
+/// Invokes a member with arguments already in rgvarg order and returns its result.
+/// Exceptions raised by the server are reported with the details of their EXCEPINFO,
+/// and arguments the server rejected with the JSON pointer among `pointers`, in
+/// rgvarg order too, of the value they were passed from. `member` names the member
+/// for diagnostics only.
+#[allow(clippy::too_many_arguments)]
+unsafe fn invoke(
+    obj: &IDispatch,
+    member: &str,
+    dispatch_id: i32,
+    lcid: u32,
+    flags: DISPATCH_FLAGS,
+    variant_args: &mut [VARIANT],
+    named_dispids: &mut [i32],
+    pointers: &[&str],
+    objects: &ObjectTable,
+) -> Result<VARIANT> {
+    let params = DISPPARAMS {
+        rgvarg: variant_args.as_mut_ptr(), // Named arguments, then positional ones last first
+        rgdispidNamedArgs: named_dispids.as_mut_ptr(), // DISPIDs of the named arguments
+        cArgs: variant_args.len() as u32,  // Total number of arguments
+        cNamedArgs: named_dispids.len() as u32, // Number of named arguments
+    };
+    let mut result = VARIANT::default(); // Return value of the member, if any
+    let mut exception = EXCEPINFO::default(); // Details of an exception raised by the member
+    let mut arg_error = u32::MAX; // Index in rgvarg of the argument the member rejected
+    let is_put = flags == DISPATCH_PROPERTYPUT || flags == DISPATCH_PROPERTYPUTREF;
+    let span = debug_span!(
+        "invoke",
+        member,
+        dispid = dispatch_id,
+        flags = flags.0,
+        hresult = field::Empty
+    )
+    .entered();
+
+    // Property puts return nothing
+    let result_ptr = match is_put {
+        true => std::ptr::null_mut(),
+        false => &mut result as *mut VARIANT,
+    };
+    // Objects of IDispatchEx are called through InvokeEx, which script engines expect.
+    // Both are called through their vtable for the HRESULT, which tells S_OK apart
+    // from the success codes with information.
+    let hresult = match unsafe { expando(obj, objects) } {
+        Some(expando) => unsafe {
+            (expando.vtable().InvokeEx)(
+                expando.as_raw(),
+                dispatch_id,
+                lcid,
+                flags.0,
+                &params,
+                result_ptr,
+                &mut exception,
+                std::ptr::null_mut(), // No caller to query for services
+            )
+        },
+        None => unsafe {
+            (obj.vtable().Invoke)(
+                obj.as_raw(),
+                dispatch_id,     // DISPID of the member
+                &GUID::zeroed(), // Reserved, must be IID_NULL for Invoke
+                lcid,            // Locale the arguments are interpreted in
+                flags,           // Kind of invocation
+                &params,         // Parameters for the invocation
+                result_ptr,
+                &mut exception, // Filled when the call fails with DISP_E_EXCEPTION
+                &mut arg_error, // Filled when the call fails because of an argument
+            )
+        },
+    };
+
+    if hresult.is_ok() {
+        SUCCESS.set(hresult);
+    }
+
+    unsafe {
+        hresult.ok().map_err(|error| {
+            let mut error = match error.code() {
+                DISP_E_EXCEPTION => Exception::take(&mut exception).into_error(),
+                _ => ComError::from(error).with_error_info(obj),
+            }
+            .invoked();
+
+            // InvokeEx does not tell the argument, which is then left unknown
+            if matches!(
+                error.code(),
+                DISP_E_TYPEMISMATCH | DISP_E_PARAMNOTFOUND | DISP_E_PARAMNOTOPTIONAL
+            ) && let Some(pointer) = pointers.get(arg_error as usize)
+                && !pointer.is_empty()
+            {
+                error = error.at_argument(pointer);
+            }
+
+            span.record("hresult", error.hresult());
+            error
+        })?;
+    }
+
+    Ok(result)
+}
+
+/// How a step invokes its member, when it says so rather than leaving it to its op, for
+/// members that are a method and a property alike
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum InvokeKind {
+    Method,
+    Propget,
+    Propput,
+    Propputref,
+}
+
+impl InvokeKind {
+    fn flags(self) -> DISPATCH_FLAGS {
+        match self {
+            Self::Method => DISPATCH_METHOD,
+            Self::Propget => DISPATCH_PROPERTYGET,
+            Self::Propput => DISPATCH_PROPERTYPUT,
+            Self::Propputref => DISPATCH_PROPERTYPUTREF,
+        }
+    }
+
+    /// The kind as type information declares members
+    fn member_kind(self) -> MemberKind {
+        match self {
+            Self::Method => MemberKind::Method,
+            Self::Propget => MemberKind::PropertyGet,
+            Self::Propput => MemberKind::PropertyPut,
+            Self::Propputref => MemberKind::PropertyPutRef,
+        }
+    }
+
+    /// The kind as steps write it
+    fn name(self) -> &'static str {
+        match self {
+            Self::Method => "method",
+            Self::Propget => "propget",
+            Self::Propput => "propput",
+            Self::Propputref => "propputref",
+        }
+    }
+
+    /// Checks that a step of `op` can invoke its member as `kind`: set steps put a
+    /// property, while call and get steps call a method or read a property
+    pub(crate) fn check(kind: Option<Self>, op: &str) -> Result<Option<Self>> {
+        let puts = matches!(kind, Some(Self::Propput | Self::Propputref));
+
+        match kind {
+            Some(kind) if puts != (op == "set") => Err(Error::new(
+                E_INVALIDARG,
+                format!("A {op} step cannot invoke its member as {}", kind.name()),
+            )
+            .into()),
+            kind => Ok(kind),
+        }
+    }
+}
+
+/// Invokes a member as `first` through `invoke`, and again as `fallback` when the object
+/// has no member of that kind by its name, the way scripting hosts do for names that
+/// are methods in some object models and properties in others. The fallback comes
+/// first when the type information only declares the member as that, among the
+/// `declared` kinds. Failing both, the first error is reported. A `kind` the step asked
+/// for is used alone.
+fn with_fallback<T>(
+    mut invoke: impl FnMut(DISPATCH_FLAGS) -> Result<T>,
+    kind: Option<InvokeKind>,
+    declared: &[MemberKind],
+    first: InvokeKind,
+    fallback: InvokeKind,
+) -> Result<T> {
+    if let Some(kind) = kind {
+        return invoke(kind.flags());
+    }
+
+    let declares = |kind: InvokeKind| declared.contains(&kind.member_kind());
+    let (flags, fallback) = match declares(fallback) && !declares(first) {
+        true => (fallback.flags(), first.flags()),
+        false => (first.flags(), fallback.flags()),
+    };
+
+    match invoke(flags) {
+        Err(error) if error.code() == DISP_E_MEMBERNOTFOUND => {
+            debug!(
+                "Retrying as flags {} after DISP_E_MEMBERNOTFOUND",
+                fallback.0
+            );
+
+            invoke(fallback).map_err(|retry| match retry.code() {
+                DISP_E_MEMBERNOTFOUND => error,
+                _ => retry,
+            })
+        }
+        result => result,
+    }
+}
+
+/// Reads a property, passing the segment's index arguments along, or calls the method of
+/// that name when there is no such property. When the property itself takes no index,
+/// the index is applied to its default member instead, the way `Worksheets(1)` reads
+/// `Worksheets.Item(1)` in VBA. A `kind` of the step reads or calls the member as told
+/// instead.
+unsafe fn invoke_property_get(
+    obj: &IDispatch,
+    segment: &PathSegment,
+    lcid: u32,
+    objects: &ObjectTable,
+    kind: Option<InvokeKind>,
+) -> Result<VARIANT> {
+    unsafe {
+        let dispatch_id = cached_dispids(obj, &[&segment.name], lcid, objects)?[0];
+        // The objects along a path were reached already, so its index arguments are
+        // converted the way the values of property gets always are
+        let conversion = Conversion {
+            lcid,
+            objects,
+            strict: false,
+            records: &HashMap::new(),
+        };
+        let positional = path_args(&segment.args, "");
+        let mut variant_args = to_rgvarg(&[], &positional, &conversion)?;
+        let pointers = rgvarg_pointers(&[], &positional);
+
+        let read = |flags| {
+            invoke(
+                obj,
+                &segment.name,
+                dispatch_id,
+                lcid,
+                flags,
+                &mut variant_args,
+                &mut [],
+                &pointers,
+                objects,
+            )
+        };
+
+        let declared = declared_kinds(obj, dispatch_id, objects);
+
+        match with_fallback(
+            read,
+            kind,
+            &declared,
+            InvokeKind::Propget,
+            InvokeKind::Method,
+        ) {
+            Err(error)
+                if !segment.args.is_empty()
+                    && (error.code() == DISP_E_BADPARAMCOUNT
+                        || error.code() == DISP_E_MEMBERNOTFOUND) =>
+            {
+                let collection = invoke(
+                    obj,
+                    &segment.name,
+                    dispatch_id,
+                    lcid,
+                    DISPATCH_PROPERTYGET,
+                    &mut [],
+                    &mut [],
+                    &[],
+                    objects,
+                )?;
+                let collection = IDispatch::try_from(&collection).map_err(|_| error)?;
+
+                invoke(
+                    &collection,
+                    &segment.name,
+                    DISPID_VALUE as i32,
+                    lcid,
+                    DISPATCH_PROPERTYGET,
+                    &mut variant_args,
+                    &mut [],
+                    &pointers,
+                    objects,
+                )
+            }
+            result => result,
+        }
+    }
+}
+
+/// Walks a member path such as `ActiveDocument.PageSetup.Orientation` by reading every
+/// segment but the last as an object-valued property. Returns the object owning the
+/// final member along with that member's segment.
+unsafe fn resolve_path(
+    obj: &IDispatch,
+    path: &str,
+    lcid: u32,
+    objects: &ObjectTable,
+) -> Result<(IDispatch, PathSegment)> {
+    let mut segments = parse_path(path)?;
+    let member = segments.pop().expect("paths have at least one segment");
+    let mut current = obj.clone();
+
+    for segment in &segments {
+        let value = unsafe { invoke_property_get(&current, segment, lcid, objects, None)? };
+
+        current = IDispatch::try_from(&value).map_err(|_| {
+            Error::new(
+                DISP_E_TYPEMISMATCH,
+                format!("'{}' in '{path}' is not an object", segment.name),
+            )
+        })?;
+    }
+
+    Ok((current, member))
+}
+
+/// Picks the kind of property assignment for a value. `{"$putref": value}` assigns an
+/// object reference through DISPATCH_PROPERTYPUTREF, like VBA's `Set`, and so do
+/// properties whose type information only declares a put by reference, among the
+/// `declared` kinds, unless the step tells the `kind` itself. The value assigned comes
+/// with its JSON pointer within the step.
+fn put_kind<'a>(
+    value: &'a Value,
+    kind: Option<InvokeKind>,
+    declared: &[MemberKind],
+) -> (DISPATCH_FLAGS, &'a Value, &'static str) {
+    let by_reference = declared.contains(&MemberKind::PropertyPutRef)
+        && !declared.contains(&MemberKind::PropertyPut);
+    let (flags, value, pointer) = match value {
+        Value::Object(object) if object.len() == 1 => match object.get("$putref") {
+            Some(inner) => (DISPATCH_PROPERTYPUTREF, inner, "/value/$putref"),
+            None => (DISPATCH_PROPERTYPUT, value, "/value"),
+        },
+        _ if by_reference => (DISPATCH_PROPERTYPUTREF, value, "/value"),
+        _ => (DISPATCH_PROPERTYPUT, value, "/value"),
+    };
+
+    (kind.map_or(flags, InvokeKind::flags), value, pointer)
+}
+
+/// Sets a property by path, as `kind` when given, which must be a put. Values without a
+/// faithful VARIANT form fail the step when `strict`, rather than being passed as
+/// VT_EMPTY.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn set_property(
+    obj: &IDispatch,
+    path: &str,
+    value: &Value,
+    lcid: u32,
+    objects: &ObjectTable,
+    strict: bool,
+    kind: Option<InvokeKind>,
+) -> Result<()> {
+    unsafe {
+        let kind = InvokeKind::check(kind, "set")?;
+        let (obj, member) = resolve_path(obj, path, lcid, objects)?;
+
+        // Get the DISPID for the property name. Objects of IDispatchEx gain the
+        // properties they lack, the way script objects do when assigned to.
+        let dispatch_id = match cached_dispids(&obj, &[&member.name], lcid, objects) {
+            Ok(dispids) => dispids[0],
+            Err(error) => ensure_member(&obj, &member.name, objects).ok_or(error)?,
+        };
+        let declared = declared_kinds(&obj, dispatch_id, objects);
+        let (flags, value, pointer) = put_kind(value, kind, &declared);
+
+        // The value is passed as the single named argument DISPID_PROPERTYPUT (used for
+        // reference puts as well), followed by the index arguments of an indexed property
+        let conversion = Conversion {
+            lcid,
+            objects,
+            strict,
+            records: &HashMap::new(),
+        };
+        let named = [(pointer.to_string(), value)];
+        let positional = path_args(&member.args, "/property");
+        let mut variant_args = to_rgvarg(&named, &positional, &conversion)?;
+
+        invoke(
+            &obj,
+            &member.name,
+            dispatch_id,
+            lcid,
+            flags,
+            &mut variant_args,
+            &mut [DISPID_PROPERTYPUT],
+            &rgvarg_pointers(&named, &positional),
+            objects,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reads a property by path, or calls the method `kind` asks for instead
+pub unsafe fn get_property(
+    obj: &IDispatch,
+    path: &str,
+    lcid: u32,
+    objects: &mut ObjectTable,
+    kind: Option<InvokeKind>,
+) -> Result<Value> {
+    unsafe {
+        let kind = InvokeKind::check(kind, "get")?;
+        let (obj, member) = resolve_path(obj, path, lcid, objects)?;
+
+        Ok(variant_to_value(
+            &invoke_property_get(&obj, &member, lcid, objects, kind)?,
+            lcid,
+            objects,
+        ))
+    }
+}
+
+/// Number of elements fetched from an enumerator at a time
+const ENUM_BATCH: usize = 64;
+
+/// Reads the elements of a collection through the IEnumVARIANT its `_NewEnum` member
+/// returns, like `For Each` in VBA. The collection is the object at `path`, read as
+/// `kind` when given, or `obj` itself without one.
+pub unsafe fn enumerate(
+    obj: &IDispatch,
+    path: Option<&str>,
+    lcid: u32,
+    objects: &mut ObjectTable,
+    kind: Option<InvokeKind>,
+) -> Result<Vec<Value>> {
+    unsafe {
+        let kind = InvokeKind::check(kind, "get")?;
+        let collection = match path {
+            Some(path) => {
+                let (owner, member) = resolve_path(obj, path, lcid, objects)?;
+                let value = invoke_property_get(&owner, &member, lcid, objects, kind)?;
+
+                IDispatch::try_from(&value).map_err(|_| {
+                    Error::new(DISP_E_TYPEMISMATCH, format!("'{path}' is not an object"))
+                })?
+            }
+            None => obj.clone(),
+        };
+        let name = path.unwrap_or("The object");
+        let enumerator = invoke(
+            &collection,
+            "_NewEnum",
+            DISPID_NEWENUM,
+            lcid,
+            DISPATCH_METHOD | DISPATCH_PROPERTYGET,
+            &mut [],
+            &mut [],
+            &[],
+            objects,
+        )
+        .map_err(|error| match error.code() {
+            DISP_E_MEMBERNOTFOUND | DISP_E_UNKNOWNNAME => Error::new(
+                DISP_E_MEMBERNOTFOUND,
+                format!("{name} is not a collection, it has no _NewEnum member"),
+            )
+            .into(),
+            _ => error,
+        })?;
+        // Enumerators are returned as VT_UNKNOWN, or as VT_DISPATCH by some servers
+        let enumerator: IEnumVARIANT = IUnknown::try_from(&enumerator)
+            .or_else(|_| IDispatch::try_from(&enumerator).and_then(|object| object.cast()))
+            .and_then(|unknown| unknown.cast())
+            .map_err(|_| {
+                Error::new(
+                    DISP_E_TYPEMISMATCH,
+                    format!("The _NewEnum member of {name} did not return an IEnumVARIANT"),
+                )
+            })?;
+        let mut items = Vec::new();
+
+        loop {
+            let mut elements = vec![VARIANT::default(); ENUM_BATCH];
+            let mut fetched = 0;
+
+            enumerator.Next(&mut elements, &mut fetched).ok()?;
+
+            for element in &elements[..fetched as usize] {
+                items.push(variant_to_value(element, lcid, objects));
+            }
+
+            // S_FALSE along with fewer elements than asked for ends the enumeration
+            if (fetched as usize) < ENUM_BATCH {
+                return Ok(items);
+            }
+        }
+    }
+}
+
+/// Calls a method by path. Index arguments written in the path come before `args`.
+/// When arguments are passed by reference, the result is `{"result": ..., "byref": ...}`
+/// with the values the method left in them, keyed by their position in `args` or their
+/// name. Values without a faithful VARIANT form fail the step when `strict`, rather
+/// than being passed as VT_EMPTY. The member is read as a property instead when `kind`
+/// says so.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn call_method(
+    obj: &IDispatch,
+    path: &str,
+    args: &[Value],
+    named_args: &[(&String, &Value)],
+    lcid: u32,
+    objects: &mut ObjectTable,
+    strict: bool,
+    kind: Option<InvokeKind>,
+) -> Result<Value> {
+    unsafe {
+        let kind = InvokeKind::check(kind, "call")?;
+        let (obj, mut member) = resolve_path(obj, path, lcid, objects)?;
+
+        // Get the DISPID for the method name, followed by the DISPIDs of its named parameters
+        let mut names = vec![member.name.as_str()];
+        names.extend(named_args.iter().map(|(arg_name, _)| arg_name.as_str()));
+        let dispids = cached_dispids(&obj, &names, lcid, objects)?;
+
+        let named_values: Vec<&Value> = named_args.iter().map(|(_, value)| *value).collect();
+        // Index arguments of the path come before those of the call
+        let first = member.args.len();
+        member.args.extend_from_slice(args);
+        let named_pointers: Vec<(String, &Value)> = named_args
+            .iter()
+            .map(|(name, value)| (format!("/named_args/{}", pointer_token(name)), *value))
+            .collect();
+        let mut positional = path_args(&member.args[..first], "/method");
+        positional.extend(
+            (0..)
+                .zip(args)
+                .map(|(index, arg)| (format!("/args/{index}"), arg)),
+        );
+        // The parameters of the arguments in rgvarg order, within the declared types,
+        // those of named arguments being found through their DISPID
+        let declared = declared_types(&obj, dispids[0], objects);
+        let count = member.args.len();
+        let param = |index: usize| match index.checked_sub(named_args.len()) {
+            Some(position) => Some(count - 1 - position),
+            None => usize::try_from(dispids[1 + index]).ok(),
+        };
+        let declared_type =
+            |index: usize| param(index).and_then(|param| declared.as_ref()?.get(param).copied());
+        // Records take the record type of the parameter they are passed to
+        let mut records = HashMap::new();
+
+        for (index, (pointer, value)) in named_pointers
+            .iter()
+            .chain(positional.iter().rev())
+            .enumerate()
+        {
+            if is_record(value)
+                && declared_type(index) == Some(VT_RECORD)
+                && let Some(param) = param(index)
+                && let Ok(Some(record)) =
+                    object_type_info(&obj).and_then(|info| param_record(&info, dispids[0], param))
+            {
+                records.insert(pointer.clone(), record);
+            }
+        }
+
+        let conversion = Conversion {
+            lcid,
+            objects,
+            strict,
+            records: &records,
+        };
+        let mut variant_args = to_rgvarg(&named_pointers, &positional, &conversion)?;
+
+        // The arguments passed by reference, by name or position, and the VARIANTs the
+        // server writes them to. Every VARIANT is in place before any is referred to.
+        let keys = named_args.iter().map(|(name, _)| name.to_string()).chain(
+            (first..member.args.len())
+                .rev()
+                .map(|index| (index - first).to_string()),
+        );
+        let values: Vec<&Value> = named_values
+            .iter()
+            .copied()
+            .chain(member.args.iter().rev())
+            .collect();
+        let mut references: Vec<(String, usize, VARIANT)> = keys
+            .zip(values.iter().copied())
+            .enumerate()
+            .filter(|(_, (_, value))| is_byref(value))
+            .map(|(index, (key, _))| (key, index, std::mem::take(&mut variant_args[index])))
+            .collect();
+
+        // Arguments take the types the method declares for its parameters, unless
+        // hinted otherwise
+        if declared.is_some() {
+            for (index, arg) in variant_args.iter_mut().enumerate() {
+                // Empty values and null are left for the server
+                if let Some(vt) = declared_type(index)
+                    && !matches!(arg.vt(), VT_EMPTY | VT_NULL)
+                    && !is_hinted(values[index])
+                {
+                    change_type(arg, vt, lcid);
+                }
+            }
+
+            // Out parameters get a value of their type to write to, null included
+            for (_, index, target) in &mut references {
+                if let Some(vt) = declared_type(*index)
+                    && !is_hinted(values[*index])
+                {
+                    if target.vt() == VT_NULL {
+                        *target = VARIANT::default();
+                    }
+
+                    change_type(target, vt, lcid);
+                }
+            }
+        }
+
+        let pointers = rgvarg_pointers(&named_pointers, &positional);
+
+        for (_, index, target) in &mut references {
+            variant_args[*index] =
+                reference_to(target).map_err(|error| error.at_argument(pointers[*index]))?;
+        }
+
+        info!("Calling method: {path}");
+
+        let mut named_dispids = dispids[1..].to_vec();
+        let call = |flags| {
+            invoke(
+                &obj,
+                &member.name,
+                dispids[0],
+                lcid,
+                flags,
+                &mut variant_args,
+                &mut named_dispids,
+                &pointers,
+                objects,
+            )
+        };
+        // Names declared as properties are read with the arguments instead
+        let declared = declared_kinds(&obj, dispids[0], objects);
+        let result = with_fallback(
+            call,
+            kind,
+            &declared,
+            InvokeKind::Method,
+            InvokeKind::Propget,
+        )?;
+        let result = variant_to_value(&result, lcid, objects);
+
+        if references.is_empty() {
+            return Ok(result);
+        }
+
+        let byref: Map<String, Value> = references
+            .iter()
+            .map(|(key, _, target)| (key.clone(), variant_to_value(target, lcid, objects)))
+            .collect();
+
+        Ok(json!({ "result": result, "byref": byref }))
+    }
+}
+
+/// The kinds the type information of an object declares a member as, from the cache for
+/// objects of a known class. Empty for objects without type information and members it
+/// does not declare, which are then invoked the way their step tells.
+unsafe fn declared_kinds(obj: &IDispatch, dispid: i32, objects: &ObjectTable) -> Vec<MemberKind> {
+    let lookup = || unsafe {
+        object_type_info(obj)
+            .and_then(|info| member_kinds(&info, dispid))
+            .unwrap_or_default()
+    };
+    let Some(class) = objects.class_of(obj) else {
+        return lookup();
+    };
+    let key: MethodKey = (class.to_string(), dispid);
+
+    if let Some(kinds) = MEMBER_KINDS.with_borrow(|cache| cache.get(&key).cloned()) {
+        return kinds;
+    }
+
+    let kinds = lookup();
+
+    MEMBER_KINDS.with_borrow_mut(|cache| cache.insert(key, kinds.clone()));
+    kinds
+}
+
+/// The types a method declares for its parameters, from the cache for objects of a known
+/// class. `None` for objects without type information and methods it does not declare.
+unsafe fn declared_types(
+    obj: &IDispatch,
+    dispid: i32,
+    objects: &ObjectTable,
+) -> Option<Vec<VARENUM>> {
+    let lookup = || unsafe {
+        object_type_info(obj)
+            .and_then(|info| param_types(&info, dispid))
+            .ok()
+            .flatten()
+    };
+    let Some(class) = objects.class_of(obj) else {
+        return lookup();
+    };
+    let key: MethodKey = (class.to_string(), dispid);
+
+    if let Some(types) = PARAM_TYPES.with_borrow(|cache| cache.get(&key).cloned()) {
+        return types;
+    }
+
+    let types = lookup();
+
+    PARAM_TYPES.with_borrow_mut(|cache| cache.insert(key, types.clone()));
+    types
+}
+
+/// Whether an argument is a record, written `{"$record": {...}}`
+fn is_record(value: &Value) -> bool {
+    matches!(value, Value::Object(object) if object.len() == 1 && object.contains_key("$record"))
+}
+
+/// Whether an argument is passed by reference, written `{"$byref": value}`
+fn is_byref(value: &Value) -> bool {
+    matches!(value, Value::Object(object) if object.len() == 1 && object.contains_key("$byref"))
+}
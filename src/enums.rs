@@ -0,0 +1,221 @@
+//! `{"$enum": "XlFileFormat.xlOpenXMLWorkbook"}` values, which stand for a constant of
+//! an enumeration of a type library. Constants are looked up in the library of the
+//! object a step uses, then in the `type_libraries` of the request, and replaced by
+//! their value before the step runs.
+
+use crate::dispatch::did_you_mean;
+use crate::error::Result;
+use crate::typeinfo::{
+    Constant, containing_library, describe_type, load_library, object_type_info,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows::Win32::System::Com::{IDispatch, ITypeLib, TKIND_ENUM};
+use windows::core::Error;
+
+/// The constants of a type library, named `Enum.member`, under that name and under
+/// `member` alone, in lowercase
+type Constants = HashMap<String, Constant>;
+
+/// The constants of the libraries looked up so far
+pub(crate) struct Enums {
+    /// Libraries of objects, by the GUID of the library
+    libraries: HashMap<String, Constants>,
+    /// Paths of the libraries named by the request, read once a constant is not found
+    /// in the library of the object
+    paths: Vec<String>,
+    explicit: Option<Vec<Constants>>,
+}
+
+/// Whether a value holds `{"$enum": ...}` anywhere, which saves reading libraries for
+/// the steps that use none
+pub(crate) fn uses_enums(value: &Value) -> bool {
+    match value {
+        Value::Object(object) if object.len() == 1 && object.contains_key("$enum") => true,
+        Value::Object(object) => object.values().any(uses_enums),
+        Value::Array(items) => items.iter().any(uses_enums),
+        _ => false,
+    }
+}
+
+/// Reads the constants of every enumeration of a library
+unsafe fn read_constants(library: &ITypeLib) -> Result<Constants> {
+    let mut constants = Constants::new();
+
+    unsafe {
+        for index in 0..library.GetTypeInfoCount() {
+            if library.GetTypeInfoType(index)? != TKIND_ENUM {
+                continue;
+            }
+
+            let description = describe_type(&library.GetTypeInfo(index)?)?;
+
+            for Constant { name, value } in description.constants {
+                let qualified = format!("{}.{name}", description.name);
+
+                // The first enumeration declaring a member name wins
+                constants
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| Constant {
+                        name: qualified.clone(),
+                        value: value.clone(),
+                    });
+                constants.insert(
+                    qualified.to_lowercase(),
+                    Constant {
+                        name: qualified,
+                        value,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(constants)
+}
+
+impl Enums {
+    pub(crate) fn new(paths: &[String]) -> Self {
+        Self {
+            libraries: HashMap::new(),
+            paths: paths.to_vec(),
+            explicit: None,
+        }
+    }
+
+    /// The constants of the library declaring the type of an object, `None` for objects
+    /// without type information
+    unsafe fn of_object(&mut self, object: &IDispatch) -> Result<Option<&Constants>> {
+        unsafe {
+            let Ok(library) = object_type_info(object).and_then(|info| containing_library(&info))
+            else {
+                return Ok(None);
+            };
+            let attributes = library.GetLibAttr()?;
+            let guid = format!("{:?}", (*attributes).guid);
+            library.ReleaseTLibAttr(attributes);
+
+            if !self.libraries.contains_key(&guid) {
+                self.libraries
+                    .insert(guid.clone(), read_constants(&library)?);
+            }
+
+            Ok(self.libraries.get(&guid))
+        }
+    }
+
+    /// Reads the constants of the libraries of the request, the first time
+    unsafe fn read_explicit(&mut self) -> Result<()> {
+        if self.explicit.is_none() {
+            let mut libraries = Vec::with_capacity(self.paths.len());
+
+            for path in &self.paths {
+                let library = unsafe { load_library(path) }.map_err(|error| {
+                    Error::new(
+                        error.code(),
+                        format!("Failed to load the type library {path}: {error}"),
+                    )
+                })?;
+
+                libraries.push(unsafe { read_constants(&library)? });
+            }
+
+            self.explicit = Some(libraries);
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the value of a constant written `Enum.member` or `member`, regardless of
+    /// case
+    unsafe fn lookup(&mut self, name: &str, object: Option<&IDispatch>) -> Result<Value> {
+        let key = name.to_lowercase();
+
+        if let Some(object) = object
+            && let Some(constant) = unsafe { self.of_object(object)? }.and_then(|c| c.get(&key))
+        {
+            return Ok(constant.value.clone());
+        }
+
+        unsafe { self.read_explicit()? };
+
+        let libraries = self.explicit.as_deref().unwrap_or_default();
+
+        if let Some(constant) = libraries.iter().find_map(|constants| constants.get(&key)) {
+            return Ok(constant.value.clone());
+        }
+
+        // Suggests the members of the enumeration named, or the members of any
+        // enumeration otherwise
+        let constants = self
+            .libraries
+            .values()
+            .chain(libraries)
+            .flat_map(|c| c.values());
+        let candidates: Vec<&str> = match key.split_once('.') {
+            Some((enumeration, _)) => constants
+                .map(|constant| constant.name.as_str())
+                .filter(|candidate| {
+                    candidate
+                        .split_once('.')
+                        .is_some_and(|(e, _)| e.eq_ignore_ascii_case(enumeration))
+                })
+                .collect(),
+            None => constants
+                .filter_map(|constant| Some(constant.name.split_once('.')?.1))
+                .collect(),
+        };
+
+        Err(Error::new(
+            E_INVALIDARG,
+            format!(
+                "Unknown enumeration constant '{name}'{}",
+                did_you_mean(name, &candidates)
+            ),
+        )
+        .into())
+    }
+
+    /// Replaces the `{"$enum": ...}` within a value with the constants they name, as
+    /// declared by the library of `object` or those of the request
+    ///
+    /// # Safety
+    ///
+    /// `object` must belong to the apartment of the current thread.
+    pub(crate) unsafe fn resolve(
+        &mut self,
+        value: &mut Value,
+        object: Option<&IDispatch>,
+    ) -> Result<()> {
+        match value {
+            Value::Object(fields) if fields.len() == 1 && fields.contains_key("$enum") => {
+                let name = match &fields["$enum"] {
+                    Value::String(name) => name.clone(),
+                    other => {
+                        return Err(Error::new(
+                            E_INVALIDARG,
+                            format!("Expected the name of a constant in $enum, got {other}"),
+                        )
+                        .into());
+                    }
+                };
+
+                *value = unsafe { self.lookup(&name, object)? };
+            }
+            Value::Object(fields) => {
+                for item in fields.values_mut() {
+                    unsafe { self.resolve(item, object)? };
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    unsafe { self.resolve(item, object)? };
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
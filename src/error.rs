@@ -0,0 +1,365 @@
+//! Errors of COM calls, carrying the exception details and error information an
+//! automation server reported along with the HRESULT.
+
+use crate::hresult::{self, KnownHresult};
+use crate::interrupt;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::panic::{self, AssertUnwindSafe};
+use windows::Win32::Foundation::{
+    CLASS_E_CLASSNOTAVAILABLE, CO_E_CLASSSTRING, DISP_E_EXCEPTION, DISP_E_MEMBERNOTFOUND,
+    DISP_E_UNKNOWNNAME, E_UNEXPECTED, ERROR_TIMEOUT, REGDB_E_CLASSNOTREG, S_OK,
+};
+use windows::Win32::System::Com::{EXCEPINFO, IDispatch, IErrorInfo, ISupportErrorInfo};
+use windows::core::{BSTR, Error, GUID, HRESULT, IUnknown, Interface};
+
+pub type Result<T> = std::result::Result<T, ComError>;
+
+/// A failed operation
+#[derive(Debug)]
+pub struct ComError {
+    error: Error,
+    exception: Option<Box<Exception>>,
+    error_info: Option<Box<ErrorInfo>>,
+    /// Whether the server failed the Invoke itself, rather than the object not being
+    /// found or the request not reaching it
+    invoked: bool,
+    /// JSON pointer to the value within the step that could not be converted
+    pointer: Option<String>,
+}
+
+/// Exit status of the process by class of failure, so scripts can branch on it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExitStatus {
+    Success = 0,
+    /// Any failure not covered by a more specific status
+    Failure = 1,
+    /// The request could not be parsed or is missing fields
+    InvalidRequest = 2,
+    /// The ProgID or CLSID is not registered
+    ClassNotRegistered = 3,
+    /// The object has no member with the given name, or it cannot be used this way
+    MemberNotFound = 4,
+    /// The server failed a call, property get or property put
+    InvokeFailed = 5,
+    /// A step ran past its timeout
+    Timeout = 6,
+    /// Ctrl+C or the closing of the console stopped the request
+    Interrupted = 7,
+}
+
+impl ExitStatus {
+    /// The status a process exited with, `None` for codes that are not one, such as
+    /// the exception code of a crash
+    pub fn from_code(code: i32) -> Option<Self> {
+        [
+            Self::Success,
+            Self::Failure,
+            Self::InvalidRequest,
+            Self::ClassNotRegistered,
+            Self::MemberNotFound,
+            Self::InvokeFailed,
+            Self::Timeout,
+            Self::Interrupted,
+        ]
+        .into_iter()
+        .find(|status| *status as i32 == code)
+    }
+}
+
+/// Exception raised by a server through `DISP_E_EXCEPTION`, as found in its EXCEPINFO
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Exception {
+    /// Application-defined error code, 0 when `scode` is used instead
+    pub code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help_file: Option<String>,
+    pub help_context: u32,
+    /// HRESULT describing the error, written like `0x800A03EC`
+    #[serde(with = "hresult_hex")]
+    pub scode: i32,
+}
+
+/// Error information a server set with `SetErrorInfo`, as read from IErrorInfo
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Interface that defined the error, such as `{00020400-0000-0000-C000-000000000046}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub help_file: Option<String>,
+    pub help_context: u32,
+}
+
+/// Code of the failures to read a request, which have no HRESULT
+pub const INVALID_REQUEST: &str = "INVALID_REQUEST";
+
+/// Machine-readable description of a failure, as reported under `error`
+#[derive(Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// Symbolic name of the HRESULT when it is a well-known one, otherwise the HRESULT
+    /// itself, or `INVALID_REQUEST` when the request could not be read
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hresult: Option<String>,
+    pub message: String,
+    /// Index of the step that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<usize>,
+    /// JSON pointer to the value of the request that could not be converted, such as
+    /// `/steps/1/args/0`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception: Option<Exception>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_info: Option<ErrorInfo>,
+}
+
+impl ErrorReport {
+    /// Reports a request that could not be parsed or is missing fields
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self {
+            code: INVALID_REQUEST.to_string(),
+            hresult: None,
+            message: message.into(),
+            step: None,
+            pointer: None,
+            hint: None,
+            exception: None,
+            error_info: None,
+        }
+    }
+}
+
+/// Writes HRESULTs the way Windows documents them, like `0x80020009`
+mod hresult_hex {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(code: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:#010X}", *code as u32))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+
+        u32::from_str_radix(digits, 16)
+            .map(|code| code as i32)
+            .map_err(|_| D::Error::custom(format!("invalid HRESULT '{text}'")))
+    }
+}
+
+impl ComError {
+    pub fn code(&self) -> HRESULT {
+        self.error.code()
+    }
+
+    /// Marks the error as returned by Invoke
+    pub fn invoked(mut self) -> Self {
+        self.invoked = true;
+        self
+    }
+
+    /// Marks the error as caused by the value at `pointer` within the step
+    pub(crate) fn at(mut self, pointer: &str) -> Self {
+        self.pointer = Some(pointer.to_string());
+        self
+    }
+
+    /// Marks the error as caused by the argument at `pointer` within the step, which
+    /// the message then names, like `Type mismatch at /args/1`
+    pub(crate) fn at_argument(mut self, pointer: &str) -> Self {
+        let message = self.error.message();
+
+        self.error = Error::new(
+            self.code(),
+            format!("{} at {pointer}", message.trim_end_matches('.')),
+        );
+        self.at(pointer)
+    }
+
+    pub fn exit_status(&self) -> ExitStatus {
+        match self.code() {
+            code if code == ERROR_TIMEOUT.to_hresult() => ExitStatus::Timeout,
+            interrupt::INTERRUPTION => ExitStatus::Interrupted,
+            REGDB_E_CLASSNOTREG | CO_E_CLASSSTRING | CLASS_E_CLASSNOTAVAILABLE => {
+                ExitStatus::ClassNotRegistered
+            }
+            DISP_E_UNKNOWNNAME | DISP_E_MEMBERNOTFOUND => ExitStatus::MemberNotFound,
+            _ if self.invoked => ExitStatus::InvokeFailed,
+            _ => ExitStatus::Failure,
+        }
+    }
+
+    /// The HRESULT written like `0x80040154`
+    pub fn hresult(&self) -> String {
+        format!("{:#010X}", self.code().0 as u32)
+    }
+
+    /// Symbolic name and explanation of the HRESULT, when it is a well-known one
+    pub fn known(&self) -> Option<&'static KnownHresult> {
+        hresult::lookup(self.code())
+    }
+
+    /// Describes the failure for the output, `step` being the index of the failed step
+    pub fn report(&self, step: Option<usize>) -> ErrorReport {
+        let hresult = self.hresult();
+        let known = self.known();
+
+        ErrorReport {
+            code: known.map_or_else(|| hresult.clone(), |known| known.name.to_string()),
+            hresult: Some(hresult),
+            message: self.error.message(),
+            step,
+            pointer: self.pointer.clone(),
+            hint: known.map(|known| known.hint.to_string()),
+            exception: self.exception.as_deref().cloned(),
+            error_info: self.error_info.as_deref().cloned(),
+        }
+    }
+
+    /// Attaches the error information captured with the error, provided `object`
+    /// declares that it sets error information for IDispatch. Without that promise
+    /// the information may be left over from an unrelated call.
+    ///
+    /// # Safety
+    ///
+    /// `object` must belong to the apartment of the current thread.
+    pub unsafe fn with_error_info(mut self, object: &IDispatch) -> Self {
+        unsafe {
+            let Ok(support) = object.cast::<ISupportErrorInfo>() else {
+                return self;
+            };
+
+            // InterfaceSupportsErrorInfo answers no with S_FALSE, which is not a failure
+            if (support.vtable().InterfaceSupportsErrorInfo)(support.as_raw(), &IDispatch::IID)
+                != S_OK
+            {
+                return self;
+            }
+
+            let raw = self.error.as_ptr();
+            let Some(info) = IUnknown::from_raw_borrowed(&raw)
+                .and_then(|unknown| unknown.cast::<IErrorInfo>().ok())
+            else {
+                return self;
+            };
+            let guid = info.GetGUID().unwrap_or_default();
+
+            self.error_info = Some(Box::new(ErrorInfo {
+                description: info.GetDescription().ok().and_then(non_empty),
+                source: info.GetSource().ok().and_then(non_empty),
+                guid: (guid != GUID::zeroed()).then(|| format!("{{{guid:?}}}")),
+                help_file: info.GetHelpFile().ok().and_then(non_empty),
+                help_context: info.GetHelpContext().unwrap_or_default(),
+            }));
+
+            self
+        }
+    }
+}
+
+fn non_empty(text: BSTR) -> Option<String> {
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Runs an operation, turning a panic within it into an `E_UNEXPECTED` error, so that
+/// a bug in a conversion or call fails the step rather than the whole process
+pub(crate) fn catch_panic<T>(operation: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(operation))
+        .unwrap_or_else(|payload| Err(panicked(payload)))
+}
+
+/// The error of an operation that panicked with `payload`
+pub(crate) fn panicked(payload: Box<dyn Any + Send>) -> ComError {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+
+    Error::new(E_UNEXPECTED, format!("Internal error: {message}")).into()
+}
+
+impl From<Error> for ComError {
+    fn from(error: Error) -> Self {
+        Self {
+            error,
+            exception: None,
+            error_info: None,
+            invoked: false,
+            pointer: None,
+        }
+    }
+}
+
+impl fmt::Display for ComError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = self.error.message();
+        let hresult = self.hresult();
+
+        match self.known() {
+            Some(known) => write!(f, "{message} ({hresult} {}). {}", known.name, known.hint),
+            None => write!(f, "{message} ({hresult})"),
+        }
+    }
+}
+
+impl Exception {
+    /// Takes the contents of an EXCEPINFO filled by a failed Invoke, running its
+    /// deferred fill-in first when the server postponed filling it
+    ///
+    /// # Safety
+    ///
+    /// `info` must have been filled by Invoke, so that its strings are valid BSTRs.
+    pub unsafe fn take(info: &mut EXCEPINFO) -> Self {
+        unsafe {
+            if let Some(fill_in) = info.pfnDeferredFillIn.take() {
+                let _ = fill_in(info);
+            }
+
+            Self {
+                code: info.wCode,
+                source: non_empty(ManuallyDrop::take(&mut info.bstrSource)),
+                description: non_empty(ManuallyDrop::take(&mut info.bstrDescription)),
+                help_file: non_empty(ManuallyDrop::take(&mut info.bstrHelpFile)),
+                help_context: info.dwHelpContext,
+                scode: info.scode,
+            }
+        }
+    }
+
+    /// Turns the exception into an error, reported under its `scode` when the server
+    /// gave one
+    pub fn into_error(self) -> ComError {
+        let code = match self.scode {
+            0 => DISP_E_EXCEPTION,
+            scode => HRESULT(scode),
+        };
+        let message = self
+            .description
+            .clone()
+            .unwrap_or_else(|| format!("Exception {} raised by the server", self.code));
+
+        ComError {
+            error: Error::new(code, message),
+            exception: Some(Box::new(self)),
+            error_info: None,
+            invoked: false,
+            pointer: None,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! The `Win32ComCli` ETW provider, which writes activations, Invokes and conversion
+//! warnings as TraceLogging events, so that WPA and xperf show them alongside the
+//! traces of the automated application.
+
+use crate::event_log::Message;
+use std::fmt;
+use tracelogging as tlg;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+tlg::define_provider!(
+    PROVIDER,
+    "Win32ComCli",
+    id("2806a303-a8c1-403b-a240-80cea42577f7")
+);
+
+/// Keyword of the activation events
+const ACTIVATION: u64 = 0x1;
+/// Keyword of the Invoke events
+const INVOKE: u64 = 0x2;
+/// Keyword of the warnings about values that could not be converted
+const CONVERSION: u64 = 0x4;
+
+/// Registers the provider for the life of the process
+pub fn register() {
+    // Providers only have to be unregistered by DLLs, before they are unloaded
+    unsafe {
+        PROVIDER.register();
+    }
+}
+
+/// Whether a trace session listens to what tracing is about to report
+fn enabled(metadata: &Metadata<'_>) -> bool {
+    match metadata.name() {
+        "activate" => PROVIDER.enabled(tlg::Level::Informational, ACTIVATION),
+        "invoke" => PROVIDER.enabled(tlg::Level::Verbose, INVOKE),
+        _ => {
+            metadata.is_event()
+                && *metadata.level() == tracing::Level::WARN
+                && metadata.module_path() == Some("win32_com_cli::variant")
+                && PROVIDER.enabled(tlg::Level::Warning, CONVERSION)
+        }
+    }
+}
+
+/// A layer writing the events of the provider, which costs nothing while no trace
+/// session listens to it
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Etw.with_filter(filter_fn(enabled))
+}
+
+struct Etw;
+
+/// Fields of an activation or Invoke span, kept until the span closes
+#[derive(Default)]
+struct SpanFields {
+    activity: tlg::Guid,
+    prog_id: String,
+    clsid: String,
+    moniker: String,
+    member: String,
+    dispid: i32,
+    flags: u16,
+    hresult: i32,
+}
+
+impl Visit for SpanFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "prog_id" => self.prog_id = value.to_string(),
+            "clsid" => self.clsid = value.to_string(),
+            "moniker" => self.moniker = value.to_string(),
+            "member" => self.member = value.to_string(),
+            // Recorded like 0x80020009
+            "hresult" => {
+                self.hresult = u32::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .map_or(0, |code| code as i32)
+            }
+            _ => {}
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "dispid" {
+            self.dispid = value as i32;
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "flags" {
+            self.flags = value as u16;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl<S> Layer<S> for Etw
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        let Some(span) = context.span(id) else {
+            return;
+        };
+        let mut fields = SpanFields {
+            activity: tlg::Guid::new(),
+            ..SpanFields::default()
+        };
+
+        attributes.record(&mut fields);
+
+        match attributes.metadata().name() {
+            "activate" => tlg::write_event!(
+                PROVIDER,
+                "Activation",
+                level(Informational),
+                keyword(ACTIVATION),
+                opcode(Start),
+                activity_id(&fields.activity),
+                str8("ProgId", &fields.prog_id),
+                str8("Clsid", &fields.clsid),
+                str8("Moniker", &fields.moniker),
+            ),
+            _ => tlg::write_event!(
+                PROVIDER,
+                "Invoke",
+                level(Verbose),
+                keyword(INVOKE),
+                opcode(Start),
+                activity_id(&fields.activity),
+                str8("Member", &fields.member),
+                i32("DispId", &fields.dispid),
+                u16("Flags", &fields.flags),
+            ),
+        };
+
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        if let Some(span) = context.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<SpanFields>()
+        {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let mut message = Message::default();
+
+        event.record(&mut message);
+        tlg::write_event!(
+            PROVIDER,
+            "ConversionWarning",
+            level(Warning),
+            keyword(CONVERSION),
+            str8("Message", &message.0),
+        );
+    }
+
+    fn on_close(&self, id: Id, context: Context<'_, S>) {
+        let Some(span) = context.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(fields) = extensions.get::<SpanFields>() else {
+            return;
+        };
+
+        match span.name() {
+            "activate" => tlg::write_event!(
+                PROVIDER,
+                "Activation",
+                level(Informational),
+                keyword(ACTIVATION),
+                opcode(Stop),
+                activity_id(&fields.activity),
+                hresult("HResult", &fields.hresult),
+            ),
+            _ => tlg::write_event!(
+                PROVIDER,
+                "Invoke",
+                level(Verbose),
+                keyword(INVOKE),
+                opcode(Stop),
+                activity_id(&fields.activity),
+                str8("Member", &fields.member),
+                hresult("HResult", &fields.hresult),
+            ),
+        };
+    }
+}
@@ -0,0 +1,179 @@
+//! `--event-log`, which reports the steps that fail to the Application event log for
+//! runs started by schedulers, where nobody reads stderr, and the
+//! `register-event-source` subcommand registering the source it reports under.
+
+use crate::registry::{self, Key};
+use std::fmt::{self, Write as _};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::EventLog::{
+    DeregisterEventSource, EVENTLOG_ERROR_TYPE, RegisterEventSourceW, ReportEventW,
+};
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use windows::core::{HSTRING, PCWSTR, Result};
+
+/// Name of the source the events are reported under
+const SOURCE: &str = "win32-com-cli";
+
+const SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\win32-com-cli";
+
+/// Message file of the .NET Framework, whose message for every event ID is its first
+/// insertion string, so that Event Viewer shows the events as they were written
+const MESSAGE_FILE: &str = r"%SystemRoot%\Microsoft.NET\Framework\v4.0.30319\EventLogMessages.dll";
+
+/// Event ID of a create step that failed
+const ACTIVATION_FAILED: u32 = 1;
+/// Event ID of any other step that failed, mostly failed Invokes
+const STEP_FAILED: u32 = 2;
+/// Event ID of the errors logged outside of steps, such as a call that could not be
+/// cancelled
+const ERROR: u32 = 3;
+
+/// Registers the event source, which takes an elevated prompt. The events of an
+/// unregistered source are logged too, but Event Viewer prefixes them with a notice
+/// that their description cannot be found.
+pub fn register() -> Result<()> {
+    Key::create(HKEY_LOCAL_MACHINE, SOURCE_KEY)?
+        .set_expandable_string("EventMessageFile", MESSAGE_FILE)
+}
+
+pub fn unregister() -> Result<()> {
+    registry::delete(HKEY_LOCAL_MACHINE, SOURCE_KEY)
+}
+
+/// A layer reporting the steps that fail and the errors logged to the event log
+pub struct EventLog(HANDLE);
+
+// Event sources may be reported to from any thread
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    pub fn open() -> Result<Self> {
+        Ok(Self(unsafe {
+            RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(SOURCE))?
+        }))
+    }
+
+    fn report(&self, id: u32, message: &str) {
+        let message = HSTRING::from(message);
+
+        // There is nowhere left to report a failure to
+        let _ = unsafe {
+            ReportEventW(
+                self.0,
+                EVENTLOG_ERROR_TYPE,
+                0,
+                id,
+                None,
+                0,
+                Some(&[PCWSTR(message.as_ptr())]),
+                None,
+            )
+        };
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = DeregisterEventSource(self.0);
+        }
+    }
+}
+
+/// Fields of a step span, kept until the span closes
+#[derive(Default)]
+struct StepFields {
+    op: String,
+    subject: Option<String>,
+    error: Option<String>,
+}
+
+impl Visit for StepFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "op" => self.op = value.to_string(),
+            "subject" => self.subject = Some(value.to_string()),
+            "error" => self.error = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+/// The message of an event
+#[derive(Default)]
+pub struct Message(pub String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        }
+    }
+}
+
+impl<S> Layer<S> for EventLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        if attributes.metadata().name() == "step"
+            && let Some(span) = context.span(id)
+        {
+            let mut fields = StepFields::default();
+
+            attributes.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, context: Context<'_, S>) {
+        if let Some(span) = context.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<StepFields>()
+        {
+            values.record(fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        if *event.metadata().level() == Level::ERROR {
+            let mut message = Message::default();
+
+            event.record(&mut message);
+            self.report(ERROR, &message.0);
+        }
+    }
+
+    fn on_close(&self, id: Id, context: Context<'_, S>) {
+        let Some(span) = context.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(StepFields {
+            op,
+            subject,
+            error: Some(error),
+        }) = extensions.get::<StepFields>()
+        else {
+            return;
+        };
+        let id = match op.as_str() {
+            "create" => ACTIVATION_FAILED,
+            _ => STEP_FAILED,
+        };
+
+        match subject {
+            Some(subject) => {
+                self.report(id, &format!("The {op} step of {subject} failed: {error}"))
+            }
+            None => self.report(id, &format!("The {op} step failed: {error}")),
+        }
+    }
+}
@@ -0,0 +1,465 @@
+//! Events of objects, received through the connection points of their source
+//! interfaces. A `subscribe` step advises a sink on the events interface of an
+//! object, and every event the object fires is queued along with copies of its
+//! arguments until the session reports it as a JSON event.
+//!
+//! Sinks are implemented by hand rather than through `#[implement]`, as servers ask
+//! them for the IID of the events interface, which is only known at run time.
+
+use crate::activation::parse_clsid;
+use crate::error::Result;
+use crate::objects::ObjectTable;
+use crate::typeinfo::{containing_library, describe_type, documentation, object_type_info};
+use crate::variant::variant_to_value;
+use crate::webhook::Endpoint;
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+use windows::Win32::Foundation::{
+    CloseHandle, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL, E_POINTER, S_OK,
+};
+use windows::Win32::System::Com::{
+    COWAIT_DISPATCH_CALLS, COWAIT_DISPATCH_WINDOW_MESSAGES, CoWaitForMultipleHandles,
+    DISPATCH_FLAGS, DISPPARAMS, EXCEPINFO, IConnectionPoint, IConnectionPointContainer, IDispatch,
+    IDispatch_Vtbl, IMPLTYPEFLAG_FDEFAULT, IMPLTYPEFLAG_FSOURCE, IMPLTYPEFLAGS, ITypeInfo,
+    TKIND_COCLASS,
+};
+use windows::Win32::System::Ole::IProvideClassInfo;
+use windows::Win32::System::Threading::CreateEventW;
+use windows::Win32::System::Variant::{VARIANT, VariantClear, VariantCopyInd};
+use windows::core::{Error, GUID, HRESULT, IUnknown, IUnknown_Vtbl, Interface, PCWSTR};
+
+/// An event fired to a sink, with copies of its arguments in declaration order
+pub(crate) struct Fired {
+    object: String,
+    interface: String,
+    event: String,
+    params: Vec<String>,
+    args: Vec<VARIANT>,
+}
+
+/// The events fired to the sinks of a session and not reported yet
+pub(crate) type EventQueue = Rc<RefCell<VecDeque<Fired>>>;
+
+/// The name and parameter names of an event, by DISPID
+type EventMembers = HashMap<i32, (String, Vec<String>)>;
+
+/// A sink advised on the connection point of an object, which is unadvised when
+/// dropped
+pub(crate) struct Subscription {
+    /// Name or handle of the object firing the events
+    pub(crate) object: String,
+    /// Name of the events interface, or its IID without type information
+    pub(crate) interface: String,
+    /// Where the events are POSTed besides being reported
+    pub(crate) callback: Option<Endpoint>,
+    point: IConnectionPoint,
+    cookie: u32,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Err(error) = unsafe { self.point.Unadvise(self.cookie) } {
+            warn!("Failed to unsubscribe from {}: {error}", self.interface);
+        }
+    }
+}
+
+/// The dispatch interface receiving the events of one subscription
+#[repr(C)]
+struct Sink {
+    vtable: *const IDispatch_Vtbl,
+    references: AtomicU32,
+    /// IID of the events interface, which servers query the sink for
+    iid: GUID,
+    object: String,
+    interface: String,
+    members: EventMembers,
+    queue: EventQueue,
+}
+
+static SINK_VTABLE: IDispatch_Vtbl = IDispatch_Vtbl {
+    base__: IUnknown_Vtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    GetTypeInfoCount: get_type_info_count,
+    GetTypeInfo: get_type_info,
+    GetIDsOfNames: get_ids_of_names,
+    Invoke: invoke,
+};
+
+unsafe extern "system" fn query_interface(
+    this: *mut c_void,
+    iid: *const GUID,
+    interface: *mut *mut c_void,
+) -> HRESULT {
+    unsafe {
+        if interface.is_null() {
+            return E_POINTER;
+        }
+
+        let sink = &*(this as *const Sink);
+
+        if *iid == IUnknown::IID || *iid == IDispatch::IID || *iid == sink.iid {
+            add_ref(this);
+            *interface = this;
+            S_OK
+        } else {
+            *interface = std::ptr::null_mut();
+            E_NOINTERFACE
+        }
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+    let sink = unsafe { &*(this as *const Sink) };
+
+    sink.references.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+    let remaining = unsafe { &*(this as *const Sink) }
+        .references
+        .fetch_sub(1, Ordering::Release)
+        - 1;
+
+    if remaining == 0 {
+        drop(unsafe { Box::from_raw(this as *mut Sink) });
+    }
+
+    remaining
+}
+
+unsafe extern "system" fn get_type_info_count(_this: *mut c_void, count: *mut u32) -> HRESULT {
+    if count.is_null() {
+        return E_POINTER;
+    }
+
+    unsafe { *count = 0 };
+
+    S_OK
+}
+
+unsafe extern "system" fn get_type_info(
+    _this: *mut c_void,
+    _index: u32,
+    _lcid: u32,
+    _info: *mut *mut c_void,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+unsafe extern "system" fn get_ids_of_names(
+    _this: *mut c_void,
+    _iid: *const GUID,
+    _names: *const PCWSTR,
+    _count: u32,
+    _lcid: u32,
+    _dispids: *mut i32,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+/// Queues the event, copying its arguments since they only live for the call
+unsafe extern "system" fn invoke(
+    this: *mut c_void,
+    dispid: i32,
+    _iid: *const GUID,
+    _lcid: u32,
+    _flags: DISPATCH_FLAGS,
+    params: *const DISPPARAMS,
+    _result: *mut VARIANT,
+    _exception: *mut EXCEPINFO,
+    _arg_error: *mut u32,
+) -> HRESULT {
+    unsafe {
+        let sink = &*(this as *const Sink);
+        let (event, names) = match sink.members.get(&dispid) {
+            Some((event, names)) => (event.clone(), names.clone()),
+            None => (dispid.to_string(), Vec::new()),
+        };
+        let mut args = Vec::new();
+
+        if let Some(params) = params.as_ref()
+            && !params.rgvarg.is_null()
+        {
+            // Arguments are passed last to first
+            for index in (0..params.cArgs as usize).rev() {
+                let mut copy = VARIANT::default();
+
+                if let Err(error) = VariantCopyInd(&mut copy, params.rgvarg.add(index)) {
+                    warn!("Unable to copy an argument of event {event}: {error}");
+                }
+
+                args.push(copy);
+            }
+        }
+
+        sink.queue.borrow_mut().push_back(Fired {
+            object: sink.object.clone(),
+            interface: sink.interface.clone(),
+            event,
+            params: names,
+            args,
+        });
+    }
+
+    S_OK
+}
+
+/// The coclass of an object, as it tells or as the library of its interface declares
+unsafe fn coclass_of(object: &IDispatch) -> Result<ITypeInfo> {
+    unsafe {
+        if let Ok(info) = object
+            .cast::<IProvideClassInfo>()
+            .and_then(|provider| provider.GetClassInfo())
+        {
+            return Ok(info);
+        }
+
+        let info = object_type_info(object)?;
+        let attributes = info.GetTypeAttr()?;
+        let guid = (*attributes).guid;
+        info.ReleaseTypeAttr(attributes);
+
+        let library = containing_library(&info)?;
+
+        for index in 0..library.GetTypeInfoCount() {
+            if library.GetTypeInfoType(index)? != TKIND_COCLASS {
+                continue;
+            }
+
+            let coclass = library.GetTypeInfo(index)?;
+            let attributes = coclass.GetTypeAttr()?;
+            let implemented = (*attributes).cImplTypes as u32;
+            coclass.ReleaseTypeAttr(attributes);
+
+            for implementation in 0..implemented {
+                let interface =
+                    coclass.GetRefTypeInfo(coclass.GetRefTypeOfImplType(implementation)?)?;
+                let attributes = interface.GetTypeAttr()?;
+                let matches = (*attributes).guid == guid;
+                interface.ReleaseTypeAttr(attributes);
+
+                if matches {
+                    return Ok(coclass);
+                }
+            }
+        }
+
+        Err(Error::new(E_NOINTERFACE, "The class of the object declares no events").into())
+    }
+}
+
+/// The events interface of an object named `name`, regardless of case, or its default
+/// one
+unsafe fn source_interface(object: &IDispatch, name: Option<&str>) -> Result<ITypeInfo> {
+    unsafe {
+        let coclass = coclass_of(object)?;
+        let attributes = coclass.GetTypeAttr()?;
+        let implemented = (*attributes).cImplTypes as u32;
+        coclass.ReleaseTypeAttr(attributes);
+
+        let mut sources = Vec::new();
+
+        for index in 0..implemented {
+            let flags: IMPLTYPEFLAGS = coclass.GetImplTypeFlags(index)?;
+
+            if flags.0 & IMPLTYPEFLAG_FSOURCE.0 != 0 {
+                let interface = coclass.GetRefTypeInfo(coclass.GetRefTypeOfImplType(index)?)?;
+                let default = flags.0 & IMPLTYPEFLAG_FDEFAULT.0 != 0;
+
+                sources.push((documentation(&interface, -1).0, interface, default));
+            }
+        }
+
+        let found = match name {
+            Some(name) => sources
+                .iter()
+                .position(|(source, ..)| source.eq_ignore_ascii_case(name)),
+            None => sources
+                .iter()
+                .position(|(.., default)| *default)
+                .or((!sources.is_empty()).then_some(0)),
+        };
+
+        let names: Vec<&str> = sources.iter().map(|(name, ..)| name.as_str()).collect();
+        let message = match found {
+            Some(index) => return Ok(sources.swap_remove(index).1),
+            None if names.is_empty() => "The class of the object declares no events".to_string(),
+            None => format!(
+                "'{}' is not an events interface of the object, which fires {}",
+                name.unwrap_or_default(),
+                names.join(", ")
+            ),
+        };
+
+        Err(Error::new(E_NOINTERFACE, message).into())
+    }
+}
+
+/// The IID, name and events of the events interface of an object. An interface given
+/// as an IID in braces is subscribed to without type information, its events being
+/// named after their DISPID.
+///
+/// # Safety
+///
+/// `object` must belong to the apartment of the current thread.
+pub(crate) unsafe fn events_interface(
+    object: &IDispatch,
+    interface: Option<&str>,
+) -> Result<(GUID, String, EventMembers)> {
+    if let Some(iid) = interface.filter(|name| name.starts_with('{')) {
+        let iid = parse_clsid(iid)
+            .map_err(|_| Error::new(E_INVALIDARG, format!("Invalid interface IID '{iid}'")))?;
+
+        return Ok((iid, format!("{{{iid:?}}}"), EventMembers::new()));
+    }
+
+    unsafe {
+        let info = source_interface(object, interface)?;
+        let description = describe_type(&info)?;
+        let attributes = info.GetTypeAttr()?;
+        let iid = (*attributes).guid;
+        info.ReleaseTypeAttr(attributes);
+
+        let members = description
+            .members
+            .into_iter()
+            .map(|member| {
+                let params = member.params.into_iter().map(|param| param.name).collect();
+
+                (member.dispid, (member.name, params))
+            })
+            .collect();
+
+        Ok((iid, description.name, members))
+    }
+}
+
+/// Advises a sink queueing the events of `interface`, or of the default events
+/// interface, of the object named `name`
+///
+/// # Safety
+///
+/// `object` must belong to the apartment of the current thread.
+pub(crate) unsafe fn subscribe(
+    object: &IDispatch,
+    name: &str,
+    interface: Option<&str>,
+    queue: &EventQueue,
+) -> Result<Subscription> {
+    unsafe {
+        let (iid, interface, members) = events_interface(object, interface)?;
+        let container = object.cast::<IConnectionPointContainer>().map_err(|_| {
+            Error::new(
+                E_NOINTERFACE,
+                "The object fires no events (no IConnectionPointContainer)",
+            )
+        })?;
+        let point = container.FindConnectionPoint(&iid)?;
+        let sink = Box::into_raw(Box::new(Sink {
+            vtable: &SINK_VTABLE,
+            references: AtomicU32::new(1),
+            iid,
+            object: name.to_string(),
+            interface: interface.clone(),
+            members,
+            queue: Rc::clone(queue),
+        }));
+        // Takes over the reference the sink was created with
+        let sink = IUnknown::from_raw(sink as *mut c_void);
+        let cookie = point.Advise(&sink)?;
+
+        Ok(Subscription {
+            object: name.to_string(),
+            interface,
+            callback: None,
+            point,
+            cookie,
+        })
+    }
+}
+
+type EventHandler = Box<dyn Fn(&Value) + Send + Sync>;
+
+static EVENT_HANDLER: OnceLock<EventHandler> = OnceLock::new();
+
+/// Sets how the events fired during the steps of requests are written as they are
+/// reported. Without a handler they are only logged. Only the first handler set is
+/// used.
+pub fn set_event_handler(handler: impl Fn(&Value) + Send + Sync + 'static) {
+    let _ = EVENT_HANDLER.set(Box::new(handler));
+}
+
+/// Hands an event to the handler of the process
+pub(crate) fn publish(event: &Value) {
+    match EVENT_HANDLER.get() {
+        Some(handler) => handler(event),
+        None => info!("Event: {event}"),
+    }
+}
+
+/// Dispatches the calls and messages waiting for the current thread, through which
+/// servers fire their events, for up to `timeout`
+pub(crate) fn pump(timeout: Duration) {
+    unsafe {
+        // Never signaled, so that the wait only dispatches until the timeout
+        let Ok(idle) = CreateEventW(None, true, false, None) else {
+            return;
+        };
+        let flags = COWAIT_DISPATCH_CALLS.0 | COWAIT_DISPATCH_WINDOW_MESSAGES.0;
+        let timeout_ms = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        let _ = CoWaitForMultipleHandles(flags as u32, timeout_ms, &[idle]);
+        let _ = CloseHandle(idle);
+    }
+}
+
+/// Takes the events queued so far as JSON: the `event` named, the `object` and
+/// `interface` of its subscription and its `args` by parameter name, or by position
+/// for parameters without one. Objects passed to events are registered as handles.
+///
+/// # Safety
+///
+/// Must be called on the thread of the session the queue belongs to.
+pub(crate) unsafe fn drain(queue: &EventQueue, lcid: u32, objects: &mut ObjectTable) -> Vec<Value> {
+    let mut events = Vec::new();
+
+    // Converting an argument may dispatch further events into the queue
+    loop {
+        let Some(mut fired) = queue.borrow_mut().pop_front() else {
+            break;
+        };
+        let mut args = Map::new();
+
+        for (index, arg) in fired.args.iter_mut().enumerate() {
+            let name = fired
+                .params
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| index.to_string());
+
+            unsafe {
+                args.insert(name, variant_to_value(arg, lcid, objects));
+                let _ = VariantClear(arg);
+            }
+        }
+
+        events.push(json!({
+            "event": fired.event,
+            "object": fired.object,
+            "interface": fired.interface,
+            "args": args,
+        }));
+    }
+
+    events
+}
@@ -0,0 +1,62 @@
+//! C ABI for hosts embedding the engine in-process, such as C# through P/Invoke, Python
+//! through ctypes or Go through cgo. See `include/win32_com_cli.h`.
+//!
+//! Requests and results are the same JSON documents the CLI reads and writes, as
+//! NUL-terminated UTF-8 strings. A request runs on the calling thread, within its
+//! apartment if the host already entered one.
+
+use crate::error::panicked;
+use crate::request::{invalid_request, run_request};
+use serde_json::{Value, json};
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Runs one request and returns its result document, never null, a panic being
+/// reported as an `E_UNEXPECTED` error rather than unwinding into the host. The
+/// result must be given back to `com_cli_free_string`.
+///
+/// # Safety
+///
+/// `request` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn com_cli_execute_json(request: *const c_char) -> *mut c_char {
+    let (output, _) = if request.is_null() {
+        invalid_request(None, "The request is null".to_string())
+    } else {
+        let request = unsafe { CStr::from_ptr(request) };
+
+        match request.to_str().map(serde_json::from_str::<Value>) {
+            Ok(Ok(request)) => panic::catch_unwind(AssertUnwindSafe(|| run_request(request)))
+                .unwrap_or_else(|payload| {
+                    let error = panicked(payload);
+
+                    (
+                        json!({ "ok": false, "error": error.report(None) }),
+                        error.exit_status(),
+                    )
+                }),
+            Ok(Err(error)) => {
+                invalid_request(None, format!("Failed to parse request JSON: {error}"))
+            }
+            Err(error) => invalid_request(None, format!("The request is not UTF-8: {error}")),
+        }
+    };
+
+    // Serialized JSON escapes NUL characters, so it never contains one
+    CString::new(output.to_string())
+        .expect("JSON contains no NUL character")
+        .into_raw()
+}
+
+/// Frees a string returned by `com_cli_execute_json`
+///
+/// # Safety
+///
+/// `string` must be null or have been returned by `com_cli_execute_json`, and must not
+/// be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn com_cli_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
@@ -0,0 +1,154 @@
+//! Encodings of the documents read from stdin and written to stdout. Every encoding
+//! carries the same schema, the binary ones merely being faster to produce and parse
+//! for large arrays.
+
+use clap::ValueEnum;
+use serde_json::Value;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Whitespace-separated JSON documents, one line per document on output
+    #[default]
+    Json,
+    /// Consecutive MessagePack values
+    #[value(name = "msgpack")]
+    MessagePack,
+    /// Consecutive CBOR data items
+    Cbor,
+}
+
+/// Why a document could not be decoded
+#[derive(Debug)]
+pub struct DecodeError {
+    pub message: String,
+    /// Line and column of the error, both counted from 1, for JSON documents
+    pub position: Option<(usize, usize)>,
+}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(error: serde_json::Error) -> Self {
+        let position = (error.line() > 0).then(|| (error.line(), error.column()));
+        let message = error.to_string();
+        // The position is reported separately rather than as part of the message
+        let message = match position {
+            Some((line, column)) => message
+                .strip_suffix(&format!(" at line {line} column {column}"))
+                .map_or(message.clone(), str::to_string),
+            None => message,
+        };
+
+        Self { message, position }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{} at line {line}, column {column}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Quotes the line of `text` an error points at, with a caret under its column
+pub fn excerpt(text: &str, (line, column): (usize, usize)) -> Option<String> {
+    let quoted = text.lines().nth(line.checked_sub(1)?)?;
+    let gutter = line.to_string().len();
+    let caret = " ".repeat(column.saturating_sub(1));
+
+    Some(format!(
+        "{line:>gutter$} | {quoted}\n{:>gutter$} | {caret}^",
+        ""
+    ))
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            Self::MessagePack => "MessagePack",
+            Self::Cbor => "CBOR",
+        })
+    }
+}
+
+impl Format {
+    /// Reads documents until the end of the input. Documents that cannot be decoded
+    /// are returned as the decoder's error, past which the stream cannot be trusted.
+    pub fn documents<R: Read + 'static>(
+        self,
+        reader: R,
+    ) -> Box<dyn Iterator<Item = std::result::Result<Value, DecodeError>>> {
+        if self == Self::Json {
+            return Box::new(
+                serde_json::Deserializer::from_reader(reader)
+                    .into_iter()
+                    .map(|document| document.map_err(DecodeError::from)),
+            );
+        }
+
+        let fail = |message: String| DecodeError {
+            message,
+            position: None,
+        };
+
+        let mut reader = BufReader::new(reader);
+
+        Box::new(std::iter::from_fn(move || {
+            // Only the end of the input between two documents is a clean end
+            match reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(_) => {}
+                Err(error) => return Some(Err(fail(error.to_string()))),
+            }
+
+            let document = match self {
+                Self::MessagePack => {
+                    rmp_serde::from_read(&mut reader).map_err(|error| fail(error.to_string()))
+                }
+                Self::Cbor => {
+                    ciborium::from_reader(&mut reader).map_err(|error| fail(error.to_string()))
+                }
+                Self::Json => unreachable!(),
+            };
+
+            Some(document)
+        }))
+    }
+
+    /// Writes one document and flushes it, so the reader gets it right away
+    pub fn write(self, writer: &mut (impl Write + ?Sized), document: &Value) -> io::Result<()> {
+        match self {
+            Self::Json => {
+                serde_json::to_writer(&mut *writer, document)?;
+                writeln!(writer)?;
+            }
+            Self::MessagePack => {
+                rmp_serde::encode::write(&mut *writer, document).map_err(io::Error::other)?
+            }
+            Self::Cbor => {
+                ciborium::into_writer(document, &mut *writer).map_err(io::Error::other)?
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Writes one document like [`Format::write`], indented for JSON. Binary formats
+    /// have no layout to choose.
+    pub fn write_pretty(
+        self,
+        writer: &mut (impl Write + ?Sized),
+        document: &Value,
+    ) -> io::Result<()> {
+        if self != Self::Json {
+            return self.write(writer, document);
+        }
+
+        serde_json::to_writer_pretty(&mut *writer, document)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
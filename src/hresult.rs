@@ -0,0 +1,262 @@
+//! Symbolic names and explanations of the HRESULTs COM automation commonly fails with
+
+use windows::Win32::Foundation::*;
+use windows::Win32::System::Rpc::RPC_S_SERVER_UNAVAILABLE;
+use windows::core::HRESULT;
+
+/// Name and explanation of a well-known HRESULT
+pub struct KnownHresult {
+    pub name: &'static str,
+    pub hint: &'static str,
+}
+
+const fn known(code: HRESULT, name: &'static str, hint: &'static str) -> (HRESULT, KnownHresult) {
+    (code, KnownHresult { name, hint })
+}
+
+const KNOWN: &[(HRESULT, KnownHresult)] = &[
+    known(
+        E_FAIL,
+        "E_FAIL",
+        "The server failed without giving a reason.",
+    ),
+    known(
+        E_NOTIMPL,
+        "E_NOTIMPL",
+        "The server does not implement this member.",
+    ),
+    known(
+        E_NOINTERFACE,
+        "E_NOINTERFACE",
+        "The object does not support the requested interface, usually IDispatch.",
+    ),
+    known(E_POINTER, "E_POINTER", "A required pointer was null."),
+    known(E_ABORT, "E_ABORT", "The operation was aborted."),
+    known(
+        E_ACCESSDENIED,
+        "E_ACCESSDENIED",
+        "Access was denied. The server may require elevation or DCOM launch permissions.",
+    ),
+    known(
+        E_INVALIDARG,
+        "E_INVALIDARG",
+        "An argument of the request is invalid.",
+    ),
+    known(
+        E_OUTOFMEMORY,
+        "E_OUTOFMEMORY",
+        "The server ran out of memory.",
+    ),
+    known(
+        E_UNEXPECTED,
+        "E_UNEXPECTED",
+        "The server failed unexpectedly.",
+    ),
+    known(
+        REGDB_E_CLASSNOTREG,
+        "REGDB_E_CLASSNOTREG",
+        "The class is not registered. Check the ProgID or CLSID, and whether the server \
+        is registered for this process's bitness (32 or 64 bit).",
+    ),
+    known(
+        CLASS_E_NOAGGREGATION,
+        "CLASS_E_NOAGGREGATION",
+        "The class does not support aggregation.",
+    ),
+    known(
+        CLASS_E_CLASSNOTAVAILABLE,
+        "CLASS_E_CLASSNOTAVAILABLE",
+        "The server does not provide this class.",
+    ),
+    known(
+        CO_E_CLASSSTRING,
+        "CO_E_CLASSSTRING",
+        "The ProgID is not registered or the CLSID is malformed.",
+    ),
+    known(
+        CO_E_SERVER_EXEC_FAILURE,
+        "CO_E_SERVER_EXEC_FAILURE",
+        "The server process could not be started or did not register its class in time.",
+    ),
+    known(
+        CO_E_NOTINITIALIZED,
+        "CO_E_NOTINITIALIZED",
+        "COM has not been initialized on this thread.",
+    ),
+    known(
+        CO_E_ELEVATION_DISABLED,
+        "CO_E_ELEVATION_DISABLED",
+        "The class is not registered for activation through the elevation moniker.",
+    ),
+    known(
+        CO_E_WRONG_SERVER_IDENTITY,
+        "CO_E_WRONG_SERVER_IDENTITY",
+        "The server runs under a different identity or session than the caller.",
+    ),
+    known(
+        MK_E_UNAVAILABLE,
+        "MK_E_UNAVAILABLE",
+        "No running instance is registered in the Running Object Table.",
+    ),
+    known(
+        MK_E_SYNTAX,
+        "MK_E_SYNTAX",
+        "The moniker display name cannot be parsed.",
+    ),
+    known(
+        MK_E_NOOBJECT,
+        "MK_E_NOOBJECT",
+        "The object named by the moniker does not exist.",
+    ),
+    known(
+        RPC_E_CALL_REJECTED,
+        "RPC_E_CALL_REJECTED",
+        "The server is busy, for example showing a dialog, and rejected the call.",
+    ),
+    known(
+        RPC_E_SERVERCALL_RETRYLATER,
+        "RPC_E_SERVERCALL_RETRYLATER",
+        "The server is busy and asked to retry the call later.",
+    ),
+    known(
+        RPC_E_DISCONNECTED,
+        "RPC_E_DISCONNECTED",
+        "The object was disconnected from its server, which may have been closed.",
+    ),
+    known(
+        RPC_E_SERVER_DIED,
+        "RPC_E_SERVER_DIED",
+        "The server process exited during the call.",
+    ),
+    known(
+        RPC_E_SERVER_DIED_DNE,
+        "RPC_E_SERVER_DIED_DNE",
+        "The server process exited before the call ran.",
+    ),
+    known(
+        RPC_E_WRONG_THREAD,
+        "RPC_E_WRONG_THREAD",
+        "The object was used from a thread other than the one it belongs to.",
+    ),
+    known(
+        RPC_E_CALL_CANCELED,
+        "RPC_E_CALL_CANCELED",
+        "The call was cancelled before the server replied.",
+    ),
+    known(
+        RPC_E_CHANGED_MODE,
+        "RPC_E_CHANGED_MODE",
+        "COM was already initialized on this thread with another apartment model.",
+    ),
+    known(
+        HRESULT::from_win32(RPC_S_SERVER_UNAVAILABLE.0 as u32),
+        "RPC_S_SERVER_UNAVAILABLE",
+        "The remote machine cannot be reached. Check its name, the firewall and that \
+        DCOM is enabled.",
+    ),
+    known(
+        DISP_E_UNKNOWNINTERFACE,
+        "DISP_E_UNKNOWNINTERFACE",
+        "The interface identifier passed to Invoke is not IID_NULL.",
+    ),
+    known(
+        DISP_E_MEMBERNOTFOUND,
+        "DISP_E_MEMBERNOTFOUND",
+        "The member exists but cannot be invoked this way, e.g. setting a read-only \
+        property or calling a property as a method.",
+    ),
+    known(
+        DISP_E_PARAMNOTFOUND,
+        "DISP_E_PARAMNOTFOUND",
+        "A named argument does not match a parameter of the member.",
+    ),
+    known(
+        DISP_E_TYPEMISMATCH,
+        "DISP_E_TYPEMISMATCH",
+        "An argument has a type the member cannot accept.",
+    ),
+    known(
+        DISP_E_UNKNOWNNAME,
+        "DISP_E_UNKNOWNNAME",
+        "The object has no member or parameter with this name.",
+    ),
+    known(
+        DISP_E_NONAMEDARGS,
+        "DISP_E_NONAMEDARGS",
+        "The member does not accept named arguments.",
+    ),
+    known(
+        DISP_E_BADVARTYPE,
+        "DISP_E_BADVARTYPE",
+        "An argument has a VARIANT type the server does not support.",
+    ),
+    known(
+        DISP_E_EXCEPTION,
+        "DISP_E_EXCEPTION",
+        "The server raised an exception, see its details.",
+    ),
+    known(
+        DISP_E_OVERFLOW,
+        "DISP_E_OVERFLOW",
+        "An argument does not fit the type of its parameter.",
+    ),
+    known(
+        DISP_E_BADINDEX,
+        "DISP_E_BADINDEX",
+        "The index is out of range.",
+    ),
+    known(
+        DISP_E_ARRAYISLOCKED,
+        "DISP_E_ARRAYISLOCKED",
+        "An array argument is locked by the server.",
+    ),
+    known(
+        DISP_E_BADPARAMCOUNT,
+        "DISP_E_BADPARAMCOUNT",
+        "The member was passed the wrong number of arguments.",
+    ),
+    known(
+        DISP_E_PARAMNOTOPTIONAL,
+        "DISP_E_PARAMNOTOPTIONAL",
+        "A required argument is missing.",
+    ),
+    known(
+        TYPE_E_ELEMENTNOTFOUND,
+        "TYPE_E_ELEMENTNOTFOUND",
+        "The type library has no element with this name.",
+    ),
+    known(
+        TYPE_E_LIBNOTREGISTERED,
+        "TYPE_E_LIBNOTREGISTERED",
+        "The type library of the server is not registered.",
+    ),
+    known(
+        ERROR_TIMEOUT.to_hresult(),
+        "ERROR_TIMEOUT",
+        "The call did not complete within its timeout.",
+    ),
+    known(
+        ERROR_CANCELLED.to_hresult(),
+        "ERROR_CANCELLED",
+        "The operation was cancelled, e.g. by declining a UAC consent prompt.",
+    ),
+    known(
+        HRESULT(STATUS_CONTROL_C_EXIT.0),
+        "STATUS_CONTROL_C_EXIT",
+        "The request was interrupted by Ctrl+C or the closing of the console, after which \
+        its cleanup steps ran.",
+    ),
+    known(
+        ERROR_ELEVATION_REQUIRED.to_hresult(),
+        "ERROR_ELEVATION_REQUIRED",
+        "The operation requires elevation, see the `elevated` option.",
+    ),
+];
+
+/// Looks up the name and explanation of an HRESULT
+pub fn lookup(code: HRESULT) -> Option<&'static KnownHresult> {
+    KNOWN
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, known)| known)
+}
@@ -0,0 +1,295 @@
+//! HTTP transport for serve mode, exposing the step operations as a REST API. Clients
+//! first open a session, which owns every object they create, and address objects
+//! within it:
+//!
+//! - `POST /sessions` opens a session and returns its id
+//! - `DELETE /sessions/{session}` releases its objects and closes it
+//! - `POST /sessions/{session}/objects` creates an object from a `create` step body
+//! - `POST /sessions/{session}/objects/{object}/invoke` calls a method from a `call`
+//!   step body
+//! - `GET /sessions/{session}/objects/{object}/properties/{path}` reads a property
+//! - `PUT /sessions/{session}/objects/{object}/properties/{path}` sets a property to
+//!   the body's `value`
+//! - `DELETE /sessions/{session}/objects/{object}` releases an object
+//! - `POST /sessions/{session}/objects/{object}/subscriptions` subscribes to the events
+//!   of an object from a `subscribe` step body, whose `callback_url` they are POSTed to
+//! - `DELETE /sessions/{session}/objects/{object}/subscriptions` unsubscribes from them
+//!
+//! Sessions dispatch the events of their objects while idle too, as their clients are
+//! not connected to receive them.
+
+use crate::worker::Session;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use win32_com_cli::error::{ErrorReport, ExitStatus};
+use win32_com_cli::session::{RunOptions, Step};
+use windows::Win32::System::Com::CoCreateGuid;
+
+/// Outcome of a step, sent back from the thread of its session
+type StepOutcome = std::result::Result<Value, (ExitStatus, ErrorReport)>;
+
+/// A step for the thread of a session, along with where to send its outcome
+type Job = (Step, Sender<StepOutcome>);
+
+/// How long an idle session waits for a step before dispatching the events fired
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Open sessions by id. COM objects belong to the apartment of the thread that
+/// created them, so every session runs its steps on a thread of its own.
+type Sessions = Arc<Mutex<HashMap<String, Sender<Job>>>>;
+
+#[derive(Serialize, Deserialize)]
+struct HttpResult {
+    ok: bool,
+    result: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HttpError {
+    ok: bool,
+    error: ErrorReport,
+}
+
+/// Starts the thread of a new session, which ends once the session is closed
+fn open_session(options: RunOptions) -> Sender<Job> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+
+    thread::spawn(move || {
+        let mut session = Session::open(&options);
+
+        loop {
+            match receiver.recv_timeout(IDLE_INTERVAL) {
+                Ok((step, reply)) => {
+                    let outcome = session
+                        .execute(&step)
+                        .map_err(|failure| (failure.status, *failure.report));
+                    let _ = reply.send(outcome);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            // Events only reach clients through the callbacks of their subscriptions
+            session.take_events();
+        }
+    });
+
+    sender
+}
+
+/// Session ids are GUIDs, so that clients cannot guess each other's sessions
+fn new_session_id() -> io::Result<String> {
+    let guid = unsafe { CoCreateGuid()? };
+
+    Ok(format!("{guid:?}").to_lowercase())
+}
+
+fn status_code(status: ExitStatus) -> u16 {
+    match status {
+        ExitStatus::Success => 200,
+        ExitStatus::InvalidRequest => 400,
+        ExitStatus::ClassNotRegistered | ExitStatus::MemberNotFound => 404,
+        ExitStatus::Timeout => 504,
+        ExitStatus::Interrupted => 503,
+        ExitStatus::InvokeFailed | ExitStatus::Failure => 500,
+    }
+}
+
+fn invalid(status: u16, message: impl Into<String>) -> (u16, String) {
+    let error = HttpError {
+        ok: false,
+        error: ErrorReport::invalid_request(message),
+    };
+
+    (
+        status,
+        serde_json::to_string(&error).expect("Failed to serialize the error"),
+    )
+}
+
+fn success(status: u16, result: Value) -> (u16, String) {
+    let result = HttpResult { ok: true, result };
+
+    (
+        status,
+        serde_json::to_string(&result).expect("Failed to serialize the result"),
+    )
+}
+
+/// Decodes the `%XX` escapes of a path segment
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escape = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Reads the JSON object of a request body, an empty body being an empty object
+fn read_body(request: &mut Request) -> std::result::Result<Map<String, Value>, String> {
+    let mut body = String::new();
+
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|error| error.to_string())?;
+
+    if body.trim().is_empty() {
+        return Ok(Map::new());
+    }
+
+    serde_json::from_str(&body).map_err(|error| format!("Invalid request body: {error}"))
+}
+
+/// Runs a step on the thread of a session
+fn run_step(sessions: &Sessions, id: &str, op: &str, params: Map<String, Value>) -> (u16, String) {
+    let step = match Step::from_params(op, params) {
+        Ok(step) => step,
+        Err(error) => return invalid(400, format!("Invalid request body: {error}")),
+    };
+    let Some(session) = sessions.lock().unwrap().get(id).cloned() else {
+        return invalid(404, format!("Unknown session '{id}'"));
+    };
+    let (reply, outcome) = mpsc::channel();
+
+    if session.send((step, reply)).is_err() {
+        return invalid(404, format!("Session '{id}' has been closed"));
+    }
+
+    match outcome.recv() {
+        Ok(Ok(result)) => success(200, result),
+        Ok(Err((status, report))) => {
+            let error = HttpError {
+                ok: false,
+                error: report,
+            };
+
+            (
+                status_code(status),
+                serde_json::to_string(&error).expect("Failed to serialize the error"),
+            )
+        }
+        Err(_) => invalid(500, format!("Session '{id}' ended during the request")),
+    }
+}
+
+fn route(sessions: &Sessions, options: &RunOptions, request: &mut Request) -> (u16, String) {
+    let url = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let segments: Vec<String> = url
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+    // An unsubscription names its interface in the body, while other deletions have
+    // none, which reads as `{}`
+    let mut body = match method {
+        Method::Post | Method::Put | Method::Delete => match read_body(request) {
+            Ok(body) => body,
+            Err(error) => return invalid(400, error),
+        },
+        _ => Map::new(),
+    };
+
+    match (method, &segments[..]) {
+        (Method::Post, ["sessions"]) => match new_session_id() {
+            Ok(id) => {
+                let session = open_session(options.clone());
+
+                sessions.lock().unwrap().insert(id.clone(), session);
+                success(201, Value::String(id))
+            }
+            Err(error) => invalid(500, error.to_string()),
+        },
+        (Method::Delete, ["sessions", id]) => match sessions.lock().unwrap().remove(*id) {
+            Some(_) => success(200, Value::Null),
+            None => invalid(404, format!("Unknown session '{id}'")),
+        },
+        (Method::Post, ["sessions", id, "objects"]) => run_step(sessions, id, "create", body),
+        (Method::Delete, ["sessions", id, "objects", object]) => {
+            body.insert("objects".to_string(), Value::from(vec![*object]));
+            run_step(sessions, id, "release", body)
+        }
+        (Method::Post, ["sessions", id, "objects", object, "invoke"]) => {
+            body.insert("object".to_string(), Value::from(*object));
+            run_step(sessions, id, "call", body)
+        }
+        (Method::Get, ["sessions", id, "objects", object, "properties", property]) => {
+            body.insert("object".to_string(), Value::from(*object));
+            body.insert("property".to_string(), Value::from(*property));
+            run_step(sessions, id, "get", body)
+        }
+        (Method::Put, ["sessions", id, "objects", object, "properties", property]) => {
+            body.insert("object".to_string(), Value::from(*object));
+            body.insert("property".to_string(), Value::from(*property));
+            run_step(sessions, id, "set", body)
+        }
+        (Method::Post, ["sessions", id, "objects", object, "subscriptions"]) => {
+            body.insert("object".to_string(), Value::from(*object));
+            run_step(sessions, id, "subscribe", body)
+        }
+        (Method::Delete, ["sessions", id, "objects", object, "subscriptions"]) => {
+            body.insert("object".to_string(), Value::from(*object));
+            run_step(sessions, id, "unsubscribe", body)
+        }
+        _ => invalid(404, format!("No route for {} {url}", request.method())),
+    }
+}
+
+/// Answers HTTP requests on an address such as `127.0.0.1:8080` until the process is
+/// ended. There is no authentication, so the address should be a loopback one.
+pub fn listen(address: &str, options: &RunOptions) -> io::Result<()> {
+    let server = Server::http(address).map_err(io::Error::other)?;
+    let sessions = Sessions::default();
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("The Content-Type header is valid");
+
+    for mut request in server.incoming_requests() {
+        let sessions = Arc::clone(&sessions);
+        let options = options.clone();
+        let content_type = content_type.clone();
+
+        // Requests of different sessions must not wait for each other
+        thread::spawn(move || {
+            let (status, body) = route(&sessions, &options, &mut request);
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type);
+
+            let _ = request.respond(response);
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Ctrl+C, Ctrl+Break and the closing of the console, which stop the requests running
+//! at their next step, cancelling the pending call to an out-of-process server, so that
+//! their cleanup steps run and their objects are released and COM uninitialized before
+//! the process ends. Otherwise the application automated is left in a locked state,
+//! such as Excel holding a workbook open for a process that no longer exists.
+
+use crate::error::ComError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use tracing::warn;
+use windows::Win32::Foundation::STATUS_CONTROL_C_EXIT;
+use windows::Win32::System::Com::CoCancelCall;
+use windows::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT, SetConsoleCtrlHandler};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::core::{BOOL, Error, HRESULT};
+
+/// How long the closing of the console waits for the requests to clean up, within the
+/// 5 seconds Windows gives before ending the process anyway
+const CLOSE_GRACE: Duration = Duration::from_millis(4500);
+
+/// The code of the steps an interruption stopped, which Windows also ends processes
+/// interrupted by Ctrl+C with
+pub(crate) const INTERRUPTION: HRESULT = HRESULT(STATUS_CONTROL_C_EXIT.0);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The threads running the steps of a request
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Notified whenever a request is done running
+static DONE: Condvar = Condvar::new();
+
+/// Stops the requests at their next step on Ctrl+C, Ctrl+Break or the closing of the
+/// console, and cleans up after them before the process ends. Interrupting again, or
+/// while no request is running, ends the process at once as usual.
+pub fn handle_interrupts() -> windows::core::Result<()> {
+    unsafe { SetConsoleCtrlHandler(Some(handler), true) }
+}
+
+unsafe extern "system" fn handler(event: u32) -> BOOL {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        return false.into();
+    }
+
+    let running = RUNNING.lock().unwrap_or_else(|error| error.into_inner());
+
+    if running.is_empty() {
+        return false.into();
+    }
+
+    warn!("Interrupted, stopping the request");
+
+    // In-process calls cannot be cancelled, and keep the request running until they
+    // return
+    for thread in running.iter() {
+        unsafe {
+            let _ = CoCancelCall(*thread, 0);
+        }
+    }
+
+    // The process ends once the handler of the close returns, so it waits for the
+    // requests to clean up first
+    if event != CTRL_C_EVENT && event != CTRL_BREAK_EVENT {
+        let _ = DONE.wait_timeout_while(running, CLOSE_GRACE, |running| !running.is_empty());
+    }
+
+    true.into()
+}
+
+/// Whether the process was interrupted
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The error of the steps an interruption stopped
+pub(crate) fn interrupted_error() -> ComError {
+    Error::new(
+        INTERRUPTION,
+        "Interrupted by Ctrl+C or the closing of the console",
+    )
+    .into()
+}
+
+/// Registers the current thread as running a request, until dropped
+pub(crate) struct Running {
+    thread: u32,
+}
+
+impl Running {
+    pub(crate) fn enter() -> Self {
+        let thread = unsafe { GetCurrentThreadId() };
+
+        RUNNING
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(thread);
+
+        Self { thread }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap_or_else(|error| error.into_inner());
+
+        if let Some(index) = running.iter().position(|thread| *thread == self.thread) {
+            running.swap_remove(index);
+        }
+
+        DONE.notify_all();
+    }
+}
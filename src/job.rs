@@ -0,0 +1,185 @@
+//! Job object the local servers started by activations are assigned to, so that they
+//! end with the process instead of piling up when it exits, crashes or is killed
+//! before releasing them, as automation processes such as EXCEL.EXE otherwise do.
+//!
+//! COM starts local servers itself, so their process is told from the IPID of the
+//! object's marshaled reference, which carries the process ID of its server, among the
+//! processes that appeared while activating. Servers that were running before are
+//! left alone, since the process did not start them.
+
+use crate::error::Result;
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HGLOBAL};
+use windows::Win32::System::Com::Marshal::{CoMarshalInterface, CoReleaseMarshalData};
+use windows::Win32::System::Com::StructuredStorage::{CreateStreamOnHGlobal, GetHGlobalFromStream};
+use windows::Win32::System::Com::{IDispatch, MSHCTX_INPROC, MSHLFLAGS_NORMAL, STREAM_SEEK_SET};
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, PROCESSENTRY32W, Process32FirstW, Process32NextW, TH32CS_SNAPPROCESS,
+};
+use windows::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+    SetInformationJobObject,
+};
+use windows::Win32::System::Memory::{GlobalLock, GlobalSize, GlobalUnlock};
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE};
+use windows::core::{IUnknown, Interface, PCWSTR};
+
+/// `MEOW`, the signature of marshaled object references
+const OBJREF_SIGNATURE: u32 = 0x574f_454d;
+
+/// Offset of the process ID within the IPID of a standard object reference
+const IPID_PROCESS_OFFSET: usize = 52;
+
+/// The process ID IPIDs carry for processes whose ID does not fit in 16 bits
+const TRUNCATED_PROCESS_ID: u16 = 0xFFFF;
+
+/// The job, never closed until the process ends, which terminates its processes
+struct Job(HANDLE);
+
+// The handle of a job may be used from any thread
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+static JOB: OnceLock<Job> = OnceLock::new();
+
+/// Terminates the local servers activated from now on, by any session, once this
+/// process ends, however it ends
+pub fn kill_servers_on_exit() -> Result<()> {
+    if JOB.get().is_some() {
+        return Ok(());
+    }
+
+    unsafe {
+        let job = CreateJobObjectW(None, PCWSTR::null())?;
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &limits as *const _ as *const c_void,
+            size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )?;
+
+        if JOB.set(Job(job)).is_err() {
+            let _ = CloseHandle(job);
+        }
+    }
+
+    Ok(())
+}
+
+/// The IDs of the processes running, when activated servers are assigned to the job
+pub(crate) fn running_processes() -> Option<HashSet<u32>> {
+    JOB.get()?;
+
+    let mut processes = HashSet::new();
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0).ok()?;
+        let mut entry = PROCESSENTRY32W {
+            dwSize: size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut found = Process32FirstW(snapshot, &mut entry);
+
+        while found.is_ok() {
+            processes.insert(entry.th32ProcessID);
+            found = Process32NextW(snapshot, &mut entry);
+        }
+
+        let _ = CloseHandle(snapshot);
+    }
+
+    Some(processes)
+}
+
+/// The process ID, possibly truncated, the IPID of a reference to `object` carries
+unsafe fn marshaled_process_id(object: &IDispatch) -> Result<Option<u16>> {
+    unsafe {
+        let stream = CreateStreamOnHGlobal(HGLOBAL::default(), true)?;
+        let unknown: IUnknown = object.cast()?;
+
+        CoMarshalInterface(
+            &stream,
+            &IUnknown::IID,
+            &unknown,
+            MSHCTX_INPROC.0 as u32,
+            None,
+            MSHLFLAGS_NORMAL.0 as u32,
+        )?;
+
+        let memory = GetHGlobalFromStream(&stream)?;
+        let data = GlobalLock(memory) as *const u8;
+        let reference = std::slice::from_raw_parts(data, GlobalSize(memory));
+        let word = |offset: usize| {
+            reference
+                .get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+        };
+        // Standard and handler references carry an IPID, custom ones do not
+        let process_id = match (word(0), word(4)) {
+            (Some(OBJREF_SIGNATURE), Some(flags)) if flags & 0b11 != 0 => reference
+                .get(IPID_PROCESS_OFFSET..IPID_PROCESS_OFFSET + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])),
+            _ => None,
+        };
+
+        let _ = GlobalUnlock(memory);
+
+        // The reference is only read, not handed to anyone
+        stream.Seek(0, STREAM_SEEK_SET, None)?;
+        CoReleaseMarshalData(&stream)?;
+
+        Ok(process_id)
+    }
+}
+
+/// Assigns the server of an object just activated to the job, provided its process is
+/// one of those that were not running before the activation
+pub(crate) unsafe fn contain(object: &IDispatch, before: &HashSet<u32>) {
+    let Some(after) = running_processes() else {
+        return;
+    };
+    let started: Vec<u32> = after.difference(before).copied().collect();
+
+    if started.is_empty() {
+        return;
+    }
+
+    let process_id = match unsafe { marshaled_process_id(object) } {
+        Ok(Some(process_id)) => process_id,
+        Ok(None) => return,
+        Err(error) => {
+            debug!("Failed to tell the process of the server: {error}");
+            return;
+        }
+    };
+    let mut servers = started.into_iter().filter(|started| match process_id {
+        TRUNCATED_PROCESS_ID => *started >= u32::from(TRUNCATED_PROCESS_ID),
+        process_id => *started == u32::from(process_id),
+    });
+    // A truncated ID may match several processes, none of which is then assigned
+    let (Some(server), None, Some(job)) = (servers.next(), servers.next(), JOB.get()) else {
+        return;
+    };
+
+    unsafe {
+        let assigned =
+            OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, false, server).and_then(|process| {
+                let assigned = AssignProcessToJobObject(job.0, process);
+
+                let _ = CloseHandle(process);
+                assigned
+            });
+
+        match assigned {
+            Ok(()) => debug!("Assigned the server process {server} to the job"),
+            Err(error) => warn!("Failed to assign the server process {server} to the job: {error}"),
+        }
+    }
+}
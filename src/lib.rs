@@ -0,0 +1,42 @@
+//! Automation of Win32 COM objects through IDispatch, with arguments, results and
+//! errors expressed as JSON values.
+//!
+//! [`ComSession`] and [`ComObject`] drive objects directly, while [`request`] runs the
+//! requests the CLI accepts.
+
+pub mod activation;
+mod com;
+mod date;
+mod dispatch;
+mod enums;
+pub mod error;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod hresult;
+mod interrupt;
+mod job;
+mod message_filter;
+mod numeric;
+mod objects;
+mod persist;
+pub mod request;
+pub mod session;
+mod template;
+pub mod typeinfo;
+mod validate;
+mod variant;
+mod watchdog;
+mod webhook;
+
+pub use com::{ComObject, ComSession};
+pub use dispatch::InvokeKind;
+pub use error::{ComError, Result};
+pub use events::set_event_handler;
+pub use hresult::KnownHresult;
+pub use interrupt::{handle_interrupts, interrupted};
+pub use job::kill_servers_on_exit;
+pub use message_filter::RetryPolicy;
+pub use objects::ObjectTable;
+pub use variant::{value_to_variant, variant_to_value};
+pub use watchdog::set_abort_handler;
@@ -1,248 +1,555 @@
-use serde::{Deserialize, Serialize};
+mod audit;
+mod browse;
+mod cli;
+mod codegen;
+mod completions;
+mod describe;
+mod etw;
+mod event_log;
+mod format;
+mod http;
+mod pipe;
+mod registry;
+mod rot;
+mod serve;
+mod tcp;
+mod websocket;
+mod worker;
+
+use audit::AuditLog;
+use browse::Browser;
+use cli::{Cli, Command, RotCommand, RunArgs};
+use event_log::EventLog;
+use format::Format;
+use serde::Serialize;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::io::{self, Read};
-use windows::Win32::System::Ole::DISPID_PROPERTYPUT;
-use windows::Win32::System::Variant::{VARIANT, VariantToString};
-use windows::{Win32::System::Com::*, core::*};
-
-#[derive(Serialize, Deserialize)]
-struct ComMethodCall {
-    version: String,
-    prog_id: String,
-    method: String,
-    properties: HashMap<String, Value>,
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Cursor, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use tracing::{Level, error, warn};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::time::uptime;
+use tracing_subscriber::prelude::*;
+use win32_com_cli::error::ExitStatus;
+use win32_com_cli::request::{
+    Output, SCHEMA, expand_environment, expand_parameters, include_steps, invalid_request,
+    run_request, validate_request,
+};
+use win32_com_cli::session::RunOptions;
+use win32_com_cli::{
+    handle_interrupts, interrupted, kill_servers_on_exit, set_abort_handler, set_event_handler,
+};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Where the results and other documents of the CLI are written, and how
+struct Sink {
+    format: Format,
+    /// `--pretty` or `--compact`, when given
+    pretty: Option<bool>,
+    out: Box<dyn Write>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ComMethodCallResult {
-    version: String,
-    prog_id: String,
-    method: String,
-    result: HashMap<String, Value>,
-}
+impl Sink {
+    /// Opens the file of `--output`, appended to with `--append`, or stdout
+    fn open(cli: &Cli) -> io::Result<Self> {
+        Self::new(cli.format, cli.pretty(), cli.output.as_deref(), cli.append)
+    }
 
-#[derive(Serialize, Deserialize)]
-struct ComMethodCallError {
-    version: String,
-    prog_id: String,
-    method: String,
-    error: String,
-}
+    fn new(
+        format: Format,
+        pretty: Option<bool>,
+        output: Option<&Path>,
+        append: bool,
+    ) -> io::Result<Self> {
+        let out: Box<dyn Write> = match output {
+            Some(path) => Box::new(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)?,
+            )),
+            None => Box::new(io::stdout()),
+        };
 
-fn to_pcwstr(s: &str) -> PCWSTR {
-    let wide: Vec<u16> = s.encode_utf16().chain(std::iter::once(0)).collect();
-    PCWSTR::from_raw(wide.as_ptr())
-}
+        Ok(Self {
+            format,
+            pretty,
+            out,
+        })
+    }
 
-unsafe fn value_to_variant(value: &Value) -> VARIANT {
-    match value {
-        Value::String(s) => VARIANT::from(BSTR::from(s.as_str())),
-        Value::Number(n) => {
-            // Prioritize integer conversion if possible
-            if n.is_i64() {
-                // Assuming i32 is sufficient for integer properties.
-                // If larger integers are expected, consider VT_I8 or custom handling.
-                n.as_i64()
-                    .map_or(VARIANT::default(), |i| VARIANT::from(i as i32))
-            } else if n.is_f64() {
-                // Handle floating-point numbers
-                n.as_f64().map_or(VARIANT::default(), |f| VARIANT::from(f))
-            } else {
-                // Fallback for numbers that don't fit i64 or f64 (e.g., very large BigInts)
-                eprintln!(
-                    "Warning: Unsupported number type in JSON, defaulting to empty VARIANT. \
-                    Value: {n}"
-                );
-                VARIANT::default()
-            }
-        }
-        Value::Bool(b) => VARIANT::from(*b),
-        Value::Null => {
-            eprintln!("Warning: Unable to set NULL as a VARIANT");
-            VARIANT::default()
+    /// Writes a result document, compact unless `--pretty` is given
+    fn emit(&mut self, output: &Value) -> io::Result<()> {
+        if self.pretty == Some(true) {
+            self.format.write_pretty(&mut self.out, output)
+        } else {
+            self.format.write(&mut self.out, output)
         }
-        Value::Array(_) => {
-            eprintln!(
-                "Warning: JSON Array type is not directly supported for simple VARIANT conversion \
-                for property setting. Defaulting to empty VARIANT."
-            );
-            VARIANT::default()
-        }
-        Value::Object(_) => {
-            eprintln!(
-                "Warning: JSON Object type is not directly supported for simple VARIANT conversion \
-                for property setting. Defaulting to empty VARIANT."
-            );
-            VARIANT::default()
+    }
+
+    /// Writes the text of a subcommand, reporting whether it could be
+    fn print(&mut self, text: &str) -> ExitStatus {
+        match self
+            .out
+            .write_all(text.as_bytes())
+            .and_then(|()| self.out.flush())
+        {
+            Ok(()) => ExitStatus::Success,
+            Err(error) => {
+                eprintln!("Error: Failed to write the output: {error}");
+                ExitStatus::Failure
+            }
         }
     }
-}
 
-unsafe fn set_property(obj: &IDispatch, name: &str, value: &Value) -> Result<()> {
-    let wide_name = to_pcwstr(name);
-    let mut dispatch_id = Default::default();
+    /// Writes a JSON document meant to be read by people, pretty unless `--compact` is
+    /// given
+    fn print_json(&mut self, document: &impl Serialize) -> ExitStatus {
+        let text = if self.pretty == Some(false) {
+            serde_json::to_string(document)
+        } else {
+            serde_json::to_string_pretty(document)
+        };
 
-    // Get the DISPID for the property name
-    unsafe {
-        obj.GetIDsOfNames(&Default::default(), &wide_name, 1, 0, &mut dispatch_id)?;
+        self.print(&format!(
+            "{}\n",
+            text.expect("Failed to serialize the output")
+        ))
     }
 
-    let mut dispid_put = DISPID_PROPERTYPUT; // Special DISPID for property put operations
-
-    // Convert serde_json::Value to VARIANT
-    unsafe {
-        let mut variant_value = value_to_variant(value);
+    /// Writes the result of a request that could not be read and returns its status
+    fn reject(&mut self, message: String) -> ExitStatus {
+        let (output, status) = invalid_request(None, message);
 
-        // Prepare DISPPARAMS for setting a property
-        let params = DISPPARAMS {
-            rgvarg: &mut variant_value,         // The value to set
-            rgdispidNamedArgs: &mut dispid_put, // Indicates this is a property put
-            cArgs: 1,                           // One argument (the value)
-            cNamedArgs: 1,                      // One named argument (DISPID_PROPERTYPUT)
-        };
+        if let Err(error) = self.emit(&output) {
+            eprintln!("Error: Failed to write the result: {error}");
+        }
 
-        // Invoke the property put operation
-        obj.Invoke(
-            dispatch_id,          // DISPID of the property
-            &GUID::zeroed(),      // Reserved, must be IID_NULL for Invoke
-            0,                    // Locale ID (LOCALE_USER_DEFAULT)
-            DISPATCH_PROPERTYPUT, // Flag indicating a property put
-            &params,              // Parameters for the invocation
-            None,                 // No return value expected for property put
-            None,                 // No exception info needed
-            None,                 // No argument error info needed
-        )?;
+        status
     }
-
-    Ok(())
 }
 
-unsafe fn get_property(obj: &IDispatch, name: &str) -> Result<String> {
-    let wide_name = to_pcwstr(name);
-    let mut dispatch_id = Default::default();
+/// Reads requests as a stream of documents, typically one JSON document per line, from
+/// a file, from `--inline` JSON or from stdin. One result is written per request as
+/// soon as `handle` has processed it, and the exit status is that of the first request
+/// that failed.
+fn run(sink: &mut Sink, args: &RunArgs, handle: &dyn Fn(Value) -> Output) -> ExitStatus {
+    let format = sink.format;
+    // The whole text of files and inline requests is kept to quote parse errors from
+    let (requests, source, input_format, text) = match (&args.file, &args.inline) {
+        (Some(path), _) => match fs::read(path) {
+            Ok(mut bytes) => {
+                // Editors on Windows like to start UTF-8 files with a byte order mark
+                if format == Format::Json && bytes.starts_with(UTF8_BOM) {
+                    bytes.drain(..UTF8_BOM.len());
+                }
+
+                let text = String::from_utf8(bytes.clone()).ok();
+
+                (
+                    format.documents(Cursor::new(bytes)),
+                    path.display().to_string(),
+                    format,
+                    text,
+                )
+            }
+            Err(error) => {
+                return sink.reject(format!("Failed to read {}: {error}", path.display()));
+            }
+        },
+        // Inline requests are JSON whatever the format of the results
+        (None, Some(json)) => (
+            Format::Json.documents(Cursor::new(json.clone().into_bytes())),
+            "--inline".to_string(),
+            Format::Json,
+            Some(json.clone()),
+        ),
+        (None, None) => (
+            format.documents(io::stdin().lock()),
+            "stdin".to_string(),
+            format,
+            None,
+        ),
+    };
+    let preposition = if source == "stdin" { "on" } else { "in" };
+    // Included files are found next to the file including them
+    let directory = args
+        .file
+        .as_deref()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""));
+    let mut handled = 0;
+    let mut status = ExitStatus::Success;
+
+    for request in requests {
+        let parsed = request.is_ok();
+        let (output, request_status) = match request {
+            Ok(mut request) => match include_steps(&mut request, directory) {
+                Ok(()) => handle(request),
+                Err(message) => {
+                    invalid_request(request["version"].as_str().map(str::to_string), message)
+                }
+            },
+            Err(error) => {
+                let message = format!(
+                    "Failed to parse request {input_format} {preposition} {source}: {error}"
+                );
+                let excerpt = error
+                    .position
+                    .zip(text.as_deref())
+                    .and_then(|(position, text)| format::excerpt(text, position));
 
-    unsafe {
-        obj.GetIDsOfNames(&Default::default(), &wide_name, 1, 0, &mut dispatch_id)?;
-    }
+                if let Some(excerpt) = excerpt {
+                    eprintln!("Error: {message}\n{excerpt}");
+                }
 
-    let params = DISPPARAMS::default();
-    let mut result = VARIANT::default();
+                invalid_request(None, message)
+            }
+        };
 
-    unsafe {
-        obj.Invoke(
-            dispatch_id,
-            &GUID::zeroed(), // Reserved, must be IID_NULL
-            0,               // Use system default locale
-            DISPATCH_PROPERTYGET,
-            &params,
-            Some(&mut result),
-            None,
-            None,
-        )?;
-    }
+        if let Err(error) = sink.emit(&output) {
+            eprintln!("Error: Failed to write the result: {error}");
+            return ExitStatus::Failure;
+        }
 
-    let bstr_val = BSTR::default();
+        handled += 1;
+
+        if status == ExitStatus::Success {
+            status = request_status;
+        }
 
-    unsafe {
-        VariantToString(&result, &mut bstr_val.to_vec())?;
+        // The requests after an interrupted one are not run
+        if interrupted() {
+            break;
+        }
+
+        // Past a malformed document the stream cannot be resynchronized
+        if !parsed {
+            break;
+        }
     }
 
-    Ok(bstr_val.to_string())
+    if handled == 0 {
+        status = sink.reject(format!("No request {preposition} {source}"));
+    }
+
+    status
 }
 
-unsafe fn call_method(
-    obj: &IDispatch,
-    name: String,
-    properties: HashMap<String, Value>,
-) -> Result<()> {
-    for (prop_name, prop_value) in properties {
-        println!("Setting property: {prop_name} = {prop_value:?}");
+/// Runs the requests read, or plans them with `validate`, in worker processes with
+/// `--isolate`, once the placeholders the command line asks for are filled in
+fn prepared(cli: &Cli, validate: bool) -> impl Fn(Value) -> Output + '_ {
+    let handle = match validate {
+        false => run_request,
+        true => validate_request,
+    };
+
+    move |mut request| {
+        let version = request["version"].as_str().map(str::to_string);
 
-        unsafe {
-            set_property(obj, &prop_name, &prop_value)?;
+        if let Err(message) = expand_parameters(&mut request, &cli.param) {
+            return invalid_request(version, message);
+        }
+
+        if cli.expand_env
+            && let Err(message) = expand_environment(&mut request)
+        {
+            return invalid_request(version, message);
         }
-    }
 
-    let wide_name = to_pcwstr(name.as_str());
-    let mut dispatch_id = Default::default();
+        if cli.strict
+            && let Some(request) = request.as_object_mut()
+        {
+            request.insert("strict".to_string(), Value::Bool(true));
+        }
 
-    // Get the DISPID for the method name
-    unsafe {
-        obj.GetIDsOfNames(&Default::default(), &wide_name, 1, 0, &mut dispatch_id)?;
+        match cli.isolate {
+            false => handle(request),
+            true => worker::run_request(request, validate, cli.format),
+        }
     }
+}
 
-    let mut variant_result = VARIANT::default(); // For potential return value of the method
-    let params = DISPPARAMS {
-        rgvarg: &mut variant_result, // If the method returns a value, it would be stored here.
-        cArgs: 0,                    // No arguments passed to the method itself
-        ..Default::default()
+/// Serves clients connecting to a listen address such as `pipe:\\.\pipe\win32-com-cli`,
+/// `tcp:127.0.0.1:7861`, `http:127.0.0.1:8080` or `ws:127.0.0.1:7862`
+fn listen(address: &str, options: &RunOptions) -> ExitStatus {
+    let result = match address.split_once(':') {
+        Some(("pipe", name)) => pipe::listen(name, options).map_err(io::Error::from),
+        Some(("tcp", address)) => tcp::listen(address, options),
+        Some(("http", address)) => http::listen(address, options),
+        Some(("ws", address)) => websocket::listen(address, options),
+        _ => {
+            eprintln!(
+                "Unsupported listen address '{address}', expected pipe:<name>, \
+                tcp:<host>:<port>, http:<host>:<port> or ws:<host>:<port>"
+            );
+            return ExitStatus::InvalidRequest;
+        }
     };
 
-    eprintln!("Calling method: {name}");
-
-    unsafe {
-        obj.Invoke(
-            dispatch_id,     // DISPID of the method
-            &GUID::zeroed(), // Reserved, must be IID_NULL for Invoke
-            0,               // Locale ID (LOCALE_USER_DEFAULT)
-            DISPATCH_METHOD, // Flag indicating a method call
-            &params,         // Parameters for the invocation
-            None, // No return value needed to be captured here (already in pVarResult if provided)
-            None, // No exception info needed
-            None, // No argument error info needed
-        )?;
+    match result {
+        Ok(()) => ExitStatus::Success,
+        Err(error) => {
+            eprintln!("Error: Failed to listen on {address}: {error}");
+            ExitStatus::Failure
+        }
     }
+}
 
-    Ok(())
+/// Prints the members of an object as JSON
+fn describe(sink: &mut Sink, target: &str) -> ExitStatus {
+    match describe::describe(target) {
+        Ok(description) => sink.print_json(&description),
+        Err(error) => {
+            eprintln!("Error: Failed to describe {target}: {error}");
+            error.exit_status()
+        }
+    }
 }
 
-fn get_data_from_stdio() -> String {
-    let mut buffer = String::new();
-    io::stdin()
-        .read_to_string(&mut buffer)
-        .expect("Failed to read from stdin");
+/// Browses the object of a ProgID or CLSID in the terminal
+fn browse(target: &str) -> ExitStatus {
+    let browser = match Browser::open(target) {
+        Ok(browser) => browser,
+        Err(error) => {
+            eprintln!("Error: Failed to browse {target}: {error}");
+            return error.exit_status();
+        }
+    };
 
-    buffer
+    match browser.run() {
+        Ok(()) => ExitStatus::Success,
+        Err(error) => {
+            eprintln!("Error: Failed to drive the terminal: {error}");
+            ExitStatus::Failure
+        }
+    }
 }
-fn get_call_params_from_json_buffer(buffer: String) -> ComMethodCall {
-    let com_method_call: ComMethodCall =
-        serde_json::from_str(&buffer).expect("Failed to deserialize ComMethodCall JSON");
 
-    com_method_call
+/// Prints the wrapper module of a type library
+fn codegen(sink: &mut Sink, target: &str) -> ExitStatus {
+    match codegen::read_library(target) {
+        Ok(library) => sink.print(&codegen::generate(&library)),
+        Err(error) => {
+            eprintln!("Error: Failed to read the type library of {target}: {error}");
+            error.exit_status()
+        }
+    }
 }
 
-fn call_com_method(params: ComMethodCall) -> Result<String> {
-    unsafe {
-        let _ = CoInitialize(None);
-        let prog_id = to_pcwstr(params.prog_id.as_str());
-        let clsid = CLSIDFromProgID(prog_id)?;
-        let obj: IDispatch = CoCreateInstance(&clsid, None, CLSCTX_ALL)?;
+/// Prints the registered ProgIDs as JSON
+fn list_prog_ids(sink: &mut Sink, filter: Option<&str>) -> ExitStatus {
+    sink.print_json(&registry::list_prog_ids(filter))
+}
 
-        call_method(&obj, params.method, params.properties)?;
+/// Prints the registration of a class as JSON
+fn resolve(sink: &mut Sink, target: &str) -> ExitStatus {
+    let Some(registration) = registry::resolve(target) else {
+        eprintln!("Error: {target} is not registered");
+        return ExitStatus::ClassNotRegistered;
+    };
 
-        let error_code = get_property(&obj, "ErrorCode")?;
+    sink.print_json(&registration)
+}
 
-        eprintln!("Error Code: {error_code}");
-        CoUninitialize();
+/// Prints the entries of the Running Object Table, or the members of one of its objects
+fn running_objects(sink: &mut Sink, command: &RotCommand) -> ExitStatus {
+    match command {
+        RotCommand::List => match rot::list() {
+            Ok(objects) => sink.print_json(&objects),
+            Err(error) => {
+                eprintln!("Error: Failed to read the Running Object Table: {error}");
+                error.exit_status()
+            }
+        },
+        RotCommand::Get { display_name } => match rot::get(display_name) {
+            Ok(description) => sink.print_json(&description),
+            Err(error) => {
+                eprintln!("Error: Failed to bind to {display_name}: {error}");
+                error.exit_status()
+            }
+        },
     }
+}
 
-    Ok("{}".to_string())
+/// Prints whether the chain of an audit log is intact, failing when it is not
+fn verify_audit_log(sink: &mut Sink, path: &Path) -> ExitStatus {
+    match audit::verify(path) {
+        Ok(report) if report["ok"] == true => sink.print_json(&report),
+        Ok(report) => {
+            sink.print_json(&report);
+            ExitStatus::Failure
+        }
+        Err(error) => {
+            eprintln!("Error: Failed to read {}: {error}", path.display());
+            ExitStatus::Failure
+        }
+    }
 }
 
-fn main() -> Result<()> {
-    let buffer = get_data_from_stdio();
-    let params = get_call_params_from_json_buffer(buffer);
-    let result = call_com_method(params);
+fn register_event_source(remove: bool) -> ExitStatus {
+    let (result, action) = match remove {
+        false => (event_log::register(), "register"),
+        true => (event_log::unregister(), "unregister"),
+    };
 
     match result {
-        Ok(message) => {
-            println!("{message}");
+        Ok(()) => ExitStatus::Success,
+        Err(error) => {
+            eprintln!("Error: Failed to {action} the event source: {error}");
+            ExitStatus::Failure
         }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_args();
+
+    let event_log = match cli.event_log.then(EventLog::open).transpose() {
+        Ok(event_log) => event_log,
         Err(error) => {
-            eprintln!("{error}");
+            eprintln!("Error: Failed to open the event log: {error}");
+            return ExitCode::from(ExitStatus::Failure as u8);
         }
+    };
+
+    let audit_log = match cli.audit_log.as_deref().map(AuditLog::open).transpose() {
+        Ok(audit_log) => audit_log,
+        Err(error) => {
+            let path = cli.audit_log.as_deref().unwrap_or(Path::new("")).display();
+
+            eprintln!("Error: Failed to open {path}: {error}");
+            return ExitCode::from(ExitStatus::Failure as u8);
+        }
+    };
+
+    etw::register();
+
+    // Messages are stamped with the time since the start, and spans report the time
+    // they took when they close. The event log, ETW and the audit log see what they
+    // report whatever the verbosity.
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_ansi(io::stderr().is_terminal())
+                .with_target(false)
+                .with_timer(uptime())
+                .with_span_events(FmtSpan::CLOSE)
+                .with_filter(cli.log_filter()),
+        )
+        .with(event_log.map(|event_log| {
+            event_log.with_filter(filter_fn(|metadata| {
+                metadata.name() == "step" || *metadata.level() == Level::ERROR
+            }))
+        }))
+        .with(etw::layer())
+        .with(audit_log.map(AuditLog::layer))
+        .init();
+
+    // Panics within steps fail them with E_UNEXPECTED, and are logged like the other
+    // errors instead of being printed bare
+    std::panic::set_hook(Box::new(|info| error!("{info}")));
+
+    let mut sink = match Sink::open(&cli) {
+        Ok(sink) => sink,
+        Err(error) => {
+            let path = cli.output.as_deref().unwrap_or(Path::new("")).display();
+
+            eprintln!("Error: Failed to open {path}: {error}");
+            return ExitCode::from(ExitStatus::Failure as u8);
+        }
+    };
+    let sink = &mut sink;
+
+    if cli.isolate {
+        worker::isolate(cli.worker_arguments());
+    }
+
+    if cli.kill_servers_on_exit
+        && let Err(error) = kill_servers_on_exit()
+    {
+        eprintln!("Error: Failed to create the job of the servers: {error}");
+        return ExitCode::from(ExitStatus::Failure as u8);
     }
 
-    Ok(())
+    // Workers reply on stdout in their own way
+    let requests = !cli.serve && cli.listen.is_none() && !cli.worker;
+
+    // A request whose call could not be cancelled still gets its result, written
+    // after those of the requests before it
+    if requests {
+        let (format, pretty, output) = (cli.format, cli.pretty(), cli.output.clone());
+
+        set_abort_handler(move |result| {
+            if let Ok(mut sink) = Sink::new(format, pretty, output.as_deref(), true) {
+                let _ = sink.emit(result);
+            }
+        });
+    }
+    // Requests clean up after themselves when interrupted
+    if requests && let Err(error) = handle_interrupts() {
+        warn!("Failed to handle Ctrl+C: {error}");
+    }
+
+    // Events are written to stdout as they are reported, even with --output
+    if requests {
+        let format = cli.format;
+
+        set_event_handler(move |event| {
+            let _ = format.write(&mut io::stdout().lock(), event);
+        });
+    }
+
+    // The options of the sessions of --serve and --listen
+    let options = RunOptions {
+        strict: cli.strict,
+        ..RunOptions::default()
+    };
+    let status = match &cli.command {
+        Some(Command::Run(args)) => run(sink, args, &prepared(&cli, args.validate)),
+        Some(Command::Call(args)) => {
+            let (output, status) = prepared(&cli, false)(args.request());
+
+            if let Err(error) = sink.emit(&output) {
+                eprintln!("Error: Failed to write the result: {error}");
+                ExitStatus::Failure
+            } else {
+                status
+            }
+        }
+        Some(Command::Describe { target }) => describe(sink, target),
+        Some(Command::Browse { target }) => browse(target),
+        Some(Command::Codegen { target }) => codegen(sink, target),
+        Some(Command::ListProgids { filter }) => list_prog_ids(sink, filter.as_deref()),
+        Some(Command::Resolve { target }) => resolve(sink, target),
+        Some(Command::Rot { command }) => running_objects(sink, command),
+        Some(Command::VerifyAuditLog { path }) => verify_audit_log(sink, path),
+        Some(Command::RegisterEventSource { remove }) => register_event_source(*remove),
+        Some(Command::Completions { shell }) => sink.print(&completions::generate(*shell)),
+        // The servers answer on stdout, which --output cannot be combined with
+        None if cli.serve => {
+            serve::run(&options, cli.format);
+            ExitStatus::Success
+        }
+        // The schema is kept in the order it is written in unless it has to be compacted
+        None if cli.emit_schema && cli.compact => {
+            let schema: Value = serde_json::from_str(SCHEMA).expect("The schema is JSON");
+
+            sink.print_json(&schema)
+        }
+        None if cli.emit_schema => sink.print(SCHEMA),
+        None if cli.worker => worker::serve(),
+        None => match &cli.listen {
+            Some(address) => listen(address, &options),
+            None => run(sink, &RunArgs::default(), &prepared(&cli, cli.validate)),
+        },
+    };
+
+    ExitCode::from(status as u8)
 }
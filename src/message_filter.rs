@@ -0,0 +1,84 @@
+//! Message filter that retries calls rejected by busy servers, such as Office
+//! applications returning `RPC_E_CALL_REJECTED` while a dialog is open.
+
+use serde::{Deserialize, Serialize};
+use windows::Win32::Media::Audio::{CoRegisterMessageFilter, IMessageFilter, IMessageFilter_Impl};
+use windows::Win32::Media::HTASK;
+use windows::Win32::System::Com::{
+    INTERFACEINFO, PENDINGMSG_WAITDEFPROCESS, SERVERCALL_ISHANDLED, SERVERCALL_RETRYLATER,
+};
+use windows::core::Result;
+use windows_core::implement;
+
+/// Tells COM to give up on a rejected call
+const CANCEL_CALL: u32 = u32::MAX;
+
+/// How long calls rejected by a busy server are retried
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total time to keep retrying a call before failing it, 0 to never retry
+    pub timeout_ms: u32,
+    /// Delay before the first retry
+    pub delay_ms: u32,
+    /// Upper bound of the delay, which doubles as the call keeps being rejected
+    pub max_delay_ms: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 30_000,
+            delay_ms: 100,
+            max_delay_ms: 2_000,
+        }
+    }
+}
+
+#[implement(IMessageFilter)]
+struct RetryFilter {
+    policy: RetryPolicy,
+}
+
+impl IMessageFilter_Impl for RetryFilter_Impl {
+    fn HandleInComingCall(
+        &self,
+        _call_type: u32,
+        _caller: HTASK,
+        _tick_count: u32,
+        _interface_info: *const INTERFACEINFO,
+    ) -> u32 {
+        SERVERCALL_ISHANDLED.0 as u32
+    }
+
+    fn RetryRejectedCall(&self, _callee: HTASK, elapsed_ms: u32, reject_type: u32) -> u32 {
+        let policy = self.policy;
+
+        // Outright rejections are final, only "retry later" is worth waiting for
+        if reject_type != SERVERCALL_RETRYLATER.0 as u32 || elapsed_ms >= policy.timeout_ms {
+            return CANCEL_CALL;
+        }
+
+        // Waiting as long as the call has already been waiting doubles the delay
+        // with every rejection
+        elapsed_ms.clamp(policy.delay_ms, policy.max_delay_ms.max(policy.delay_ms))
+    }
+
+    fn MessagePending(&self, _callee: HTASK, _tick_count: u32, _pending_type: u32) -> u32 {
+        PENDINGMSG_WAITDEFPROCESS.0 as u32
+    }
+}
+
+/// Registers the retry filter for the current single-threaded apartment
+pub fn register(policy: RetryPolicy) -> Result<()> {
+    let filter: IMessageFilter = RetryFilter { policy }.into();
+
+    unsafe { CoRegisterMessageFilter(&filter, None) }
+}
+
+/// Removes the filter of the current apartment
+pub fn unregister() {
+    unsafe {
+        let _ = CoRegisterMessageFilter(None, None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Exact conversion between decimal strings and scaled integers, used for the
+//! fixed-point VARIANT types.
+
+/// Parses a plain decimal string such as `-12.3456` into an integer mantissa and the
+/// number of fractional digits, so that the value equals `mantissa / 10^scale`.
+/// Exponents, thousands separators and surrounding whitespace are rejected.
+pub fn parse_decimal(text: &str) -> Option<(i128, u32)> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    if integer.is_empty() && fraction.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let mut mantissa: i128 = 0;
+
+    for digit in integer.bytes().chain(fraction.bytes()) {
+        mantissa = mantissa
+            .checked_mul(10)?
+            .checked_add(i128::from(digit - b'0'))?;
+    }
+
+    Some((
+        if negative { -mantissa } else { mantissa },
+        fraction.len() as u32,
+    ))
+}
+
+/// Rescales a mantissa to the given number of fractional digits. Fails when digits
+/// would be lost or the result overflows.
+pub fn rescale(mantissa: i128, scale: u32, target_scale: u32) -> Option<i128> {
+    if scale <= target_scale {
+        mantissa.checked_mul(10_i128.checked_pow(target_scale - scale)?)
+    } else {
+        let divisor = 10_i128.checked_pow(scale - target_scale)?;
+
+        (mantissa % divisor == 0).then_some(mantissa / divisor)
+    }
+}
+
+/// Formats `mantissa / 10^scale` as a decimal string with exactly `scale` fractional digits
+pub fn format_decimal(mantissa: i128, scale: u32) -> String {
+    let digits = mantissa.unsigned_abs().to_string();
+    let sign = if mantissa < 0 { "-" } else { "" };
+
+    if scale == 0 {
+        return format!("{sign}{digits}");
+    }
+
+    let digits = format!("{digits:0>width$}", width = scale as usize + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - scale as usize);
+
+    format!("{sign}{integer}.{fraction}")
+}
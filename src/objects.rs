@@ -0,0 +1,136 @@
+use crate::activation::Credentials;
+use std::collections::HashMap;
+use std::rc::Rc;
+use windows::Win32::System::Com::IDispatch;
+use windows::core::{IUnknown, Interface};
+
+/// Prefix of the handles under which returned objects are registered
+pub const HANDLE_PREFIX: &str = "$obj:";
+
+/// Objects a request can refer to, either by the name given when creating them or by
+/// the `$obj:<n>` handle they were returned under
+#[derive(Default)]
+pub struct ObjectTable {
+    objects: HashMap<String, IDispatch>,
+    /// Returned objects without IDispatch, which can only be passed back as VT_UNKNOWN
+    unknowns: HashMap<String, IUnknown>,
+    /// The classes of the objects created from a ProgID or CLSID, by name
+    classes: HashMap<String, String>,
+    next_handle: u64,
+    /// The credentials the proxies of objects were given, by name. Declared last so
+    /// that the proxies are released before the credentials they refer to are freed.
+    credentials: HashMap<String, Rc<Credentials>>,
+}
+
+impl ObjectTable {
+    pub fn insert(&mut self, name: String, object: IDispatch) {
+        self.classes.remove(&name);
+        self.objects.insert(name.clone(), object);
+        self.credentials.remove(&name);
+    }
+
+    /// Stores an object created from a class, which the DISPIDs of its members are
+    /// cached under
+    pub fn insert_instance(&mut self, name: String, object: IDispatch, class: String) {
+        self.classes.insert(name.clone(), class);
+        self.objects.insert(name.clone(), object);
+        self.credentials.remove(&name);
+    }
+
+    /// Keeps the credentials the proxy of the object held under a name refers to, until
+    /// that object is released
+    pub fn keep_credentials(&mut self, name: &str, credentials: Credentials) {
+        self.credentials
+            .insert(name.to_string(), Rc::new(credentials));
+    }
+
+    /// The credentials the proxy of the object held under a name refers to, for those
+    /// keeping the object beyond its release
+    pub(crate) fn credentials(&self, name: &str) -> Option<Rc<Credentials>> {
+        self.credentials.get(name).cloned()
+    }
+
+    /// The class an object held by the table was created from
+    pub fn class_of(&self, object: &IDispatch) -> Option<&str> {
+        self.classes.iter().find_map(|(name, class)| {
+            let held = self.objects.get(name)?;
+
+            (held.as_raw() == object.as_raw()).then_some(class.as_str())
+        })
+    }
+
+    fn next_handle(&mut self) -> String {
+        self.next_handle += 1;
+
+        format!("{HANDLE_PREFIX}{}", self.next_handle)
+    }
+
+    /// Stores a returned object under a new handle and returns the handle
+    pub fn register(&mut self, object: IDispatch) -> String {
+        let handle = self.next_handle();
+        self.objects.insert(handle.clone(), object);
+
+        handle
+    }
+
+    /// Stores a returned object that has no IDispatch under a new handle and returns
+    /// the handle. It can only be passed back to calls, as `{"$ref": handle}`.
+    pub fn register_unknown(&mut self, object: IUnknown) -> String {
+        let handle = self.next_handle();
+        self.unknowns.insert(handle.clone(), object);
+
+        handle
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IDispatch> {
+        self.objects.get(name)
+    }
+
+    /// Looks up an object without IDispatch by its handle
+    pub fn get_unknown(&self, name: &str) -> Option<&IUnknown> {
+        self.unknowns.get(name)
+    }
+
+    /// Drops the reference held under a name or handle, returning whether there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.classes.remove(name);
+
+        let object = self.objects.remove(name);
+        let removed = object.is_some() | self.unknowns.remove(name).is_some();
+
+        // The credentials stay with another name of the same proxy, if any
+        if let Some(credentials) = self.credentials.remove(name) {
+            let other = object.and_then(|object| {
+                self.objects
+                    .iter()
+                    .find(|(_, held)| held.as_raw() == object.as_raw())
+                    .map(|(other, _)| other.clone())
+            });
+
+            if let Some(other) = other {
+                self.credentials.insert(other, credentials);
+            }
+        }
+
+        removed
+    }
+
+    /// Drops every held reference and returns how many there were
+    pub fn clear(&mut self) -> usize {
+        let count = self.len();
+        self.objects.clear();
+        self.unknowns.clear();
+        self.classes.clear();
+        self.credentials.clear();
+
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len() + self.unknowns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.unknowns.is_empty()
+    }
+}
@@ -0,0 +1,87 @@
+//! `load_file` and `save_file` steps, which open and save the documents of objects
+//! through IPersistFile rather than through the Open and Save methods of each object
+//! model.
+
+use crate::error::Result;
+use serde_json::Value;
+use std::path::Path;
+use windows::Win32::Foundation::{E_INVALIDARG, E_NOINTERFACE};
+use windows::Win32::System::Com::{
+    CoTaskMemFree, IDispatch, IPersistFile, STGM_READ, STGM_READWRITE, STGM_SHARE_DENY_WRITE,
+};
+use windows::core::{Error, HSTRING, Interface, PCWSTR};
+
+/// The IPersistFile of an object, failing for objects that are not stored in files
+pub(crate) fn persist_file(object: &IDispatch) -> Result<IPersistFile> {
+    object.cast::<IPersistFile>().map_err(|_| {
+        Error::new(
+            E_NOINTERFACE,
+            "The object cannot be loaded from or saved to a file (no IPersistFile)",
+        )
+        .into()
+    })
+}
+
+/// Servers resolve relative paths against their own working directory, if at all
+fn absolute(path: &str) -> Result<HSTRING> {
+    let path = std::path::absolute(Path::new(path))
+        .map_err(|error| Error::new(E_INVALIDARG, format!("Invalid path '{path}': {error}")))?;
+
+    Ok(HSTRING::from(path.as_os_str()))
+}
+
+/// Loads the file at `path` into an object, for writing too unless `read_only`
+///
+/// # Safety
+///
+/// `object` must belong to the apartment of the current thread.
+pub(crate) unsafe fn load_file(object: &IDispatch, path: &str, read_only: bool) -> Result<()> {
+    let mode = match read_only {
+        true => STGM_READ | STGM_SHARE_DENY_WRITE,
+        false => STGM_READWRITE | STGM_SHARE_DENY_WRITE,
+    };
+
+    unsafe { persist_file(object)?.Load(&absolute(path)?, mode)? };
+
+    Ok(())
+}
+
+/// Saves an object to `path`, or to the file it was loaded from without one, and
+/// returns the path of the file the object is now bound to. With `remember`, the object
+/// goes on saving to the new file, like Save As, rather than saving a copy.
+///
+/// # Safety
+///
+/// `object` must belong to the apartment of the current thread.
+pub(crate) unsafe fn save_file(
+    object: &IDispatch,
+    path: Option<&str>,
+    remember: bool,
+) -> Result<Value> {
+    let persist = persist_file(object)?;
+    let path = path.map(absolute).transpose()?;
+    let name = path
+        .as_ref()
+        .map_or(PCWSTR::null(), |path| PCWSTR(path.as_ptr()));
+
+    unsafe {
+        persist.Save(name, remember)?;
+        // Ends the no-scribble state objects enter while being saved, naming the file
+        // they are bound to from now on
+        persist.SaveCompleted(match remember {
+            true => name,
+            false => PCWSTR::null(),
+        })?;
+
+        // Objects that were never bound to a file may have no name
+        Ok(match persist.GetCurFile() {
+            Ok(current) if !current.is_null() => {
+                let text = current.to_string().unwrap_or_default();
+
+                CoTaskMemFree(Some(current.as_ptr() as *const _));
+                Value::String(text)
+            }
+            _ => Value::Null,
+        })
+    }
+}
@@ -0,0 +1,131 @@
+//! Named pipe transport for serve mode. Every client gets its own thread and session,
+//! and every pipe message carries exactly one JSON-RPC message.
+
+use crate::serve::{Connection, IDLE_INTERVAL, Received, serve};
+use serde_json::Value;
+use std::io;
+use std::thread;
+use tracing::warn;
+use win32_com_cli::session::RunOptions;
+use windows::Win32::Foundation::{CloseHandle, ERROR_MORE_DATA, ERROR_PIPE_CONNECTED, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAGS_AND_ATTRIBUTES, FlushFileBuffers, PIPE_ACCESS_DUPLEX,
+    ReadFile, WriteFile,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_MESSAGE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_MESSAGE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    PeekNamedPipe,
+};
+use windows::core::{Error, HSTRING, Result};
+
+/// Size of the pipe buffers, larger messages are read in several chunks
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+/// One connected client
+struct PipeConnection {
+    pipe: HANDLE,
+}
+
+// The handle is only ever used by the thread serving the client
+unsafe impl Send for PipeConnection {}
+
+impl Connection for PipeConnection {
+    fn receive(&mut self) -> Option<Received> {
+        let mut available = 0;
+
+        // Pipes opened for synchronous reads cannot time out, so they are only read
+        // once a message is waiting
+        unsafe { PeekNamedPipe(self.pipe, None, 0, None, Some(&mut available), None) }.ok()?;
+
+        if available == 0 {
+            thread::sleep(IDLE_INTERVAL);
+            return Some(Received::Idle);
+        }
+
+        let mut message = Vec::new();
+        let mut buffer = vec![0; BUFFER_SIZE as usize];
+
+        loop {
+            let mut read = 0;
+            let result = unsafe { ReadFile(self.pipe, Some(&mut buffer), Some(&mut read), None) };
+
+            message.extend_from_slice(&buffer[..read as usize]);
+
+            match result {
+                Ok(()) => break,
+                // The message continues in the next read
+                Err(error) if error.code() == ERROR_MORE_DATA.to_hresult() => {}
+                // The client disconnected
+                Err(_) => return None,
+            }
+        }
+
+        Some(Received::Message(
+            serde_json::from_slice(&message).map_err(|error| error.to_string()),
+        ))
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let message = serde_json::to_vec(message)?;
+        let mut written = 0;
+
+        unsafe { WriteFile(self.pipe, Some(&message), Some(&mut written), None)? };
+
+        Ok(())
+    }
+}
+
+impl Drop for PipeConnection {
+    fn drop(&mut self) {
+        unsafe {
+            // Lets the client read the last response before the pipe goes away
+            let _ = FlushFileBuffers(self.pipe);
+            let _ = DisconnectNamedPipe(self.pipe);
+            let _ = CloseHandle(self.pipe);
+        }
+    }
+}
+
+/// Accepts clients on a named pipe such as `\\.\pipe\win32-com-cli` until the process
+/// is ended. Remote clients are rejected, and the pipe must not exist yet so that no
+/// other process can impersonate the server.
+pub fn listen(name: &str, options: &RunOptions) -> Result<()> {
+    let name = HSTRING::from(name);
+    let mut flags = FILE_FLAG_FIRST_PIPE_INSTANCE;
+
+    loop {
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                &name,
+                PIPE_ACCESS_DUPLEX | flags,
+                PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                None,
+            )
+        };
+
+        if pipe.is_invalid() {
+            return Err(Error::from_win32());
+        }
+
+        flags = FILE_FLAGS_AND_ATTRIBUTES::default();
+
+        let mut connection = PipeConnection { pipe };
+
+        // A client may have connected between the creation of the pipe and this call
+        if let Err(error) = unsafe { ConnectNamedPipe(pipe, None) }
+            && error.code() != ERROR_PIPE_CONNECTED.to_hresult()
+        {
+            warn!("Failed to accept a client: {error}");
+            continue;
+        }
+
+        let options = options.clone();
+
+        thread::spawn(move || serve(&mut connection, &options));
+    }
+}
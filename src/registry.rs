@@ -0,0 +1,322 @@
+//! `list-progids` and `resolve` subcommands, which find automation targets and their
+//! servers in the class registrations of `HKEY_CLASSES_ROOT`, along with the registry
+//! access they and `register-event-source` share.
+
+use serde::Serialize;
+use windows::Win32::Foundation::{ERROR_NO_MORE_ITEMS, ERROR_SUCCESS};
+use windows::Win32::System::Registry::{
+    HKEY, HKEY_CLASSES_ROOT, KEY_READ, KEY_WOW64_32KEY, KEY_WOW64_64KEY, KEY_WRITE, REG_EXPAND_SZ,
+    REG_OPTION_NON_VOLATILE, REG_SAM_FLAGS, RRF_RT_REG_SZ, RegCloseKey, RegCreateKeyExW,
+    RegDeleteTreeW, RegEnumKeyExW, RegGetValueW, RegOpenKeyExW, RegSetValueExW,
+};
+use windows::Win32::System::Threading::{GetCurrentProcess, IsWow64Process};
+use windows::core::{HSTRING, PCWSTR, PWSTR, Result};
+
+/// An open registry key, closed when dropped
+pub struct Key(HKEY);
+
+impl Key {
+    /// Opens `path` under `parent` for reading, in the given view of the registry
+    pub fn open(parent: HKEY, path: &str, view: REG_SAM_FLAGS) -> Option<Self> {
+        let mut key = HKEY::default();
+        let status = unsafe {
+            RegOpenKeyExW(
+                parent,
+                &HSTRING::from(path),
+                None,
+                KEY_READ | view,
+                &mut key,
+            )
+        };
+
+        (status == ERROR_SUCCESS).then_some(Self(key))
+    }
+
+    /// Opens `path` under `parent` for writing, creating it when it does not exist
+    pub fn create(parent: HKEY, path: &str) -> Result<Self> {
+        let mut key = HKEY::default();
+
+        unsafe {
+            RegCreateKeyExW(
+                parent,
+                &HSTRING::from(path),
+                None,
+                PCWSTR::null(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_READ | KEY_WRITE,
+                None,
+                &mut key,
+                None,
+            )
+            .ok()?;
+        }
+
+        Ok(Self(key))
+    }
+
+    /// Names of the subkeys, in the order the registry enumerates them
+    pub fn subkeys(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        // Key names are at most 255 characters long
+        let mut buffer = [0u16; 256];
+
+        for index in 0.. {
+            let mut length = buffer.len() as u32;
+            let status = unsafe {
+                RegEnumKeyExW(
+                    self.0,
+                    index,
+                    Some(PWSTR(buffer.as_mut_ptr())),
+                    &mut length,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+
+            if status == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+
+            if status == ERROR_SUCCESS {
+                names.push(String::from_utf16_lossy(&buffer[..length as usize]));
+            }
+        }
+
+        names
+    }
+
+    /// Whether `path` exists under the key
+    pub fn has_subkey(&self, path: &str) -> bool {
+        Self::open(self.0, path, REG_SAM_FLAGS::default()).is_some()
+    }
+
+    /// Reads a string value of the subkey at `path`, its default value when `name` is
+    /// `None`. Environment variables of expandable strings are expanded.
+    pub fn string(&self, path: &str, name: Option<&str>) -> Option<String> {
+        let name = name.map(HSTRING::from);
+        let name = name
+            .as_ref()
+            .map_or(PCWSTR::null(), |name| PCWSTR(name.as_ptr()));
+        let path = HSTRING::from(path);
+        let mut size = 0u32;
+
+        // The first call measures the value, in bytes including the terminating NUL
+        let status = unsafe {
+            RegGetValueW(
+                self.0,
+                &path,
+                name,
+                RRF_RT_REG_SZ,
+                None,
+                None,
+                Some(&mut size),
+            )
+        };
+
+        if status != ERROR_SUCCESS {
+            return None;
+        }
+
+        let mut buffer = vec![0u16; (size as usize).div_ceil(2)];
+        let status = unsafe {
+            RegGetValueW(
+                self.0,
+                &path,
+                name,
+                RRF_RT_REG_SZ,
+                None,
+                Some(buffer.as_mut_ptr().cast()),
+                Some(&mut size),
+            )
+        };
+
+        if status != ERROR_SUCCESS {
+            return None;
+        }
+
+        let length = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+
+        Some(String::from_utf16_lossy(&buffer[..length]))
+    }
+
+    /// Writes a string value whose environment variables are expanded when it is read
+    pub fn set_expandable_string(&self, name: &str, value: &str) -> Result<()> {
+        let data: Vec<u8> = value
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+
+        unsafe {
+            RegSetValueExW(
+                self.0,
+                &HSTRING::from(name),
+                None,
+                REG_EXPAND_SZ,
+                Some(&data),
+            )
+        }
+        .ok()
+    }
+}
+
+/// Deletes `path` under `parent` along with its subkeys and values
+pub fn delete(parent: HKEY, path: &str) -> Result<()> {
+    unsafe { RegDeleteTreeW(parent, &HSTRING::from(path)) }.ok()
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = RegCloseKey(self.0);
+        }
+    }
+}
+
+/// A ProgID along with the class it names
+#[derive(Serialize)]
+pub struct ProgId {
+    pub prog_id: String,
+    pub clsid: String,
+    /// `inproc` for a DLL loaded into the caller, `local` for an executable
+    pub servers: Vec<&'static str>,
+}
+
+/// Kinds of the servers registered for a class, in the view of the current process
+pub fn servers(classes: &Key, clsid: &str) -> Vec<&'static str> {
+    [("InprocServer32", "inproc"), ("LocalServer32", "local")]
+        .into_iter()
+        .filter(|(subkey, _)| classes.has_subkey(&format!("CLSID\\{clsid}\\{subkey}")))
+        .map(|(_, kind)| kind)
+        .collect()
+}
+
+/// Lists the ProgIDs whose name contains `filter`, ignoring case
+pub fn list_prog_ids(filter: Option<&str>) -> Vec<ProgId> {
+    let Some(classes) = Key::open(HKEY_CLASSES_ROOT, "", REG_SAM_FLAGS::default()) else {
+        return Vec::new();
+    };
+    let filter = filter.map(str::to_lowercase);
+
+    classes
+        .subkeys()
+        .into_iter()
+        // File extensions and CLSIDs are not ProgIDs
+        .filter(|name| !name.starts_with('.') && !name.starts_with('{'))
+        .filter(|name| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| name.to_lowercase().contains(filter))
+        })
+        .filter_map(|prog_id| {
+            let clsid = classes.string(&format!("{prog_id}\\CLSID"), None)?;
+
+            Some(ProgId {
+                servers: servers(&classes, &clsid),
+                prog_id,
+                clsid,
+            })
+        })
+        .collect()
+}
+
+/// A registered server of a class
+#[derive(Serialize)]
+pub struct Server {
+    /// `inproc` or `local`, as listed by `list-progids`
+    pub kind: &'static str,
+    /// The DLL, or the command line of the executable
+    pub path: String,
+    /// `Apartment`, `Free`, `Both` or `Neutral` for DLLs, absent for the main STA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threading_model: Option<String>,
+    /// The registry view the server is registered in, 32 or 64
+    pub bitness: u32,
+}
+
+/// What the registry knows of a class
+#[derive(Serialize)]
+pub struct Registration {
+    pub clsid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prog_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_independent_prog_id: Option<String>,
+    /// The display name of the class
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub servers: Vec<Server>,
+}
+
+/// Views of the registry with their bitness, both on 64-bit Windows
+fn views() -> Vec<(u32, REG_SAM_FLAGS)> {
+    let mut wow64 = false.into();
+    let is_wow64 =
+        unsafe { IsWow64Process(GetCurrentProcess(), &mut wow64) }.is_ok() && wow64.as_bool();
+
+    if cfg!(target_pointer_width = "64") || is_wow64 {
+        vec![(64, KEY_WOW64_64KEY), (32, KEY_WOW64_32KEY)]
+    } else {
+        vec![(32, REG_SAM_FLAGS::default())]
+    }
+}
+
+/// Finds the CLSID of a ProgID, following `CurVer` from a version-independent one
+fn prog_id_clsid(classes: &Key, prog_id: &str) -> Option<String> {
+    classes
+        .string(&format!("{prog_id}\\CLSID"), None)
+        .or_else(|| {
+            let current = classes.string(&format!("{prog_id}\\CurVer"), None)?;
+
+            classes.string(&format!("{current}\\CLSID"), None)
+        })
+}
+
+/// Looks up a class by ProgID, or by CLSID when written in braces, in every view of the
+/// registry. `None` when no view registers it.
+pub fn resolve(target: &str) -> Option<Registration> {
+    let classes = Key::open(HKEY_CLASSES_ROOT, "", REG_SAM_FLAGS::default())?;
+    let clsid = if target.starts_with('{') {
+        target.to_uppercase()
+    } else {
+        prog_id_clsid(&classes, target)?
+    };
+    let mut registration = Registration {
+        clsid: clsid.clone(),
+        prog_id: None,
+        version_independent_prog_id: None,
+        name: None,
+        servers: Vec::new(),
+    };
+    let mut found = false;
+
+    for (bitness, view) in views() {
+        let Some(class) = Key::open(HKEY_CLASSES_ROOT, &format!("CLSID\\{clsid}"), view) else {
+            continue;
+        };
+
+        found = true;
+        registration.name = registration.name.or_else(|| class.string("", None));
+        registration.prog_id = registration
+            .prog_id
+            .or_else(|| class.string("ProgID", None));
+        registration.version_independent_prog_id = registration
+            .version_independent_prog_id
+            .or_else(|| class.string("VersionIndependentProgID", None));
+
+        for (subkey, kind) in [("InprocServer32", "inproc"), ("LocalServer32", "local")] {
+            if let Some(path) = class.string(subkey, None) {
+                registration.servers.push(Server {
+                    kind,
+                    path,
+                    threading_model: class.string(subkey, Some("ThreadingModel")),
+                    bitness,
+                });
+            }
+        }
+    }
+
+    found.then_some(registration)
+}
@@ -0,0 +1,445 @@
+//! Requests as the CLI reads them: either a single call of a method, with properties
+//! set before and read after it, or a batch of explicit steps. Requests can also be
+//! validated, which plans their steps without invoking anything.
+
+use crate::activation::ActivationOptions;
+use crate::error::{ErrorReport, ExitStatus};
+use crate::session::{Operation, RunOptions, Step, StepResults, run_steps};
+use crate::template;
+use crate::validate::plan_steps;
+use crate::variant::pointer_token;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use windows::Win32::Foundation::S_OK;
+use windows::core::HRESULT;
+
+#[derive(Serialize, Deserialize)]
+struct ComMethodCall {
+    version: String,
+    #[serde(default)]
+    prog_id: Option<String>,
+    method: String,
+    properties: HashMap<String, Value>,
+    #[serde(default)]
+    args: Vec<Value>,
+    #[serde(default)]
+    named_args: HashMap<String, Value>,
+    #[serde(default)]
+    get: Vec<String>,
+    #[serde(flatten)]
+    activation: ActivationOptions,
+    #[serde(flatten)]
+    options: RunOptions,
+}
+
+/// A request made of explicit steps, see [`Step`]
+#[derive(Serialize, Deserialize)]
+struct ComBatch {
+    version: String,
+    steps: Vec<Step>,
+    #[serde(flatten)]
+    options: RunOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ComMethodCallResult {
+    ok: bool,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prog_id: Option<String>,
+    method: String,
+    result: Value,
+    properties: Map<String, Value>,
+    /// The code the call succeeded with
+    hresult: String,
+    /// Whether every step succeeded with S_OK rather than a code such as S_FALSE
+    s_ok: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ComMethodCallError {
+    ok: bool,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prog_id: Option<String>,
+    method: String,
+    error: ErrorReport,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ComBatchResult {
+    ok: bool,
+    version: String,
+    results: Vec<Value>,
+    /// The code each step succeeded with, in order
+    hresults: Vec<String>,
+    s_ok: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ComBatchError {
+    ok: bool,
+    version: String,
+    error: ErrorReport,
+    results: Vec<Value>,
+    hresults: Vec<String>,
+}
+
+/// The steps a request would run, see [`validate_request`]
+#[derive(Serialize, Deserialize)]
+struct ComPlan {
+    ok: bool,
+    version: String,
+    plan: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ComPlanError {
+    ok: bool,
+    version: String,
+    error: ErrorReport,
+    plan: Vec<Value>,
+}
+
+/// A request that could not be read, see [`ErrorReport::invalid_request`]
+#[derive(Serialize, Deserialize)]
+struct ComRequestError {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    error: ErrorReport,
+}
+
+impl ComMethodCall {
+    /// Expresses the call as steps: create the object, set its properties, call the
+    /// method and read the requested properties
+    fn steps(&self) -> Vec<Step> {
+        let mut operations = vec![Operation::Create {
+            prog_id: self.prog_id.clone(),
+            name: None,
+            activation: self.activation.clone(),
+        }];
+
+        operations.extend(
+            self.properties
+                .iter()
+                .map(|(property, value)| Operation::Set {
+                    object: None,
+                    property: property.clone(),
+                    value: value.clone(),
+                    kind: None,
+                }),
+        );
+        operations.push(Operation::Call {
+            object: None,
+            method: self.method.clone(),
+            args: self.args.clone(),
+            named_args: self.named_args.clone(),
+            kind: None,
+        });
+        operations.extend(self.get.iter().map(|property| Operation::Get {
+            object: None,
+            property: property.clone(),
+            enumerate: false,
+            kind: None,
+        }));
+
+        operations
+            .into_iter()
+            .map(|operation| Step {
+                operation,
+                save_as: None,
+                timeout_ms: None,
+            })
+            .collect()
+    }
+}
+
+/// JSON Schema of the requests [`run_request`] accepts, single calls and batches alike
+pub const SCHEMA: &str = include_str!("../schema/request.schema.json");
+
+/// A result document along with the exit status it warrants
+pub type Output = (Value, ExitStatus);
+
+/// Writes the success code of a step the way errors write their HRESULT
+fn hex(code: &HRESULT) -> String {
+    format!("{:#010X}", code.0 as u32)
+}
+
+/// The JSON pointer of a value within a call request, from its pointer within the step
+/// of the call, where the values of `properties` are those of `set` steps
+fn call_pointer(step: &Step, pointer: &str) -> String {
+    match &step.operation {
+        Operation::Set { property, .. } => {
+            let rest = pointer.strip_prefix("/value").unwrap_or_default();
+
+            format!("/properties/{}{rest}", pointer_token(property))
+        }
+        _ => pointer.to_string(),
+    }
+}
+
+fn call_com_method(params: ComMethodCall) -> Output {
+    let steps = params.steps();
+    let (output, status) = match run_steps(&steps, &params.options) {
+        Ok(StepResults {
+            mut results,
+            hresults: codes,
+        }) => {
+            // Results follow the order of the steps: create, sets, call, then gets
+            let gets = results.split_off(results.len() - params.get.len());
+            let properties: Map<String, Value> = params.get.iter().cloned().zip(gets).collect();
+            let call = results.len() - 1;
+
+            let output = serde_json::to_value(&ComMethodCallResult {
+                ok: true,
+                version: params.version,
+                prog_id: params.prog_id,
+                method: params.method,
+                result: results.pop().unwrap_or_default(),
+                properties,
+                hresult: hex(&codes[call]),
+                s_ok: codes.iter().all(|code| *code == S_OK),
+            });
+
+            (output, ExitStatus::Success)
+        }
+        Err(failure) => {
+            let mut error = failure.error.report(None);
+
+            error.pointer = error
+                .pointer
+                .map(|pointer| call_pointer(&steps[failure.step], &pointer));
+
+            let output = serde_json::to_value(&ComMethodCallError {
+                ok: false,
+                version: params.version,
+                prog_id: params.prog_id,
+                method: params.method,
+                error,
+            });
+
+            (output, failure.error.exit_status())
+        }
+    };
+
+    (output.expect("Failed to serialize the call result"), status)
+}
+
+fn run_batch(batch: ComBatch) -> Output {
+    let (output, status) = match run_steps(&batch.steps, &batch.options) {
+        Ok(StepResults {
+            results,
+            hresults: codes,
+        }) => {
+            let output = serde_json::to_value(&ComBatchResult {
+                ok: true,
+                version: batch.version,
+                results,
+                hresults: codes.iter().map(hex).collect(),
+                s_ok: codes.iter().all(|code| *code == S_OK),
+            });
+
+            (output, ExitStatus::Success)
+        }
+        Err(failure) => {
+            let mut error = failure.error.report(Some(failure.step));
+
+            error.pointer = error
+                .pointer
+                .map(|pointer| format!("/steps/{}{pointer}", failure.step));
+
+            let output = serde_json::to_value(&ComBatchError {
+                ok: false,
+                version: batch.version,
+                error,
+                results: failure.results,
+                hresults: failure.hresults.iter().map(hex).collect(),
+            });
+
+            (output, failure.error.exit_status())
+        }
+    };
+
+    (
+        output.expect("Failed to serialize the batch result"),
+        status,
+    )
+}
+
+/// The result of a request that could not be read
+pub fn invalid_request(version: Option<String>, message: String) -> Output {
+    let output = serde_json::to_value(&ComRequestError {
+        ok: false,
+        version,
+        error: ErrorReport::invalid_request(message),
+    });
+
+    (
+        output.expect("Failed to serialize the request error"),
+        ExitStatus::InvalidRequest,
+    )
+}
+
+enum Request {
+    Call(Box<ComMethodCall>),
+    Batch(ComBatch),
+}
+
+/// Reads a request, either a single call or a batch of steps, failing with the result
+/// of an invalid request
+fn parse_request(request: Value) -> std::result::Result<Request, Output> {
+    let version = request
+        .get("version")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    if request.get("steps").is_some() {
+        serde_json::from_value(request)
+            .map(Request::Batch)
+            .map_err(|error| invalid_request(version, format!("Invalid batch request: {error}")))
+    } else {
+        serde_json::from_value(request)
+            .map(|params| Request::Call(Box::new(params)))
+            .map_err(|error| invalid_request(version, format!("Invalid call request: {error}")))
+    }
+}
+
+/// Deepest nesting of included files, which stops files including one another
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Replaces the `{"$include": "path"}` steps of a batch with the steps of the JSON file
+/// they name, which holds a single step or an array of them. Paths are relative to
+/// `directory`, and within included files to the file including them.
+pub fn include_steps(request: &mut Value, directory: &Path) -> Result<(), String> {
+    if let Some(steps) = request.get_mut("steps").and_then(Value::as_array_mut) {
+        *steps = expand_includes(std::mem::take(steps), directory, 0)?;
+    }
+
+    Ok(())
+}
+
+fn expand_includes(
+    steps: Vec<Value>,
+    directory: &Path,
+    depth: usize,
+) -> Result<Vec<Value>, String> {
+    let mut expanded = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        let Some(path) = step
+            .as_object()
+            .filter(|step| step.len() == 1)
+            .and_then(|step| step.get("$include"))
+            .and_then(Value::as_str)
+        else {
+            expanded.push(step);
+            continue;
+        };
+
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(format!(
+                "Includes are nested more than {MAX_INCLUDE_DEPTH} deep at '{path}'"
+            ));
+        }
+
+        let path = directory.join(path);
+        let text = fs::read_to_string(&path)
+            .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+        // Editors on Windows like to start UTF-8 files with a byte order mark
+        let fragment = serde_json::from_str(text.trim_start_matches('\u{feff}'))
+            .map_err(|error| format!("Failed to parse {}: {error}", path.display()))?;
+        let fragment = match fragment {
+            Value::Array(steps) => steps,
+            step => vec![step],
+        };
+
+        expanded.extend(expand_includes(
+            fragment,
+            path.parent().unwrap_or(directory),
+            depth + 1,
+        )?);
+    }
+
+    Ok(expanded)
+}
+
+/// Expands the `${env:NAME}` placeholders within the strings of a request with the
+/// environment variables of the process
+pub fn expand_environment(request: &mut Value) -> Result<(), String> {
+    template::expand(request, "env", &|name| std::env::var(name).ok())
+        .map_err(|name| format!("Environment variable '{name}' is not set"))
+}
+
+/// Expands the `${param:NAME}` placeholders within the strings of a request with the
+/// values of `parameters`, the last one of a name winning
+pub fn expand_parameters(
+    request: &mut Value,
+    parameters: &[(String, String)],
+) -> Result<(), String> {
+    let lookup = |name: &str| {
+        parameters
+            .iter()
+            .rev()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    template::expand(request, "param", &lookup)
+        .map_err(|name| format!("Parameter '{name}' is not given"))
+}
+
+/// Runs a request, either a single call or a batch of steps, and returns its result
+/// document
+pub fn run_request(request: Value) -> Output {
+    match parse_request(request) {
+        Ok(Request::Call(params)) => call_com_method(*params),
+        Ok(Request::Batch(batch)) => run_batch(batch),
+        Err(output) => output,
+    }
+}
+
+/// Plans a request instead of running it: its objects are created, and the members it
+/// names are looked up, but none of them is invoked. The result lists the steps with
+/// the DISPIDs of their members and the VARIANT types of their values.
+pub fn validate_request(request: Value) -> Output {
+    let (version, steps, options, step_errors) = match parse_request(request) {
+        Ok(Request::Call(params)) => (
+            params.version.clone(),
+            params.steps(),
+            params.options,
+            false,
+        ),
+        Ok(Request::Batch(batch)) => (batch.version, batch.steps, batch.options, true),
+        Err(output) => return output,
+    };
+
+    let (output, status) = match plan_steps(&steps, &options) {
+        Ok(plan) => {
+            let output = serde_json::to_value(&ComPlan {
+                ok: true,
+                version,
+                plan,
+            });
+
+            (output, ExitStatus::Success)
+        }
+        Err(failure) => {
+            // Steps are only reported for batches, as calls are not written as steps
+            let step = step_errors.then_some(failure.step);
+            let output = serde_json::to_value(&ComPlanError {
+                ok: false,
+                version,
+                error: failure.error.report(step),
+                plan: failure.results,
+            });
+
+            (output, failure.error.exit_status())
+        }
+    };
+
+    (output.expect("Failed to serialize the plan"), status)
+}
@@ -0,0 +1,126 @@
+//! `rot` subcommand, which lists the Running Object Table, where running applications
+//! and open documents are registered for others to attach to, and describes the object
+//! of one of its entries.
+
+use serde::Serialize;
+use win32_com_cli::ComSession;
+use win32_com_cli::Result;
+use win32_com_cli::activation::parse_clsid;
+use win32_com_cli::session::RunOptions;
+use win32_com_cli::typeinfo::{TypeDescription, describe_type, object_type_info};
+use windows::Win32::Foundation::MK_E_UNAVAILABLE;
+use windows::Win32::System::Com::{
+    CoTaskMemFree, CreateBindCtx, GetRunningObjectTable, IBindCtx, IDispatch, IMoniker, IPersist,
+    IRunningObjectTable, ProgIDFromCLSID,
+};
+use windows::core::{Error, GUID, Interface, PWSTR};
+
+/// An entry of the Running Object Table
+#[derive(Serialize)]
+pub struct RunningObject {
+    /// The name `rot get` and the `moniker` of requests bind to, such as the path of a
+    /// workbook or `!{00024500-0000-0000-C000-000000000046}` for a running Excel
+    pub display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clsid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prog_id: Option<String>,
+}
+
+/// Takes a string COM allocated for the caller
+unsafe fn take_string(text: PWSTR) -> String {
+    unsafe {
+        let string = text.to_string().unwrap_or_default();
+
+        CoTaskMemFree(Some(text.as_ptr() as *const _));
+        string
+    }
+}
+
+/// The monikers of the table along with their display names
+unsafe fn entries(
+    table: &IRunningObjectTable,
+    context: &IBindCtx,
+) -> Result<Vec<(IMoniker, String)>> {
+    let mut entries = Vec::new();
+
+    unsafe {
+        let monikers = table.EnumRunning()?;
+
+        loop {
+            let mut moniker = [None];
+            let mut fetched = 0;
+
+            monikers.Next(&mut moniker, Some(&mut fetched)).ok()?;
+
+            let [Some(moniker)] = moniker else {
+                return Ok(entries);
+            };
+
+            // Entries whose server went away without revoking them have no name
+            if let Ok(name) = moniker.GetDisplayName(context, None) {
+                entries.push((moniker, take_string(name)));
+            }
+        }
+    }
+}
+
+/// The class of a running object, as it reports it, or as its `!{CLSID}` name tells
+/// for objects registered through RegisterActiveObject
+unsafe fn class_of(table: &IRunningObjectTable, moniker: &IMoniker, name: &str) -> Option<GUID> {
+    let reported = unsafe { table.GetObject(moniker) }
+        .and_then(|object| object.cast::<IPersist>())
+        .and_then(|persist| unsafe { persist.GetClassID() });
+
+    reported
+        .ok()
+        .or_else(|| parse_clsid(name.strip_prefix('!')?).ok())
+}
+
+/// Lists the entries of the Running Object Table with the classes of their objects
+pub fn list() -> Result<Vec<RunningObject>> {
+    let _session = ComSession::new(&RunOptions::default());
+
+    unsafe {
+        let table = GetRunningObjectTable(0)?;
+        let context = CreateBindCtx(0)?;
+        let mut objects = Vec::new();
+
+        for (moniker, display_name) in entries(&table, &context)? {
+            let clsid = class_of(&table, &moniker, &display_name);
+
+            objects.push(RunningObject {
+                clsid: clsid.map(|clsid| format!("{{{clsid:?}}}")),
+                prog_id: clsid
+                    .and_then(|clsid| ProgIDFromCLSID(&clsid).ok().map(|id| take_string(id))),
+                display_name,
+            });
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Binds to the running object registered under a display name, regardless of case,
+/// and describes its dispatch interface
+pub fn get(display_name: &str) -> Result<TypeDescription> {
+    let _session = ComSession::new(&RunOptions::default());
+
+    unsafe {
+        let table = GetRunningObjectTable(0)?;
+        let context = CreateBindCtx(0)?;
+        let moniker = entries(&table, &context)?
+            .into_iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(display_name))
+            .map(|(moniker, _)| moniker)
+            .ok_or_else(|| {
+                Error::new(
+                    MK_E_UNAVAILABLE,
+                    format!("No running object is registered as '{display_name}'"),
+                )
+            })?;
+        let object: IDispatch = table.GetObject(&moniker)?.cast()?;
+
+        describe_type(&object_type_info(&object)?)
+    }
+}
@@ -0,0 +1,282 @@
+//! Long-lived session answering JSON-RPC 2.0 messages, so objects such as a running
+//! Excel keep their state between the requests of another process.
+//!
+//! The methods are `create`, `invoke` (or `call`), `get`, `set`, `foreach`,
+//! `load_file`, `save_file`, `subscribe`, `unsubscribe`, `wait_event`, `release` and
+//! `shutdown`. Their `params` are the fields of the step of the same kind. The events
+//! of the objects subscribed to are sent as `event` notifications as soon as they are
+//! fired while the client is idle, or before the response of the request during which
+//! they were fired. Messages are exchanged over a [`Connection`], stdin and stdout by
+//! default, where they may also be encoded in one of the binary [`Format`]s.
+
+use crate::format::Format;
+use crate::worker::Session;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value, json};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use win32_com_cli::error::ErrorReport;
+use win32_com_cli::session::{RunOptions, Step};
+
+const JSONRPC_VERSION: &str = "2.0";
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// Failure of the COM operation itself, detailed in the error's `data`
+const SERVER_ERROR: i32 = -32000;
+
+/// How long a connection waits for a message before the session dispatches the events
+/// fired meanwhile
+pub const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Serialize, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// Absent for notifications, which get no response, unlike requests whose id is
+    /// `null`
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Map<String, Value>,
+}
+
+/// Reads a field that is there, `null` included, which `#[serde(default)]` leaves
+/// `None` when missing
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcResult {
+    jsonrpc: String,
+    id: Value,
+    result: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcFailure {
+    jsonrpc: String,
+    id: Value,
+    error: RpcError,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Box<ErrorReport>>,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+/// What a connection received
+pub enum Received {
+    /// A message, or the parser's error for one that is not valid JSON
+    Message(std::result::Result<Value, String>),
+    /// No message arrived within [`IDLE_INTERVAL`]
+    Idle,
+}
+
+/// A transport carrying one client's messages
+pub trait Connection {
+    /// Receives the next message, waiting for about [`IDLE_INTERVAL`] at most, `None`
+    /// once the client is done
+    fn receive(&mut self) -> Option<Received>;
+
+    /// Sends one message
+    fn send(&mut self, message: &Value) -> io::Result<()>;
+}
+
+/// Messages on stdin and stdout, newline-delimited in JSON. Stdin is read on a thread
+/// of its own, so that the session dispatches events while waiting.
+struct Stdio {
+    format: Format,
+    messages: Receiver<std::result::Result<Value, String>>,
+    failed: bool,
+}
+
+impl Connection for Stdio {
+    fn receive(&mut self) -> Option<Received> {
+        // Past a malformed message the stream cannot be resynchronized
+        if self.failed {
+            return None;
+        }
+
+        match self.messages.recv_timeout(IDLE_INTERVAL) {
+            Ok(message) => {
+                self.failed = message.is_err();
+
+                Some(Received::Message(message))
+            }
+            Err(RecvTimeoutError::Timeout) => Some(Received::Idle),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.format.write(&mut io::stdout().lock(), message)
+    }
+}
+
+/// Maps a JSON-RPC method to the `op` of the step it runs, or `None` for unknown ones
+fn operation_name(method: &str) -> Option<&str> {
+    match method {
+        "invoke" => Some("call"),
+        "create" | "call" | "get" | "set" | "foreach" | "load_file" | "save_file" | "subscribe"
+        | "unsubscribe" | "wait_event" | "release" => Some(method),
+        _ => None,
+    }
+}
+
+fn respond(
+    connection: &mut dyn Connection,
+    id: Value,
+    outcome: std::result::Result<Value, RpcError>,
+) -> io::Result<()> {
+    let jsonrpc = JSONRPC_VERSION.to_string();
+    let output = match outcome {
+        Ok(result) => serde_json::to_value(RpcResult {
+            jsonrpc,
+            id,
+            result,
+        }),
+        Err(error) => serde_json::to_value(RpcFailure { jsonrpc, id, error }),
+    };
+
+    connection.send(&output.expect("Failed to serialize the response"))
+}
+
+/// Runs the step a request asks for within the session
+fn handle(session: &mut Session, request: RpcRequest) -> std::result::Result<Value, RpcError> {
+    let operation = operation_name(&request.method).ok_or_else(|| {
+        RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", request.method),
+        )
+    })?;
+    let step = Step::from_params(operation, request.params)
+        .map_err(|error| RpcError::new(INVALID_PARAMS, error.to_string()))?;
+
+    session.execute(&step).map_err(|failure| RpcError {
+        code: SERVER_ERROR,
+        message: failure.message,
+        data: Some(failure.report),
+    })
+}
+
+/// Sends the events fired since the last time as `event` notifications
+fn send_events(connection: &mut dyn Connection, session: &mut Session) -> io::Result<()> {
+    session
+        .take_events()
+        .into_iter()
+        .map(|event| json!({ "jsonrpc": JSONRPC_VERSION, "method": "event", "params": event }))
+        .try_for_each(|event| connection.send(&event))
+}
+
+/// Answers the JSON-RPC messages of a connection until `shutdown` is received, the
+/// client goes away or its input ends. Objects live in one session for the whole time
+/// and are released at the end, in a worker process of its own with `--isolate`.
+/// Events are sent, and POSTed to the callbacks of their subscriptions, while the
+/// client is idle too.
+pub fn serve(connection: &mut dyn Connection, options: &RunOptions) {
+    let mut session = Session::open(options);
+
+    while let Some(received) = connection.receive() {
+        let message = match received {
+            Received::Message(message) => message,
+            Received::Idle => match send_events(connection, &mut session) {
+                Ok(()) => continue,
+                Err(_) => break,
+            },
+        };
+        let request = message
+            .map_err(|error| RpcError::new(PARSE_ERROR, error))
+            .and_then(|message| {
+                serde_json::from_value::<RpcRequest>(message)
+                    .map_err(|error| RpcError::new(INVALID_REQUEST, error.to_string()))
+            });
+        // The id is null only when the message does not tell it
+        let request = match request {
+            Ok(request) => request,
+            Err(error) => match respond(connection, Value::Null, Err(error)) {
+                Ok(()) => continue,
+                Err(_) => break,
+            },
+        };
+
+        if request.jsonrpc != JSONRPC_VERSION {
+            let error = RpcError::new(
+                INVALID_REQUEST,
+                format!(
+                    "Unsupported JSON-RPC version '{}', expected '2.0'",
+                    request.jsonrpc
+                ),
+            );
+
+            match respond(connection, request.id.unwrap_or_default(), Err(error)) {
+                Ok(()) => continue,
+                Err(_) => break,
+            }
+        }
+
+        let id = request.id.clone();
+        let shutdown = request.method == "shutdown";
+        let outcome = if shutdown {
+            Ok(Value::Null)
+        } else {
+            handle(&mut session, request)
+        };
+
+        if send_events(connection, &mut session).is_err() {
+            break;
+        }
+
+        if let Some(id) = id
+            && respond(connection, id, outcome).is_err()
+        {
+            break;
+        }
+
+        if shutdown {
+            break;
+        }
+    }
+}
+
+/// Serves a single client on stdin and stdout
+pub fn run(options: &RunOptions, format: Format) {
+    let (sender, messages) = mpsc::channel();
+
+    thread::spawn(move || {
+        for message in format.documents(io::stdin().lock()) {
+            if sender
+                .send(message.map_err(|error| error.to_string()))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut stdio = Stdio {
+        format,
+        messages,
+        failed: false,
+    };
+
+    serve(&mut stdio, options);
+}
@@ -0,0 +1,987 @@
+use crate::ComSession;
+use crate::activation::{ActivationOptions, create_object};
+use crate::dispatch::{self, InvokeKind, call_method, enumerate, get_property, set_property};
+use crate::enums::{Enums, uses_enums};
+use crate::error::{ComError, Result, catch_panic};
+use crate::events::{self, EventQueue, Subscription, subscribe};
+use crate::interrupt::{self, Running};
+use crate::message_filter::{self, RetryPolicy};
+use crate::objects::ObjectTable;
+use crate::persist::{load_file, save_file};
+use crate::template;
+use crate::variant::redact;
+use crate::watchdog::{self, Watchdog};
+use crate::webhook::{Endpoint, Webhooks};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, field, info, warn};
+use windows::Win32::Foundation::{E_INVALIDARG, E_NOINTERFACE};
+use windows::Win32::Globalization::LocaleNameToLCID;
+use windows::Win32::System::Com::{
+    CoEnableCallCancellation, CoInitialize, CoUninitialize, IDispatch,
+};
+use windows::Win32::System::Ole::IDispatchEx;
+use windows::core::{Error, HRESULT, HSTRING, Interface};
+
+/// A single step of a batch request
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Step {
+    #[serde(flatten)]
+    pub operation: Operation,
+    /// Stores the step's result in a variable for later steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub save_as: Option<String>,
+    /// Overrides the timeout of the request for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u32>,
+}
+
+impl Step {
+    /// Builds a step of the kind named by `op` from the fields of a message, such as
+    /// the `params` of a JSON-RPC call
+    pub fn from_params(op: &str, mut params: Map<String, Value>) -> serde_json::Result<Self> {
+        params.insert("op".to_string(), Value::String(op.to_string()));
+
+        serde_json::from_value(Value::Object(params))
+    }
+}
+
+/// Settings applying to every step of a request
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RunOptions {
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Time a step may take before its pending call is cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u32>,
+    /// Locale member names are looked up and values are parsed in by servers that
+    /// localize them, such as Office. The neutral locale by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    /// Paths of type libraries, or of the DLLs and executables embedding them, whose
+    /// enumerations `{"$enum": ...}` values may name besides those of the library of
+    /// the object
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub type_libraries: Vec<String>,
+    /// Steps run once the others are done, whether they succeeded, failed or were
+    /// interrupted, such as a call of `Quit`. Their failures are only logged.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cleanup: Vec<Step>,
+    /// Fails the steps passing values that have no faithful VARIANT form, rather than
+    /// passing them as VT_EMPTY
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+}
+
+/// A locale, written as a name like `de-DE` or as an LCID like `1031`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Locale(pub u32);
+
+impl Serialize for Locale {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Locale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Written {
+            Lcid(u32),
+            Name(String),
+        }
+
+        match Written::deserialize(deserializer)? {
+            Written::Lcid(lcid) => Ok(Self(lcid)),
+            Written::Name(name) => match unsafe { LocaleNameToLCID(&HSTRING::from(&name), 0) } {
+                0 => Err(D::Error::custom(format!("Unknown locale '{name}'"))),
+                lcid => Ok(Self(lcid)),
+            },
+        }
+    }
+}
+
+fn remember_default() -> bool {
+    true
+}
+
+/// Longest summary of the arguments of a step, in characters
+const SUMMARY_LENGTH: usize = 200;
+
+/// Most events kept for `wait_event` steps to find, the oldest being dropped first
+const MAX_FIRED: usize = 1000;
+
+/// How long a `wait_event` step dispatches calls before looking for its event again
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a step does
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// Creates an object, named after its ProgID (or CLSID or moniker) unless `name` is given
+    Create {
+        #[serde(default)]
+        prog_id: Option<String>,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(flatten)]
+        activation: ActivationOptions,
+    },
+    /// Sets a property, on the most recently created object unless `object` is given
+    Set {
+        #[serde(default)]
+        object: Option<String>,
+        property: String,
+        value: Value,
+        /// Invokes the member as this kind rather than the one of the op
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<InvokeKind>,
+    },
+    /// Calls a method and returns its result
+    Call {
+        #[serde(default)]
+        object: Option<String>,
+        method: String,
+        #[serde(default)]
+        args: Vec<Value>,
+        #[serde(default)]
+        named_args: HashMap<String, Value>,
+        /// Invokes the member as this kind rather than the one of the op
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<InvokeKind>,
+    },
+    /// Reads a property and returns its value, or with `enumerate` the elements of the
+    /// collection it holds
+    Get {
+        #[serde(default)]
+        object: Option<String>,
+        property: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        enumerate: bool,
+        /// Invokes the member as this kind rather than the one of the op
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<InvokeKind>,
+    },
+    /// Runs `steps` once for every element of a collection, the object at `collection`
+    /// or the object itself, with the element in the variable named by `as`. Returns
+    /// the results of the steps of every element.
+    Foreach {
+        #[serde(default)]
+        object: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        collection: Option<String>,
+        #[serde(rename = "as")]
+        variable: String,
+        steps: Vec<Step>,
+    },
+    /// Loads the document at `path` into an object through IPersistFile
+    LoadFile {
+        #[serde(default)]
+        object: Option<String>,
+        path: String,
+        /// Opens the file for reading only, leaving others free to read it too
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        read_only: bool,
+    },
+    /// Saves an object through IPersistFile to `path`, or to the file it was loaded
+    /// from, and returns the path of the file it is bound to
+    SaveFile {
+        #[serde(default)]
+        object: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Saves a copy instead, the object staying bound to its file, when `false`
+        #[serde(default = "remember_default")]
+        remember: bool,
+    },
+    /// Subscribes to the events of an object, those of its default events interface
+    /// unless `interface` names another one or gives its IID in braces
+    Subscribe {
+        #[serde(default)]
+        object: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interface: Option<String>,
+        /// URL the events are POSTed to as JSON, besides being reported
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        callback_url: Option<String>,
+    },
+    /// Stops receiving the events of an object, those of every interface unless
+    /// `interface` is given
+    Unsubscribe {
+        #[serde(default)]
+        object: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interface: Option<String>,
+    },
+    /// Waits for an event of the objects subscribed to, or of `object` only, and
+    /// returns it. Events fired since the previous wait count too, and a step without
+    /// a timeout waits for as long as it takes.
+    WaitEvent {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        object: Option<String>,
+        event: String,
+    },
+    /// Releases objects by name or handle, or every object when none are listed
+    Release {
+        #[serde(default)]
+        objects: Vec<String>,
+    },
+}
+
+impl Operation {
+    /// The `op` the operation is written with
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Operation::Create { .. } => "create",
+            Operation::Set { .. } => "set",
+            Operation::Call { .. } => "call",
+            Operation::Get { .. } => "get",
+            Operation::Foreach { .. } => "foreach",
+            Operation::LoadFile { .. } => "load_file",
+            Operation::SaveFile { .. } => "save_file",
+            Operation::Subscribe { .. } => "subscribe",
+            Operation::Unsubscribe { .. } => "unsubscribe",
+            Operation::WaitEvent { .. } => "wait_event",
+            Operation::Release { .. } => "release",
+        }
+    }
+
+    /// The class a create step activates, or the member path other steps use
+    pub(crate) fn subject(&self) -> Option<&str> {
+        match self {
+            Operation::Create {
+                prog_id,
+                activation,
+                ..
+            } => prog_id
+                .as_deref()
+                .or(activation.clsid.as_deref())
+                .or(activation.moniker.as_deref()),
+            Operation::Set { property, .. } | Operation::Get { property, .. } => Some(property),
+            Operation::Call { method, .. } => Some(method),
+            Operation::Foreach {
+                object, collection, ..
+            } => collection.as_deref().or(object.as_deref()),
+            Operation::LoadFile { path, .. } => Some(path),
+            Operation::SaveFile { path, object, .. } => path.as_deref().or(object.as_deref()),
+            Operation::Subscribe {
+                object, interface, ..
+            }
+            | Operation::Unsubscribe { object, interface } => {
+                interface.as_deref().or(object.as_deref())
+            }
+            Operation::WaitEvent { event, .. } => Some(event),
+            Operation::Release { .. } => None,
+        }
+    }
+
+    /// The values a set or call step passes, written as JSON with secrets redacted and
+    /// cut short
+    pub(crate) fn arguments(&self) -> Option<String> {
+        let summary = match self {
+            Operation::Set { value, .. } => redact(value),
+            Operation::Call {
+                args, named_args, ..
+            } if named_args.is_empty() => redact(&json!(args)),
+            Operation::Call {
+                args, named_args, ..
+            } => redact(&json!({ "args": args, "named_args": named_args })),
+            _ => return None,
+        }
+        .to_string();
+
+        Some(match summary.char_indices().nth(SUMMARY_LENGTH) {
+            Some((end, _)) => format!("{}...", &summary[..end]),
+            None => summary,
+        })
+    }
+}
+
+/// The single-threaded apartment entered on the current thread, which is left once the
+/// sessions and objects sharing it are all dropped
+pub(crate) struct Apartment {
+    initialized: bool,
+}
+
+impl Apartment {
+    pub(crate) fn enter() -> Rc<Self> {
+        // Fails when a host process already made the thread part of the multithreaded
+        // apartment, which is then used as is and must not be left on its behalf
+        let initialized = unsafe { CoInitialize(None) }.is_ok();
+
+        Rc::new(Self { initialized })
+    }
+}
+
+impl Drop for Apartment {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe {
+                CoUninitialize();
+            }
+        }
+    }
+}
+
+/// Objects created or returned by the steps of one request, or of every request of a
+/// long-lived session, within the COM apartment of the current thread
+pub(crate) struct Session {
+    /// Shared with the [`ComObject`](crate::ComObject)s handed out for the session
+    objects: Rc<RefCell<ObjectTable>>,
+    current: Option<String>,
+    variables: HashMap<String, Value>,
+    timeout_ms: Option<u32>,
+    /// Locale of the name lookups and calls
+    lcid: u32,
+    strict: bool,
+    enums: Enums,
+    /// Events fired to the sinks of `subscriptions`, until they are reported
+    events: EventQueue,
+    subscriptions: Vec<Subscription>,
+    /// Events read from the queue but not reported yet
+    unreported: Vec<Value>,
+    /// Events no `wait_event` step has returned yet, the oldest first
+    fired: VecDeque<Value>,
+    webhooks: Webhooks,
+    filtered: bool,
+    // Declared last so that the objects are released before the apartment is left
+    apartment: Rc<Apartment>,
+}
+
+/// The results of the steps of a batch, in order
+pub struct StepResults {
+    pub results: Vec<Value>,
+    /// The code the last member each step invoked succeeded with, S_OK for the steps
+    /// invoking none
+    pub hresults: Vec<HRESULT>,
+}
+
+/// The step a batch stopped at, along with the results of the steps before it
+pub struct StepFailure {
+    pub step: usize,
+    pub error: ComError,
+    pub results: Vec<Value>,
+    pub hresults: Vec<HRESULT>,
+}
+
+impl Session {
+    /// Enters a single-threaded apartment on the current thread, set up for `options`.
+    /// The apartment is left again when the session and its objects are dropped.
+    pub(crate) fn new(options: &RunOptions) -> Self {
+        let apartment = Apartment::enter();
+        let filtered = options.retry.timeout_ms > 0;
+
+        if filtered && let Err(error) = message_filter::register(options.retry) {
+            warn!("Failed to register the message filter: {error}");
+        }
+
+        // Allows the watchdog to cancel calls of steps with a timeout
+        if let Err(error) = unsafe { CoEnableCallCancellation(None) } {
+            warn!("Failed to enable call cancellation: {error}");
+        }
+
+        Self {
+            objects: Rc::default(),
+            current: None,
+            variables: HashMap::new(),
+            timeout_ms: options.timeout_ms,
+            lcid: options.locale.map_or(0, |locale| locale.0),
+            strict: options.strict,
+            enums: Enums::new(&options.type_libraries),
+            events: EventQueue::default(),
+            subscriptions: Vec::new(),
+            unreported: Vec::new(),
+            fired: VecDeque::new(),
+            webhooks: Webhooks::default(),
+            filtered,
+            apartment,
+        }
+    }
+
+    /// Looks up an object by name or handle, defaulting to the most recently created one
+    fn object(&self, name: Option<&str>) -> Result<IDispatch> {
+        let name = name
+            .or(self.current.as_deref())
+            .ok_or_else(|| Error::new(E_INVALIDARG, "No object has been created yet"))?;
+
+        let objects = self.objects.borrow();
+
+        if let Some(object) = objects.get(name) {
+            return Ok(object.clone());
+        }
+
+        match objects.get_unknown(name) {
+            Some(_) => Err(Error::new(
+                E_NOINTERFACE,
+                format!(
+                    "'{name}' has no IDispatch, it can only be passed to calls as \
+                    {{\"$ref\": \"{name}\"}}"
+                ),
+            )
+            .into()),
+            None => Err(Error::new(E_INVALIDARG, format!("Unknown object '{name}'")).into()),
+        }
+    }
+
+    /// The name an object is referred to by, defaulting to the most recently created one
+    fn object_name(&self, name: Option<&str>) -> Result<String> {
+        Ok(name
+            .or(self.current.as_deref())
+            .ok_or_else(|| Error::new(E_INVALIDARG, "No object has been created yet"))?
+            .to_string())
+    }
+
+    /// The objects of the session, by name and handle
+    pub(crate) fn objects(&self) -> &Rc<RefCell<ObjectTable>> {
+        &self.objects
+    }
+
+    pub(crate) fn apartment(&self) -> &Rc<Apartment> {
+        &self.apartment
+    }
+
+    pub(crate) fn lcid(&self) -> u32 {
+        self.lcid
+    }
+
+    /// Reads the events fired so far, dispatching those waiting for the thread first
+    fn collect_events(&mut self) {
+        if self.subscriptions.is_empty() && self.events.borrow().is_empty() {
+            return;
+        }
+
+        events::pump(Duration::ZERO);
+
+        // The session, being neither Send nor Sync, is on the thread of its sinks
+        let events =
+            unsafe { events::drain(&self.events, self.lcid, &mut self.objects.borrow_mut()) };
+
+        for event in &events {
+            let callback = self.subscriptions.iter().find_map(|subscription| {
+                (event["object"] == subscription.object.as_str()
+                    && event["interface"] == subscription.interface.as_str())
+                .then_some(subscription.callback.as_ref())?
+            });
+
+            if let Some(endpoint) = callback {
+                self.webhooks.post(endpoint, event);
+            }
+        }
+
+        self.fired.extend(events.iter().cloned());
+        self.fired
+            .drain(..self.fired.len().saturating_sub(MAX_FIRED));
+        self.unreported.extend(events);
+    }
+
+    /// Reports the events fired since the last time
+    pub(crate) fn take_events(&mut self) -> Vec<Value> {
+        self.collect_events();
+
+        std::mem::take(&mut self.unreported)
+    }
+
+    /// Dispatches calls until an event named `event`, regardless of case, is fired by
+    /// `object` or by any object subscribed to, and returns it
+    fn wait_event(
+        &mut self,
+        object: Option<&str>,
+        event: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Value> {
+        let subscribed = self
+            .subscriptions
+            .iter()
+            .any(|subscription| object.is_none_or(|object| subscription.object == object));
+
+        if !subscribed {
+            let message = match object {
+                Some(object) => format!("No subscription to the events of '{object}'"),
+                None => "No subscription to the events of any object".to_string(),
+            };
+
+            return Err(Error::new(E_INVALIDARG, message).into());
+        }
+
+        info!("Waiting for event {event}");
+
+        let started = Instant::now();
+
+        loop {
+            self.collect_events();
+
+            let found = self.fired.iter().position(|fired| {
+                fired["event"]
+                    .as_str()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(event))
+                    && object.is_none_or(|object| fired["object"] == object)
+            });
+
+            if let Some(index) = found {
+                return Ok(self.fired.remove(index).unwrap_or_default());
+            }
+
+            let remaining = match timeout {
+                Some(timeout) => timeout
+                    .checked_sub(started.elapsed())
+                    .filter(|remaining| !remaining.is_zero())
+                    .ok_or_else(|| watchdog::timeout_error(timeout))?,
+                None => WAIT_INTERVAL,
+            };
+
+            events::pump(remaining.min(WAIT_INTERVAL));
+        }
+    }
+
+    /// Releases every object and returns how many were held
+    pub(crate) fn release_all(&mut self) -> usize {
+        self.current = None;
+        self.subscriptions.clear();
+        self.fired.clear();
+        self.objects.borrow_mut().clear()
+    }
+
+    /// Executes one step and returns its result, `null` for steps without one.
+    /// Variables are substituted into the step first, and the result is saved when
+    /// the step asks for it. A step running past its timeout has its pending call
+    /// cancelled and fails with a timeout error.
+    ///
+    /// # Safety
+    ///
+    /// The session must be used on the thread that created it.
+    pub(crate) unsafe fn execute(&mut self, step: &Step) -> Result<Value> {
+        let span = debug_span!(
+            "step",
+            op = step.operation.name(),
+            subject = step.operation.subject(),
+            args = step.operation.arguments(),
+            hresult = field::Empty,
+            error = field::Empty
+        )
+        .entered();
+
+        // Failures are recorded on the span, for the diagnostics reporting them
+        catch_panic(|| unsafe { self.run_step(step) }).inspect_err(|error| {
+            span.record("hresult", error.hresult());
+            span.record("error", error.to_string());
+        })
+    }
+
+    unsafe fn run_step(&mut self, step: &Step) -> Result<Value> {
+        let mut operation = self.substitute_variables(&step.operation)?;
+
+        unsafe { self.resolve_enums(&mut operation)? };
+
+        // The timeout of the request bounds the steps of a loop, not the loop as a whole
+        let timeout = match operation {
+            Operation::Foreach { .. } => step.timeout_ms,
+            _ => step.timeout_ms.or(self.timeout_ms),
+        }
+        .map(|ms| Duration::from_millis(ms.into()));
+        // Waits for events have no call to cancel, and time out on their own
+        let watchdog = timeout
+            .filter(|_| !matches!(operation, Operation::WaitEvent { .. }))
+            .map(Watchdog::arm);
+        let result = unsafe { self.execute_operation(&operation, timeout) };
+
+        if let Some(timeout) = timeout
+            && watchdog.is_some_and(Watchdog::disarm)
+        {
+            return Err(watchdog::timeout_error(timeout));
+        }
+
+        let result = result?;
+
+        if let Some(name) = &step.save_as {
+            self.variables.insert(name.clone(), result.clone());
+        }
+
+        Ok(result)
+    }
+
+    /// The value of a variable, or of a field within it for names such as
+    /// `opened.args.Doc`, elements of arrays being numbered from 0
+    fn variable(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+
+        let mut segments = name.split('.');
+        let mut value = self.variables.get(segments.next()?)?;
+
+        for segment in segments {
+            value = match value {
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                _ => value.get(segment)?,
+            };
+        }
+
+        Some(value.clone())
+    }
+
+    fn substitute_variables(&self, operation: &Operation) -> Result<Operation> {
+        let invalid = |message: String| Error::new(E_INVALIDARG, message);
+        let mut value = serde_json::to_value(operation).map_err(|e| invalid(e.to_string()))?;
+        // The steps of a loop are substituted as they run, once their variable is set
+        let steps = value
+            .as_object_mut()
+            .and_then(|fields| fields.remove("steps"));
+
+        template::substitute(&mut value, &|name| self.variable(name))
+            .map_err(|name| invalid(format!("Unknown variable '{name}'")))?;
+
+        if let Some(steps) = steps {
+            value["steps"] = steps;
+        }
+
+        Ok(serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?)
+    }
+
+    /// Replaces the `{"$enum": ...}` values of a set or call step with the constants
+    /// they name, in the library of the object of the step
+    unsafe fn resolve_enums(&mut self, operation: &mut Operation) -> Result<()> {
+        let (object, mut values): (_, Vec<&mut Value>) = match operation {
+            Operation::Set { object, value, .. } => (object, vec![value]),
+            Operation::Call {
+                object,
+                args,
+                named_args,
+                ..
+            } => (
+                object,
+                args.iter_mut().chain(named_args.values_mut()).collect(),
+            ),
+            _ => return Ok(()),
+        };
+
+        if !values.iter().any(|value| uses_enums(value)) {
+            return Ok(());
+        }
+
+        // Unknown objects fail the step once it runs
+        let object = self.object(object.as_deref()).ok();
+
+        for value in &mut values {
+            unsafe { self.enums.resolve(value, object.as_ref())? };
+        }
+
+        Ok(())
+    }
+
+    unsafe fn execute_operation(
+        &mut self,
+        operation: &Operation,
+        timeout: Option<Duration>,
+    ) -> Result<Value> {
+        unsafe {
+            match operation {
+                Operation::Create {
+                    prog_id,
+                    name,
+                    activation,
+                } => {
+                    debug!(
+                        "Creating object {}",
+                        operation.subject().unwrap_or_default()
+                    );
+
+                    let (object, credentials) = create_object(prog_id.as_deref(), activation)?;
+                    let name = name
+                        .clone()
+                        .or_else(|| prog_id.clone())
+                        .or_else(|| activation.clsid.clone())
+                        .or_else(|| activation.moniker.clone())
+                        .unwrap_or_default();
+                    let mut objects = self.objects.borrow_mut();
+
+                    // Objects bound through monikers may be of any class, and those of
+                    // IDispatchEx gain members their class lacks. Objects of a known class
+                    // are thereby known to lack IDispatchEx.
+                    match prog_id.as_ref().or(activation.clsid.as_ref()) {
+                        Some(class) if object.cast::<IDispatchEx>().is_err() => {
+                            // Servers of other machines may be other versions
+                            let class = match &activation.machine {
+                                Some(machine) => format!(r"\\{machine}\{class}"),
+                                None => class.clone(),
+                            };
+
+                            objects.insert_instance(name.clone(), object, class.to_lowercase());
+                        }
+                        _ => objects.insert(name.clone(), object),
+                    }
+
+                    if let Some(credentials) = credentials {
+                        objects.keep_credentials(&name, credentials);
+                    }
+
+                    drop(objects);
+                    self.current = Some(name.clone());
+
+                    Ok(Value::String(name))
+                }
+                Operation::Set {
+                    object,
+                    property,
+                    value,
+                    kind,
+                } => {
+                    info!("Setting property: {property} = {:?}", redact(value));
+
+                    set_property(
+                        &self.object(object.as_deref())?,
+                        property,
+                        value,
+                        self.lcid,
+                        &self.objects.borrow(),
+                        self.strict,
+                        *kind,
+                    )?;
+
+                    Ok(Value::Null)
+                }
+                Operation::Call {
+                    object,
+                    method,
+                    args,
+                    named_args,
+                    kind,
+                } => {
+                    let named_args: Vec<(&String, &Value)> = named_args.iter().collect();
+
+                    call_method(
+                        &self.object(object.as_deref())?,
+                        method,
+                        args,
+                        &named_args,
+                        self.lcid,
+                        &mut self.objects.borrow_mut(),
+                        self.strict,
+                        *kind,
+                    )
+                }
+                Operation::Get {
+                    object,
+                    property,
+                    enumerate: false,
+                    kind,
+                } => get_property(
+                    &self.object(object.as_deref())?,
+                    property,
+                    self.lcid,
+                    &mut self.objects.borrow_mut(),
+                    *kind,
+                ),
+                Operation::Get {
+                    object,
+                    property,
+                    enumerate: true,
+                    kind,
+                } => Ok(Value::Array(enumerate(
+                    &self.object(object.as_deref())?,
+                    Some(property),
+                    self.lcid,
+                    &mut self.objects.borrow_mut(),
+                    *kind,
+                )?)),
+                Operation::Foreach {
+                    object,
+                    collection,
+                    variable,
+                    steps,
+                } => {
+                    let elements = enumerate(
+                        &self.object(object.as_deref())?,
+                        collection.as_deref(),
+                        self.lcid,
+                        &mut self.objects.borrow_mut(),
+                        None,
+                    )?;
+                    let mut results = Vec::with_capacity(elements.len());
+
+                    for element in elements {
+                        self.variables.insert(variable.clone(), element);
+
+                        let element_results = steps
+                            .iter()
+                            .map(|step| self.execute(step))
+                            .collect::<Result<Vec<Value>>>()?;
+
+                        results.push(Value::Array(element_results));
+                    }
+
+                    Ok(Value::Array(results))
+                }
+                Operation::LoadFile {
+                    object,
+                    path,
+                    read_only,
+                } => {
+                    info!("Loading file: {path}");
+
+                    load_file(&self.object(object.as_deref())?, path, *read_only)?;
+
+                    Ok(Value::Null)
+                }
+                Operation::SaveFile {
+                    object,
+                    path,
+                    remember,
+                } => {
+                    info!("Saving file: {}", path.as_deref().unwrap_or("(current)"));
+
+                    save_file(&self.object(object.as_deref())?, path.as_deref(), *remember)
+                }
+                Operation::Subscribe {
+                    object,
+                    interface,
+                    callback_url,
+                } => {
+                    let name = self.object_name(object.as_deref())?;
+                    let callback = callback_url.as_deref().map(Endpoint::parse).transpose()?;
+
+                    info!("Subscribing to the events of {name}");
+
+                    let mut subscription = subscribe(
+                        &self.object(Some(&name))?,
+                        &name,
+                        interface.as_deref(),
+                        &self.events,
+                    )?;
+                    let interface = subscription.interface.clone();
+
+                    subscription.callback = callback;
+
+                    // Replaces an earlier subscription to the same interface
+                    self.subscriptions
+                        .retain(|earlier| earlier.object != name || earlier.interface != interface);
+                    self.subscriptions.push(subscription);
+
+                    Ok(json!({ "object": name, "interface": interface }))
+                }
+                Operation::Unsubscribe { object, interface } => {
+                    let name = self.object_name(object.as_deref())?;
+                    let count = self.subscriptions.len();
+
+                    self.subscriptions.retain(|subscription| {
+                        subscription.object != name
+                            || interface.as_deref().is_some_and(|interface| {
+                                !subscription.interface.eq_ignore_ascii_case(interface)
+                            })
+                    });
+
+                    Ok(json!({ "unsubscribed": count - self.subscriptions.len() }))
+                }
+                Operation::WaitEvent { object, event } => {
+                    self.wait_event(object.as_deref(), event, timeout)
+                }
+                Operation::Release { objects } => {
+                    let released = if objects.is_empty() {
+                        self.release_all()
+                    } else {
+                        self.subscriptions
+                            .retain(|subscription| !objects.contains(&subscription.object));
+                        objects
+                            .iter()
+                            .filter(|name| self.objects.borrow_mut().remove(name))
+                            .count()
+                    };
+
+                    if self
+                        .current
+                        .as_deref()
+                        .is_some_and(|name| self.objects.borrow().get(name).is_none())
+                    {
+                        self.current = None;
+                    }
+
+                    Ok(json!({ "released": released, "remaining": self.objects.borrow().len() }))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Objects handed out as a ComObject keep the apartment alive past this point
+        self.release_all();
+
+        if self.filtered {
+            message_filter::unregister();
+        }
+    }
+}
+
+/// Runs steps in order within one COM apartment, stopping at the first failure or at
+/// an interruption, and then the cleanup steps of `options`. The events fired during a
+/// step are handed to the event handler once it ends.
+pub fn run_steps(
+    steps: &[Step],
+    options: &RunOptions,
+) -> std::result::Result<StepResults, StepFailure> {
+    // Declared first so that an interruption waits for the apartment to be left too
+    let _running = Running::enter();
+    let mut session = ComSession::new(options);
+    let outcome = run_session(&mut session, steps);
+
+    for step in &options.cleanup {
+        if let Err(error) = session.execute(step) {
+            warn!("The cleanup step {} failed: {error}", step.operation.name());
+        }
+
+        for event in session.take_events() {
+            events::publish(&event);
+        }
+    }
+
+    outcome
+}
+
+fn run_session(
+    session: &mut ComSession,
+    steps: &[Step],
+) -> std::result::Result<StepResults, StepFailure> {
+    let mut results = Vec::with_capacity(steps.len());
+    let mut hresults = Vec::with_capacity(steps.len());
+
+    for (index, step) in steps.iter().enumerate() {
+        // Forgets the codes of the members invoked before the step
+        let _ = dispatch::take_success();
+
+        let result = match interrupt::interrupted() {
+            false => session.execute(step),
+            true => Err(interrupt::interrupted_error()),
+        };
+
+        for event in session.take_events() {
+            events::publish(&event);
+        }
+
+        match result {
+            Ok(result) => {
+                results.push(result);
+                hresults.push(dispatch::take_success());
+            }
+            Err(error) => {
+                return Err(StepFailure {
+                    step: index,
+                    // The call the interruption cancelled fails in its own way
+                    error: match interrupt::interrupted() {
+                        false => error,
+                        true => interrupt::interrupted_error(),
+                    },
+                    results,
+                    hresults,
+                });
+            }
+        }
+    }
+
+    Ok(StepResults { results, hresults })
+}
@@ -0,0 +1,108 @@
+//! TCP transport for serve mode. Every message is framed as a 4-byte big-endian length
+//! followed by that many bytes of UTF-8 JSON, in both directions. Every client gets
+//! its own thread and session.
+
+use crate::serve::{Connection, IDLE_INTERVAL, Received, serve};
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use tracing::warn;
+use win32_com_cli::session::RunOptions;
+
+/// Largest message accepted from a client, guarding against bogus length prefixes
+const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+struct TcpConnection {
+    stream: TcpStream,
+}
+
+impl TcpConnection {
+    /// Whether a message starts arriving within [`IDLE_INTERVAL`], `None` once the
+    /// client closed the connection or it broke
+    fn readable(&self) -> Option<bool> {
+        self.stream.set_read_timeout(Some(IDLE_INTERVAL)).ok()?;
+
+        let peeked = self.stream.peek(&mut [0]);
+
+        self.stream.set_read_timeout(None).ok()?;
+
+        match peeked {
+            Ok(0) => None,
+            Ok(_) => Some(true),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Some(false)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+impl Connection for TcpConnection {
+    fn receive(&mut self) -> Option<Received> {
+        // Messages are only read once they start arriving, so that a timeout never
+        // leaves one half read
+        if !self.readable()? {
+            return Some(Received::Idle);
+        }
+
+        let mut prefix = [0; 4];
+
+        // The client closed the connection, or it broke
+        self.stream.read_exact(&mut prefix).ok()?;
+
+        let length = u32::from_be_bytes(prefix) as usize;
+
+        if length > MAX_MESSAGE_SIZE {
+            // The rest of the stream cannot be trusted to be framed correctly either
+            warn!("Closing a connection that sent a {length} byte message");
+            return None;
+        }
+
+        let mut message = vec![0; length];
+
+        self.stream.read_exact(&mut message).ok()?;
+
+        Some(Received::Message(
+            serde_json::from_slice(&message).map_err(|error| error.to_string()),
+        ))
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let message = serde_json::to_string(message)?;
+        let length = u32::try_from(message.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "response too large"))?;
+
+        self.stream.write_all(&length.to_be_bytes())?;
+        self.stream.write_all(message.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+/// Accepts clients on a TCP address such as `127.0.0.1:7861` until the process is
+/// ended. There is no authentication, so the address should be a loopback one.
+pub fn listen(address: &str, options: &RunOptions) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to accept a client: {error}");
+                continue;
+            }
+        };
+        let options = options.clone();
+
+        let _ = stream.set_nodelay(true);
+
+        thread::spawn(move || serve(&mut TcpConnection { stream }, &options));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Placeholder substitution inside request values: `${name}` within strings and
+//! `{"$var": "name"}` for whole, typed values, and `${namespace:name}` placeholders
+//! filled in before a request runs.
+
+use serde_json::Value;
+
+/// Replaces placeholders in `value`, looking names up through `lookup`.
+/// Fails with the name of the first placeholder that could not be resolved.
+pub fn substitute(value: &mut Value, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains("${") => {
+            *text = substitute_str(text, lookup)?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                substitute(item, lookup)?;
+            }
+        }
+        Value::Object(object) => {
+            if let (1, Some(Value::String(name))) = (object.len(), object.get("$var")) {
+                *value = lookup(name).ok_or_else(|| name.clone())?;
+                return Ok(());
+            }
+
+            for item in object.values_mut() {
+                substitute(item, lookup)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Expands the `${namespace:name}` placeholders within the strings of `value`, object
+/// keys included, leaving any other placeholder for later. Fails with the name of the
+/// first placeholder `lookup` could not resolve.
+pub fn expand(
+    value: &mut Value,
+    namespace: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let prefix = format!("{namespace}:");
+    let lookup = |name: &str| match name.strip_prefix(&prefix) {
+        Some(name) => lookup(name).map(Value::String),
+        None => Some(Value::String(format!("${{{name}}}"))),
+    };
+
+    expand_strings(value, &lookup).map_err(|name| name[prefix.len()..].to_string())
+}
+
+fn expand_strings(value: &mut Value, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains("${") => {
+            *text = substitute_str(text, lookup)?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                expand_strings(item, lookup)?;
+            }
+        }
+        Value::Object(object) => {
+            let entries = std::mem::take(object);
+
+            for (key, mut item) in entries {
+                let key = match key.contains("${") {
+                    true => substitute_str(&key, lookup)?,
+                    false => key,
+                };
+
+                expand_strings(&mut item, lookup)?;
+                object.insert(key, item);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Expands every `${name}` in a string. Strings are inserted as they are, other
+/// values as their JSON text.
+fn substitute_str(text: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + length];
+
+        result.push_str(&rest[..start]);
+
+        match lookup(name).ok_or_else(|| name.to_string())? {
+            Value::String(value) => result.push_str(&value),
+            value => result.push_str(&value.to_string()),
+        }
+
+        rest = &rest[start + 3 + length..];
+    }
+
+    result.push_str(rest);
+
+    Ok(result)
+}
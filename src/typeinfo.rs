@@ -0,0 +1,675 @@
+//! Descriptions of the types an object or type library declares, read through
+//! ITypeInfo, for introspection and code generation.
+
+use crate::error::Result;
+use crate::objects::ObjectTable;
+use crate::variant::variant_to_value;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use windows::Win32::Foundation::E_NOTIMPL;
+use windows::Win32::System::Com::*;
+use windows::Win32::System::Ole::{
+    GetRecordInfoFromTypeInfo, IRecordInfo, LoadTypeLibEx, PARAMFLAG_FOPT, PARAMFLAG_FOUT,
+    PARAMFLAG_FRETVAL, REGKIND_NONE, TYPEFLAG_FHIDDEN,
+};
+use windows::Win32::System::Variant::*;
+use windows::core::{BSTR, Error, GUID, HSTRING};
+
+/// The types of a type library
+#[derive(Serialize, Deserialize)]
+pub struct TypeLibrary {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    pub guid: String,
+    pub types: Vec<TypeDescription>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeKind {
+    Enum,
+    Record,
+    Module,
+    /// A vtable-only interface, which cannot be called through IDispatch
+    Interface,
+    /// A dispatch or dual interface
+    Dispatch,
+    Coclass,
+    Alias,
+    Union,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TypeDescription {
+    pub name: String,
+    pub kind: TypeKind,
+    pub guid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    /// Methods and property accessors, in declaration order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Member>,
+    /// Values of an enumeration or module
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constants: Vec<Constant>,
+    /// The interface a coclass is driven through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_interface: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberKind {
+    Method,
+    PropertyGet,
+    PropertyPut,
+    PropertyPutRef,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Member {
+    pub name: String,
+    pub dispid: i32,
+    pub kind: MemberKind,
+    pub params: Vec<Param>,
+    /// Type of the result, `void` when there is none
+    #[serde(rename = "type")]
+    pub return_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    /// Name of a VARIANT type such as `string` or `i32`, or of a type of the library
+    #[serde(rename = "type")]
+    pub param_type: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    /// Passed by reference so that the server can write to it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub out: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Constant {
+    pub name: String,
+    pub value: Value,
+}
+
+fn format_guid(guid: GUID) -> String {
+    format!("{{{guid:?}}}")
+}
+
+fn non_empty(text: BSTR) -> Option<String> {
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The type information of an object, which describes its dispatch interface
+///
+/// # Safety
+///
+/// `object` must belong to the apartment of the current thread.
+pub unsafe fn object_type_info(object: &IDispatch) -> Result<ITypeInfo> {
+    unsafe {
+        if object.GetTypeInfoCount()? == 0 {
+            return Err(Error::new(E_NOTIMPL, "The object provides no type information").into());
+        }
+
+        Ok(object.GetTypeInfo(0, 0)?)
+    }
+}
+
+/// The type library declaring a type
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub unsafe fn containing_library(info: &ITypeInfo) -> Result<ITypeLib> {
+    let mut library = None;
+    let mut index = 0;
+
+    unsafe { info.GetContainingTypeLib(&mut library, &mut index)? };
+
+    library.ok_or_else(|| Error::new(E_NOTIMPL, "The type belongs to no type library").into())
+}
+
+/// Loads a type library from a `.tlb` file, or from the resources of a `.dll`, `.olb`
+/// or `.exe`, without registering it
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub unsafe fn load_library(path: &str) -> Result<ITypeLib> {
+    Ok(unsafe { LoadTypeLibEx(&HSTRING::from(path), REGKIND_NONE)? })
+}
+
+/// Describes every type of a type library but the hidden ones
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub unsafe fn describe_library(library: &ITypeLib) -> Result<TypeLibrary> {
+    unsafe {
+        let mut name = BSTR::new();
+        let mut doc = BSTR::new();
+        let mut help_context = 0;
+
+        library.GetDocumentation(-1, Some(&mut name), Some(&mut doc), &mut help_context, None)?;
+
+        let attributes = library.GetLibAttr()?;
+        let guid = (*attributes).guid;
+        library.ReleaseTLibAttr(attributes);
+
+        let mut types = Vec::new();
+
+        for index in 0..library.GetTypeInfoCount() {
+            let info = library.GetTypeInfo(index)?;
+
+            if !is_hidden(&info)? {
+                types.push(describe_type(&info)?);
+            }
+        }
+
+        Ok(TypeLibrary {
+            name: name.to_string(),
+            doc: non_empty(doc),
+            guid: format_guid(guid),
+            types,
+        })
+    }
+}
+
+/// Whether a type is meant to be left out of object browsers
+unsafe fn is_hidden(info: &ITypeInfo) -> Result<bool> {
+    unsafe {
+        let attributes = info.GetTypeAttr()?;
+        let flags = (*attributes).wTypeFlags;
+        info.ReleaseTypeAttr(attributes);
+
+        Ok(i32::from(flags) & TYPEFLAG_FHIDDEN.0 != 0)
+    }
+}
+
+/// Describes a type with its members and constants
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub unsafe fn describe_type(info: &ITypeInfo) -> Result<TypeDescription> {
+    unsafe {
+        let attributes = info.GetTypeAttr()?;
+        let TYPEATTR {
+            guid,
+            typekind,
+            cFuncs: functions,
+            cVars: variables,
+            cImplTypes: implemented,
+            ..
+        } = *attributes;
+        info.ReleaseTypeAttr(attributes);
+
+        let kind = match typekind {
+            TKIND_ENUM => TypeKind::Enum,
+            TKIND_RECORD => TypeKind::Record,
+            TKIND_MODULE => TypeKind::Module,
+            TKIND_INTERFACE => TypeKind::Interface,
+            TKIND_DISPATCH => TypeKind::Dispatch,
+            TKIND_COCLASS => TypeKind::Coclass,
+            TKIND_ALIAS => TypeKind::Alias,
+            _ => TypeKind::Union,
+        };
+        let (name, doc) = documentation(info, -1);
+        let mut members = Vec::new();
+        let mut constants = Vec::new();
+
+        for index in 0..functions {
+            if let Some(member) = describe_function(info, index.into())? {
+                members.push(member);
+            }
+        }
+
+        for index in 0..variables {
+            describe_variable(info, index.into(), &mut members, &mut constants)?;
+        }
+
+        let default_interface = match kind {
+            TypeKind::Coclass => default_interface(info, implemented.into())?,
+            _ => None,
+        };
+
+        Ok(TypeDescription {
+            name,
+            kind,
+            guid: format_guid(guid),
+            doc,
+            members,
+            constants,
+            default_interface,
+        })
+    }
+}
+
+/// The name and documentation string of a type (`memid` -1) or member
+pub(crate) unsafe fn documentation(info: &ITypeInfo, memid: i32) -> (String, Option<String>) {
+    let mut name = BSTR::new();
+    let mut doc = BSTR::new();
+    let mut help_context = 0;
+
+    unsafe {
+        let _ = info.GetDocumentation(
+            memid,
+            Some(&mut name),
+            Some(&mut doc),
+            &mut help_context,
+            None,
+        );
+    }
+
+    (name.to_string(), non_empty(doc))
+}
+
+unsafe fn describe_function(info: &ITypeInfo, index: u32) -> Result<Option<Member>> {
+    unsafe {
+        let function = info.GetFuncDesc(index)?;
+        let member = describe_function_desc(info, &*function);
+        info.ReleaseFuncDesc(function);
+
+        Ok(member)
+    }
+}
+
+unsafe fn describe_function_desc(info: &ITypeInfo, function: &FUNCDESC) -> Option<Member> {
+    // Restricted members, such as those inherited from IDispatch, cannot be called
+    // from scripts, and hidden ones are left out of object browsers too
+    if function.wFuncFlags.0 & (FUNCFLAG_FRESTRICTED.0 | FUNCFLAG_FHIDDEN.0) != 0 {
+        return None;
+    }
+
+    let count = function.cParams.max(0) as usize;
+    let mut names = vec![BSTR::new(); count + 1];
+    let mut found = 0;
+
+    unsafe {
+        let _ = info.GetNames(function.memid, &mut names, &mut found);
+    }
+
+    let descriptions = match count {
+        0 => &[][..],
+        _ => unsafe { std::slice::from_raw_parts(function.lprgelemdescParam, count) },
+    };
+    let optional_from = count.saturating_sub(function.cParamsOpt.max(0) as usize);
+    let mut return_type = unsafe { type_name(info, &function.elemdescFunc.tdesc) };
+    let mut params = Vec::with_capacity(count);
+
+    for (index, description) in descriptions.iter().enumerate() {
+        let flags = unsafe { description.Anonymous.paramdesc.wParamFlags };
+        let param_type = unsafe { type_name(info, &description.tdesc) };
+
+        // Vtable interfaces return their result through a last out parameter
+        if flags.0 & PARAMFLAG_FRETVAL.0 != 0 {
+            return_type = param_type;
+            continue;
+        }
+
+        let name = names
+            .get(index + 1)
+            .filter(|name| !name.is_empty())
+            .map(BSTR::to_string)
+            // The value of a property put has no name
+            .unwrap_or_else(|| "value".to_string());
+
+        params.push(Param {
+            name,
+            param_type,
+            optional: flags.0 & PARAMFLAG_FOPT.0 != 0
+                || (function.cParamsOpt > 0 && index >= optional_from),
+            out: flags.0 & PARAMFLAG_FOUT.0 != 0,
+        });
+    }
+
+    if return_type == "hresult" {
+        return_type = "void".to_string();
+    }
+
+    let kind = match function.invkind {
+        INVOKE_PROPERTYGET => MemberKind::PropertyGet,
+        INVOKE_PROPERTYPUT => MemberKind::PropertyPut,
+        INVOKE_PROPERTYPUTREF => MemberKind::PropertyPutRef,
+        _ => MemberKind::Method,
+    };
+    let (_, doc) = unsafe { documentation(info, function.memid) };
+
+    Some(Member {
+        name: names[0].to_string(),
+        dispid: function.memid,
+        kind,
+        params,
+        return_type,
+        doc,
+    })
+}
+
+/// The VARIANT types of the parameters of the method `dispid`, those of pointers, such
+/// as out parameters, being the type they point to. Parameters of types arguments are
+/// not converted to, such as interfaces, arrays and VARIANTs, are `VT_VARIANT`. `None`
+/// when the type does not declare the method.
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub(crate) unsafe fn param_types(info: &ITypeInfo, dispid: i32) -> Result<Option<Vec<VARENUM>>> {
+    unsafe {
+        let attributes = info.GetTypeAttr()?;
+        let functions = (*attributes).cFuncs;
+        info.ReleaseTypeAttr(attributes);
+
+        for index in 0..functions {
+            let function = info.GetFuncDesc(index.into())?;
+            let types =
+                ((*function).memid == dispid && (*function).invkind == INVOKE_FUNC).then(|| {
+                    let count = (*function).cParams.max(0) as usize;
+                    let descriptions = match count {
+                        0 => &[][..],
+                        _ => std::slice::from_raw_parts((*function).lprgelemdescParam, count),
+                    };
+
+                    descriptions
+                        .iter()
+                        .filter(|description| {
+                            description.Anonymous.paramdesc.wParamFlags.0 & PARAMFLAG_FRETVAL.0 == 0
+                        })
+                        .map(|description| param_vt(info, &description.tdesc))
+                        .collect()
+                });
+            info.ReleaseFuncDesc(function);
+
+            if types.is_some() {
+                return Ok(types);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The kinds the member `dispid` is declared as, such as a property get and put, its
+/// functions and the properties declared as variables alike. Empty when the type does
+/// not declare the member.
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub(crate) unsafe fn member_kinds(info: &ITypeInfo, dispid: i32) -> Result<Vec<MemberKind>> {
+    unsafe {
+        let attributes = info.GetTypeAttr()?;
+        let (functions, variables) = ((*attributes).cFuncs, (*attributes).cVars);
+        info.ReleaseTypeAttr(attributes);
+
+        let mut kinds = Vec::new();
+
+        for index in 0..functions {
+            let function = info.GetFuncDesc(index.into())?;
+
+            if (*function).memid == dispid {
+                kinds.push(match (*function).invkind {
+                    INVOKE_PROPERTYGET => MemberKind::PropertyGet,
+                    INVOKE_PROPERTYPUT => MemberKind::PropertyPut,
+                    INVOKE_PROPERTYPUTREF => MemberKind::PropertyPutRef,
+                    _ => MemberKind::Method,
+                });
+            }
+            info.ReleaseFuncDesc(function);
+        }
+
+        for index in 0..variables {
+            let variable = info.GetVarDesc(index.into())?;
+
+            if (*variable).memid == dispid && (*variable).varkind == VAR_DISPATCH {
+                kinds.push(MemberKind::PropertyGet);
+
+                if (*variable).wVarFlags.0 & VARFLAG_FREADONLY.0 == 0 {
+                    kinds.push(MemberKind::PropertyPut);
+                }
+            }
+            info.ReleaseVarDesc(variable);
+        }
+
+        Ok(kinds)
+    }
+}
+
+/// The record type of the parameter of a method at `param` among those [`param_types`]
+/// lists, for parameters declared as a structure
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub(crate) unsafe fn param_record(
+    info: &ITypeInfo,
+    dispid: i32,
+    param: usize,
+) -> Result<Option<IRecordInfo>> {
+    unsafe {
+        let attributes = info.GetTypeAttr()?;
+        let functions = (*attributes).cFuncs;
+        info.ReleaseTypeAttr(attributes);
+
+        for index in 0..functions {
+            let function = info.GetFuncDesc(index.into())?;
+            let record =
+                ((*function).memid == dispid && (*function).invkind == INVOKE_FUNC).then(|| {
+                    let count = (*function).cParams.max(0) as usize;
+                    let descriptions = match count {
+                        0 => &[][..],
+                        _ => std::slice::from_raw_parts((*function).lprgelemdescParam, count),
+                    };
+
+                    descriptions
+                        .iter()
+                        .filter(|description| {
+                            description.Anonymous.paramdesc.wParamFlags.0 & PARAMFLAG_FRETVAL.0 == 0
+                        })
+                        .nth(param)
+                        .and_then(|description| record_of(info, &description.tdesc))
+                });
+            info.ReleaseFuncDesc(function);
+
+            if let Some(record) = record {
+                return Ok(record);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The record type a type stands for, through pointers and aliases
+unsafe fn record_of(info: &ITypeInfo, description: &TYPEDESC) -> Option<IRecordInfo> {
+    unsafe {
+        match description.vt {
+            VT_PTR => record_of(info, &*description.Anonymous.lptdesc),
+            VT_USERDEFINED => {
+                let referenced = info.GetRefTypeInfo(description.Anonymous.hreftype).ok()?;
+                let attributes = referenced.GetTypeAttr().ok()?;
+                let record = match (*attributes).typekind {
+                    TKIND_RECORD => GetRecordInfoFromTypeInfo(&referenced).ok(),
+                    TKIND_ALIAS => record_of(&referenced, &(*attributes).tdescAlias),
+                    _ => None,
+                };
+                referenced.ReleaseTypeAttr(attributes);
+
+                record
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The VARIANT type arguments of a parameter are converted to, enumerations being
+/// `long` and aliases the type they stand for
+unsafe fn param_vt(info: &ITypeInfo, description: &TYPEDESC) -> VARENUM {
+    unsafe {
+        match description.vt {
+            VT_PTR => param_vt(info, &*description.Anonymous.lptdesc),
+            VT_USERDEFINED => {
+                let Ok(referenced) = info.GetRefTypeInfo(description.Anonymous.hreftype) else {
+                    return VT_VARIANT;
+                };
+                let Ok(attributes) = referenced.GetTypeAttr() else {
+                    return VT_VARIANT;
+                };
+                let vt = match (*attributes).typekind {
+                    TKIND_ENUM => VT_I4,
+                    TKIND_RECORD => VT_RECORD,
+                    TKIND_ALIAS => param_vt(&referenced, &(*attributes).tdescAlias),
+                    _ => VT_VARIANT,
+                };
+                referenced.ReleaseTypeAttr(attributes);
+
+                vt
+            }
+            VT_INT => VT_I4,
+            VT_UINT => VT_UI4,
+            vt @ (VT_I1 | VT_I2 | VT_I4 | VT_I8 | VT_UI1 | VT_UI2 | VT_UI4 | VT_UI8 | VT_R4
+            | VT_R8 | VT_CY | VT_DATE | VT_BSTR | VT_BOOL | VT_DECIMAL) => vt,
+            _ => VT_VARIANT,
+        }
+    }
+}
+
+/// Adds a constant of an enumeration, or a property of a dispatch interface declared
+/// as a variable, which can be read and, unless read-only, set
+unsafe fn describe_variable(
+    info: &ITypeInfo,
+    index: u32,
+    members: &mut Vec<Member>,
+    constants: &mut Vec<Constant>,
+) -> Result<()> {
+    unsafe {
+        let variable = info.GetVarDesc(index)?;
+        let VARDESC {
+            memid,
+            Anonymous: value,
+            elemdescVar: element,
+            wVarFlags: flags,
+            varkind,
+            ..
+        } = *variable;
+        let (name, doc) = documentation(info, memid);
+
+        if varkind == VAR_CONST && !value.lpvarValue.is_null() {
+            constants.push(Constant {
+                name,
+                value: variant_to_value(&*value.lpvarValue, 0, &mut ObjectTable::default()),
+            });
+        } else if varkind == VAR_DISPATCH
+            && flags.0 & (VARFLAG_FRESTRICTED.0 | VARFLAG_FHIDDEN.0) == 0
+        {
+            let property_type = type_name(info, &element.tdesc);
+
+            if flags.0 & VARFLAG_FREADONLY.0 == 0 {
+                members.push(Member {
+                    name: name.clone(),
+                    dispid: memid,
+                    kind: MemberKind::PropertyPut,
+                    params: vec![Param {
+                        name: "value".to_string(),
+                        param_type: property_type.clone(),
+                        optional: false,
+                        out: false,
+                    }],
+                    return_type: "void".to_string(),
+                    doc: doc.clone(),
+                });
+            }
+
+            members.push(Member {
+                name,
+                dispid: memid,
+                kind: MemberKind::PropertyGet,
+                params: Vec::new(),
+                return_type: property_type,
+                doc,
+            });
+        }
+
+        info.ReleaseVarDesc(variable);
+    }
+
+    Ok(())
+}
+
+/// The name of the default, non-source interface of a coclass
+unsafe fn default_interface(info: &ITypeInfo, implemented: u32) -> Result<Option<String>> {
+    unsafe {
+        for index in 0..implemented {
+            let flags = info.GetImplTypeFlags(index)?;
+
+            if flags.0 & IMPLTYPEFLAG_FDEFAULT.0 != 0 && flags.0 & IMPLTYPEFLAG_FSOURCE.0 == 0 {
+                let interface = info.GetRefTypeInfo(info.GetRefTypeOfImplType(index)?)?;
+
+                return Ok(Some(documentation(&interface, -1).0));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Names a type: `string`, `i32` and the like for VARIANT types, `array<T>` for arrays
+/// and the type's own name for those of a type library. Pointers are named after the
+/// type they point to.
+unsafe fn type_name(info: &ITypeInfo, description: &TYPEDESC) -> String {
+    unsafe {
+        match description.vt {
+            VT_PTR => type_name(info, &*description.Anonymous.lptdesc),
+            VT_SAFEARRAY => format!(
+                "array<{}>",
+                type_name(info, &*description.Anonymous.lptdesc)
+            ),
+            VT_CARRAY => format!(
+                "array<{}>",
+                type_name(info, &(*description.Anonymous.lpadesc).tdescElem)
+            ),
+            VT_USERDEFINED => info
+                .GetRefTypeInfo(description.Anonymous.hreftype)
+                .map(|referenced| documentation(&referenced, -1).0)
+                .unwrap_or_else(|_| "unknown".to_string()),
+            vt => vt_name(vt),
+        }
+    }
+}
+
+pub(crate) fn vt_name(vt: VARENUM) -> String {
+    let name = match vt {
+        VT_EMPTY | VT_VOID => "void",
+        VT_NULL => "null",
+        VT_I1 => "i8",
+        VT_I2 => "i16",
+        VT_I4 | VT_INT => "i32",
+        VT_I8 => "i64",
+        VT_UI1 => "u8",
+        VT_UI2 => "u16",
+        VT_UI4 | VT_UINT => "u32",
+        VT_UI8 => "u64",
+        VT_R4 => "f32",
+        VT_R8 => "f64",
+        VT_CY => "currency",
+        VT_DATE => "date",
+        VT_BSTR | VT_LPSTR | VT_LPWSTR => "string",
+        VT_DISPATCH => "dispatch",
+        VT_ERROR => "scode",
+        VT_HRESULT => "hresult",
+        VT_BOOL => "bool",
+        VT_VARIANT => "variant",
+        VT_UNKNOWN => "unknown",
+        VT_DECIMAL => "decimal",
+        VT_RECORD => "record",
+        _ => return format!("vt{}", vt.0),
+    };
+
+    name.to_string()
+}
@@ -0,0 +1,488 @@
+//! Dry runs of requests, which create or bind to the objects of a request and look up
+//! the members it names, but invoke none of them. Each step is reported with the
+//! DISPIDs of its paths and the VARIANT types its values would be passed as.
+
+use crate::activation::create_object;
+use crate::dispatch::{InvokeKind, did_you_mean, get_dispids, parse_path};
+use crate::enums::{Enums, uses_enums};
+use crate::error::{Result, catch_panic};
+use crate::events::events_interface;
+use crate::objects::{HANDLE_PREFIX, ObjectTable};
+use crate::persist::persist_file;
+use crate::session::{Apartment, Operation, RunOptions, Step, StepFailure};
+use crate::template;
+use crate::typeinfo::{
+    MemberKind, TypeDescription, TypeKind, containing_library, describe_library, describe_type,
+    object_type_info, vt_name,
+};
+use crate::variant::{type_name, value_to_variant};
+use crate::webhook::Endpoint;
+use serde_json::{Map, Value, json};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use windows::Win32::Foundation::{DISP_E_UNKNOWNNAME, E_INVALIDARG};
+use windows::Win32::System::Com::IDispatch;
+use windows::Win32::System::Variant::VT_RECORD;
+use windows::core::Error;
+
+/// What a dry run knows of the objects of a request, in place of a session
+struct Planner {
+    objects: ObjectTable,
+    current: Option<String>,
+    /// Interfaces of the objects created so far, by object name
+    interfaces: HashMap<String, String>,
+    /// Types of the libraries of those interfaces, by name
+    types: HashMap<String, TypeDescription>,
+    libraries: HashSet<String>,
+    /// Locale the names are looked up in
+    lcid: u32,
+    enums: Enums,
+    // Declared last so that the objects are released before the apartment is left
+    _apartment: Rc<Apartment>,
+}
+
+impl Planner {
+    fn new(options: &RunOptions) -> Self {
+        Self {
+            objects: ObjectTable::default(),
+            current: None,
+            interfaces: HashMap::new(),
+            types: HashMap::new(),
+            libraries: HashSet::new(),
+            lcid: options.locale.map_or(0, |locale| locale.0),
+            enums: Enums::new(&options.type_libraries),
+            _apartment: Apartment::enter(),
+        }
+    }
+
+    /// Looks up an object by name, defaulting to the most recently created one. Objects
+    /// only known once the request runs, such as returned handles, are `None`.
+    fn object(&self, name: Option<&str>) -> Result<(String, Option<IDispatch>)> {
+        let name = name
+            .or(self.current.as_deref())
+            .ok_or_else(|| Error::new(E_INVALIDARG, "No object has been created yet"))?;
+
+        if let Some(object) = self.objects.get(name) {
+            return Ok((name.to_string(), Some(object.clone())));
+        }
+
+        if name.starts_with(HANDLE_PREFIX) || name.contains("${") {
+            return Ok((name.to_string(), None));
+        }
+
+        Err(Error::new(E_INVALIDARG, format!("Unknown object '{name}'")).into())
+    }
+
+    /// Reads the types of the library declaring the interface of an object, and returns
+    /// the name of that interface. Objects without type information give `None`.
+    unsafe fn learn(&mut self, object: &IDispatch) -> Option<String> {
+        unsafe {
+            let info = object_type_info(object).ok()?;
+            let description = describe_type(&info).ok()?;
+            let name = description.name.clone();
+
+            if let Ok(library) = containing_library(&info)
+                && let Ok(library) = describe_library(&library)
+                && self.libraries.insert(library.guid)
+            {
+                self.types.extend(
+                    library
+                        .types
+                        .into_iter()
+                        .map(|description| (description.name.clone(), description)),
+                );
+            }
+
+            // Hidden interfaces are left out of the library description
+            self.types.entry(name.clone()).or_insert(description);
+
+            Some(name)
+        }
+    }
+
+    /// The interface a library type is driven through, for types that have members
+    fn interface(&self, name: &str) -> Option<&TypeDescription> {
+        let description = self.types.get(name)?;
+
+        match description.kind {
+            TypeKind::Dispatch => Some(description),
+            TypeKind::Coclass => self.interface(description.default_interface.as_deref()?),
+            _ => None,
+        }
+    }
+
+    /// Looks up the DISPIDs of the members along `path` on the object named `name`. The
+    /// first one is asked of the object itself, the others are found in the types the
+    /// members before them return. Members whose object is only known at run time have
+    /// a `null` DISPID.
+    unsafe fn plan_path(
+        &self,
+        name: &str,
+        object: Option<&IDispatch>,
+        path: &str,
+    ) -> Result<Value> {
+        let segments = parse_path(path)?;
+        let mut interface = self.interfaces.get(name).cloned();
+        let mut members = Vec::with_capacity(segments.len());
+
+        for (index, segment) in segments.iter().enumerate() {
+            let mut dispid = None;
+
+            if index == 0
+                && let Some(object) = object
+            {
+                dispid = Some(
+                    unsafe { get_dispids(object, &[&segment.name], self.lcid, &self.objects)? }[0],
+                );
+            }
+
+            let description = interface.as_deref().and_then(|name| self.interface(name));
+            let member = description.and_then(|description| {
+                description.members.iter().find(|member| {
+                    member.name.eq_ignore_ascii_case(&segment.name)
+                        && matches!(member.kind, MemberKind::Method | MemberKind::PropertyGet)
+                })
+            });
+
+            if let (None, Some(description), None) = (dispid, description, member) {
+                let names: Vec<&str> = description
+                    .members
+                    .iter()
+                    .map(|member| member.name.as_str())
+                    .collect();
+
+                return Err(Error::new(
+                    DISP_E_UNKNOWNNAME,
+                    format!(
+                        "'{}' is not a member of {}{}",
+                        segment.name,
+                        description.name,
+                        did_you_mean(&segment.name, &names)
+                    ),
+                )
+                .into());
+            }
+
+            members.push(json!({
+                "name": segment.name,
+                "dispid": dispid.or(member.map(|member| member.dispid)),
+            }));
+            interface = member.map(|member| member.return_type.clone());
+        }
+
+        Ok(Value::Array(members))
+    }
+
+    /// Replaces the `{"$enum": ...}` within a value with their constants, which fails
+    /// for the names the libraries do not declare
+    unsafe fn resolve_enums(&mut self, value: &Value, object: Option<&IDispatch>) -> Result<Value> {
+        let mut value = value.clone();
+
+        if uses_enums(&value) {
+            unsafe { self.enums.resolve(&mut value, object)? };
+        }
+
+        Ok(value)
+    }
+
+    /// Names the VARIANT type a value would be passed as, `variable` for values made
+    /// of variables of earlier steps
+    unsafe fn plan_value(&self, value: &Value) -> Value {
+        if template::substitute(&mut value.clone(), &|_| None).is_err() {
+            return json!("variable");
+        }
+
+        // Records only get their type from the parameter they are passed to
+        if matches!(value, Value::Object(object) if object.len() == 1 && object.contains_key("$record"))
+        {
+            return json!(vt_name(VT_RECORD));
+        }
+
+        json!(type_name(&unsafe {
+            value_to_variant(value, self.lcid, &self.objects)
+        }))
+    }
+
+    unsafe fn plan(&mut self, operation: &Operation) -> Result<Value> {
+        unsafe {
+            match operation {
+                Operation::Create {
+                    prog_id,
+                    name,
+                    activation,
+                } => {
+                    let (object, credentials) = create_object(prog_id.as_deref(), activation)?;
+                    let name = name
+                        .clone()
+                        .or_else(|| prog_id.clone())
+                        .or_else(|| activation.clsid.clone())
+                        .or_else(|| activation.moniker.clone())
+                        .unwrap_or_default();
+                    let interface = self.learn(&object);
+
+                    if let Some(interface) = &interface {
+                        self.interfaces.insert(name.clone(), interface.clone());
+                    }
+
+                    self.objects.insert(name.clone(), object);
+
+                    if let Some(credentials) = credentials {
+                        self.objects.keep_credentials(&name, credentials);
+                    }
+
+                    self.current = Some(name.clone());
+
+                    Ok(json!({ "op": "create", "object": name, "type": interface }))
+                }
+                Operation::Set {
+                    object,
+                    property,
+                    value,
+                    kind,
+                } => {
+                    let kind = InvokeKind::check(*kind, "set")?;
+                    let (name, object) = self.object(object.as_deref())?;
+                    let value = &self.resolve_enums(value, object.as_ref())?;
+                    let (value, putref) = match value {
+                        Value::Object(object) if object.len() == 1 => match object.get("$putref") {
+                            Some(value) => (value, true),
+                            None => (value, false),
+                        },
+                        _ => (value, false),
+                    };
+                    let putref = kind.map_or(putref, |kind| kind == InvokeKind::Propputref);
+
+                    Ok(json!({
+                        "op": "set",
+                        "object": name,
+                        "property": self.plan_path(&name, object.as_ref(), property)?,
+                        "value": self.plan_value(value),
+                        "putref": putref,
+                    }))
+                }
+                Operation::Call {
+                    object,
+                    method,
+                    args,
+                    named_args,
+                    kind,
+                } => {
+                    let kind = InvokeKind::check(*kind, "call")?;
+                    let (name, object) = self.object(object.as_deref())?;
+                    let members = self.plan_path(&name, object.as_ref(), method)?;
+                    let args = args
+                        .iter()
+                        .map(|arg| self.resolve_enums(arg, object.as_ref()))
+                        .collect::<Result<Vec<Value>>>()?;
+                    let named_args = named_args
+                        .iter()
+                        .map(|(name, arg)| {
+                            Ok((name.clone(), self.resolve_enums(arg, object.as_ref())?))
+                        })
+                        .collect::<Result<HashMap<String, Value>>>()?;
+                    let mut names: Vec<&str> = named_args.keys().map(String::as_str).collect();
+
+                    names.sort_unstable();
+
+                    // Parameter names can only be checked on the object itself
+                    let dispids = match &object {
+                        Some(object) if !method.contains('.') && !names.is_empty() => {
+                            let mut request = vec![method.split('(').next().unwrap_or(method)];
+
+                            request.extend(&names);
+                            get_dispids(object, &request, self.lcid, &self.objects)?.split_off(1)
+                        }
+                        _ => Vec::new(),
+                    };
+                    let named_args: Map<String, Value> = names
+                        .iter()
+                        .enumerate()
+                        .map(|(index, name)| {
+                            let plan = json!({
+                                "dispid": dispids.get(index),
+                                "type": self.plan_value(&named_args[*name]),
+                            });
+
+                            (name.to_string(), plan)
+                        })
+                        .collect();
+
+                    Ok(json!({
+                        "op": "call",
+                        "object": name,
+                        "method": members,
+                        "args": args.iter().map(|arg| self.plan_value(arg)).collect::<Vec<_>>(),
+                        "named_args": named_args,
+                        "kind": kind,
+                    }))
+                }
+                Operation::Get {
+                    object,
+                    property,
+                    enumerate,
+                    kind,
+                } => {
+                    let kind = InvokeKind::check(*kind, "get")?;
+                    let (name, object) = self.object(object.as_deref())?;
+
+                    Ok(json!({
+                        "op": "get",
+                        "object": name,
+                        "property": self.plan_path(&name, object.as_ref(), property)?,
+                        "enumerate": enumerate,
+                        "kind": kind,
+                    }))
+                }
+                Operation::Foreach {
+                    object,
+                    collection,
+                    variable,
+                    steps,
+                } => {
+                    let (name, object) = self.object(object.as_deref())?;
+                    let collection = match collection {
+                        Some(path) => Some(self.plan_path(&name, object.as_ref(), path)?),
+                        None => None,
+                    };
+                    // The elements are only known once the request runs
+                    let steps = steps
+                        .iter()
+                        .map(|step| self.plan(&step.operation))
+                        .collect::<Result<Vec<Value>>>()?;
+
+                    Ok(json!({
+                        "op": "foreach",
+                        "object": name,
+                        "collection": collection,
+                        "as": variable,
+                        "steps": steps,
+                    }))
+                }
+                Operation::LoadFile {
+                    object,
+                    path,
+                    read_only,
+                } => {
+                    let (name, object) = self.object(object.as_deref())?;
+
+                    if let Some(object) = &object {
+                        persist_file(object)?;
+                    }
+
+                    Ok(json!({
+                        "op": "load_file",
+                        "object": name,
+                        "path": path,
+                        "read_only": read_only,
+                    }))
+                }
+                Operation::SaveFile {
+                    object,
+                    path,
+                    remember,
+                } => {
+                    let (name, object) = self.object(object.as_deref())?;
+
+                    if let Some(object) = &object {
+                        persist_file(object)?;
+                    }
+
+                    Ok(json!({
+                        "op": "save_file",
+                        "object": name,
+                        "path": path,
+                        "remember": remember,
+                    }))
+                }
+                Operation::Subscribe {
+                    object,
+                    interface,
+                    callback_url,
+                } => {
+                    let (name, object) = self.object(object.as_deref())?;
+
+                    if let Some(url) = callback_url {
+                        Endpoint::parse(url)?;
+                    }
+
+                    let interface = match &object {
+                        Some(object) => Some(events_interface(object, interface.as_deref())?.1),
+                        None => interface.clone(),
+                    };
+
+                    Ok(json!({
+                        "op": "subscribe",
+                        "object": name,
+                        "interface": interface,
+                        "callback_url": callback_url,
+                    }))
+                }
+                Operation::Unsubscribe { object, interface } => {
+                    let (name, _) = self.object(object.as_deref())?;
+
+                    Ok(json!({ "op": "unsubscribe", "object": name, "interface": interface }))
+                }
+                Operation::WaitEvent { object, event } => {
+                    // Any object subscribed to may fire the event
+                    let object = match object {
+                        Some(name) => Some(self.object(Some(name))?.0),
+                        None => None,
+                    };
+
+                    Ok(json!({ "op": "wait_event", "object": object, "event": event }))
+                }
+                Operation::Release { objects } => {
+                    if objects.is_empty() {
+                        self.objects.clear();
+                        self.interfaces.clear();
+                    } else {
+                        for name in objects {
+                            self.objects.remove(name);
+                            self.interfaces.remove(name);
+                        }
+                    }
+
+                    if self
+                        .current
+                        .as_deref()
+                        .is_some_and(|name| self.objects.get(name).is_none())
+                    {
+                        self.current = None;
+                    }
+
+                    Ok(json!({ "op": "release", "objects": objects }))
+                }
+            }
+        }
+    }
+}
+
+/// Plans steps in order without invoking any member, stopping at the first step that
+/// would fail: an object that cannot be created, a member or parameter name the object
+/// does not know in the locale of `options`, a file step on an object that is not
+/// stored in files, or a subscription to events the object does not declare
+pub fn plan_steps(
+    steps: &[Step],
+    options: &RunOptions,
+) -> std::result::Result<Vec<Value>, StepFailure> {
+    let mut planner = Planner::new(options);
+    let mut plans = Vec::with_capacity(steps.len());
+
+    // Cleanup steps are planned after the others, which they run after
+    for (index, step) in steps.iter().chain(&options.cleanup).enumerate() {
+        // The planner lives on the current thread only
+        match catch_panic(|| unsafe { planner.plan(&step.operation) }) {
+            Ok(plan) => plans.push(plan),
+            Err(error) => {
+                return Err(StepFailure {
+                    step: index,
+                    error,
+                    results: plans,
+                    hresults: Vec::new(),
+                });
+            }
+        }
+    }
+
+    Ok(plans)
+}
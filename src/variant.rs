@@ -0,0 +1,1077 @@
+use crate::error::{ComError, Result};
+use crate::objects::ObjectTable;
+use crate::typeinfo::vt_name;
+use crate::{date, numeric};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+use tracing::{debug, warn};
+use windows::Win32::Foundation::{
+    DECIMAL, DECIMAL_0, DECIMAL_0_0, DECIMAL_1, DISP_E_PARAMNOTFOUND, E_INVALIDARG,
+    SysAllocStringByteLen, SysStringByteLen,
+};
+use windows::Win32::System::Com::{CY, IDispatch, INVOKE_PROPERTYPUT, SAFEARRAY, SAFEARRAYBOUND};
+use windows::Win32::System::Ole::{
+    IRecordInfo, SafeArrayAccessData, SafeArrayCreate, SafeArrayCreateVector, SafeArrayGetDim,
+    SafeArrayGetElement, SafeArrayGetLBound, SafeArrayGetUBound, SafeArrayPutElement,
+    SafeArrayUnaccessData, VarDateFromStr, VarR8FromStr,
+};
+use windows::Win32::System::Variant::*;
+use windows::core::{BSTR, Error, HSTRING, IUnknown, Interface, PCWSTR};
+
+/// The invariant culture, which strings are parsed and formatted in without a locale
+const LOCALE_INVARIANT: u32 = 0x007F;
+
+/// The locale strings are parsed and formatted in for a request in locale `lcid`. The
+/// neutral locale 0 would use the settings of the machine, so the invariant culture
+/// stands in for it, giving the same results everywhere.
+pub(crate) fn culture(lcid: u32) -> u32 {
+    match lcid {
+        0 => LOCALE_INVARIANT,
+        lcid => lcid,
+    }
+}
+
+/// How JSON values are converted into VARIANTs. Values without a faithful VARIANT
+/// form, such as objects that are no known tag or invalid `$date` strings, are passed
+/// as VT_EMPTY with a warning, or fail the conversion when it is strict.
+pub(crate) struct Conversion<'a> {
+    pub(crate) lcid: u32,
+    pub(crate) objects: &'a ObjectTable,
+    pub(crate) strict: bool,
+    /// Record types of the `{"$record": ...}` values by their JSON pointer, as declared
+    /// by the parameters they are passed to
+    pub(crate) records: &'a HashMap<String, IRecordInfo>,
+}
+
+/// Escapes a key as a token of a JSON pointer
+pub(crate) fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+impl Conversion<'_> {
+    /// The VARIANT passed for the value at `pointer`, which has no faithful VARIANT
+    /// form for the reason given by `problem`
+    fn unsupported(&self, pointer: &str, problem: String) -> Result<VARIANT> {
+        if self.strict {
+            let error = Error::new(
+                E_INVALIDARG,
+                format!("Unsupported value at {pointer}: {problem}"),
+            );
+
+            return Err(ComError::from(error).at(pointer));
+        }
+
+        warn!("{problem}, defaulting to empty VARIANT.");
+        Ok(VARIANT::default())
+    }
+}
+
+/// Builds a VARIANT of the given type around its raw data
+fn variant_from_raw(vt: VARENUM, data: VARIANT_0_0_0) -> VARIANT {
+    VARIANT {
+        Anonymous: VARIANT_0 {
+            Anonymous: ManuallyDrop::new(VARIANT_0_0 {
+                vt,
+                wReserved1: 0,
+                wReserved2: 0,
+                wReserved3: 0,
+                Anonymous: data,
+            }),
+        },
+    }
+}
+
+/// The dimensions of a JSON array, more than one when it nests rectangular arrays, like
+/// `[2, 3]` for `[[1, 2, 3], [4, 5, 6]]`, along with its innermost elements and their
+/// JSON pointers below the array in row-major order
+fn array_shape(items: &[Value]) -> (Vec<u32>, Vec<(String, &Value)>) {
+    let mut dimensions = vec![items.len() as u32];
+    let mut elements: Vec<(String, &Value)> = (0..)
+        .zip(items)
+        .map(|(index, item)| (format!("/{index}"), item))
+        .collect();
+
+    loop {
+        let length = match elements.first() {
+            Some((_, Value::Array(first))) if !first.is_empty() => first.len(),
+            _ => break,
+        };
+
+        if !elements
+            .iter()
+            .all(|(_, item)| matches!(item, Value::Array(inner) if inner.len() == length))
+        {
+            break;
+        }
+
+        dimensions.push(length as u32);
+        elements = elements
+            .into_iter()
+            .flat_map(|(pointer, item)| {
+                let inner = item.as_array().map(Vec::as_slice).unwrap_or_default();
+
+                (0..)
+                    .zip(inner)
+                    .map(move |(index, inner)| (format!("{pointer}/{index}"), inner))
+            })
+            .collect();
+    }
+
+    (dimensions, elements)
+}
+
+/// Converts a JSON array into a SAFEARRAY, of as many dimensions as it nests
+/// rectangular arrays: `[[1, 2], [3, 4]]` becomes a 2x2 array, like the values of an
+/// Excel range. Their lower bounds are 0. The array is typed after its elements when
+/// they all share one simple type, otherwise it holds VARIANTs.
+unsafe fn array_to_variant(
+    items: &[Value],
+    pointer: &str,
+    conversion: &Conversion,
+) -> Result<VARIANT> {
+    let (dimensions, items) = array_shape(items);
+    let elements = items
+        .iter()
+        .map(|(inner, item)| unsafe { convert(item, &format!("{pointer}{inner}"), conversion) })
+        .collect::<Result<Vec<VARIANT>>>()?;
+
+    let element_vt = match elements.first().map(VARIANT::vt) {
+        Some(vt @ (VT_I4 | VT_I8 | VT_UI8 | VT_R8 | VT_BOOL | VT_BSTR))
+            if elements.iter().all(|element| element.vt() == vt) =>
+        {
+            vt
+        }
+        _ => VT_VARIANT,
+    };
+    let bounds: Vec<SAFEARRAYBOUND> = dimensions
+        .iter()
+        .map(|&elements| SAFEARRAYBOUND {
+            cElements: elements,
+            lLbound: 0,
+        })
+        .collect();
+
+    unsafe {
+        let psa = SafeArrayCreate(element_vt, bounds.len() as u32, bounds.as_ptr());
+
+        if psa.is_null() {
+            return conversion.unsupported(pointer, "Unable to allocate a SAFEARRAY".to_string());
+        }
+
+        // Wrap the array right away so it is destroyed together with the VARIANT on failure
+        let array = variant_from_raw(
+            VARENUM(VT_ARRAY.0 | element_vt.0),
+            VARIANT_0_0_0 { parray: psa },
+        );
+
+        for (index, element) in elements.iter().enumerate() {
+            // Indices go from the leftmost dimension to the rightmost one, the
+            // elements being in row-major order
+            let mut rest = index as u32;
+            let mut indices = vec![0i32; dimensions.len()];
+
+            for (position, &length) in dimensions.iter().enumerate().rev() {
+                indices[position] = (rest % length) as i32;
+                rest /= length;
+            }
+
+            let data = &element.Anonymous.Anonymous.Anonymous;
+
+            // SafeArrayPutElement copies the element: BSTRs are passed as the string
+            // pointer itself, everything else by the address of its data
+            let element_ptr: *const c_void = match element_vt {
+                VT_VARIANT => element as *const VARIANT as *const c_void,
+                VT_BSTR => data.bstrVal.as_ptr() as *const c_void,
+                _ => data as *const VARIANT_0_0_0 as *const c_void,
+            };
+
+            if let Err(error) = SafeArrayPutElement(psa, indices.as_ptr(), element_ptr) {
+                return conversion.unsupported(
+                    &format!("{pointer}{}", items[index].0),
+                    format!("Unable to store the array element ({error})"),
+                );
+            }
+        }
+
+        Ok(array)
+    }
+}
+
+/// The numbers JSON has no form for, written `{"$number": "NaN"}`, `"Infinity"` or
+/// `"-Infinity"` in any locale
+fn special_number(text: &str) -> Option<f64> {
+    match text {
+        "NaN" => Some(f64::NAN),
+        "Infinity" => Some(f64::INFINITY),
+        "-Infinity" => Some(f64::NEG_INFINITY),
+        _ => None,
+    }
+}
+
+/// A floating-point number as JSON, the ones JSON has no form for being tagged
+fn number_to_value(number: f64) -> Value {
+    match number {
+        number if number.is_nan() => json!({ "$number": "NaN" }),
+        f64::INFINITY => json!({ "$number": "Infinity" }),
+        f64::NEG_INFINITY => json!({ "$number": "-Infinity" }),
+        number => Value::from(number),
+    }
+}
+
+/// The SCODE of a `{"$error": ...}` value, an HRESULT written like `"0x800A07FA"` or as
+/// a number
+fn error_code(value: &Value) -> Option<i32> {
+    match value {
+        Value::String(text) => {
+            let digits = text
+                .strip_prefix("0x")
+                .or_else(|| text.strip_prefix("0X"))?;
+
+            u32::from_str_radix(digits, 16).ok().map(|code| code as i32)
+        }
+        // Negative SCODEs, or their unsigned form
+        Value::Number(number) => number.as_i64().and_then(|code| {
+            i32::try_from(code)
+                .or_else(|_| u32::try_from(code).map(|code| code as i32))
+                .ok()
+        }),
+        _ => None,
+    }
+}
+
+/// Number of fractional digits stored in a VT_CY value
+const CURRENCY_SCALE: u32 = 4;
+
+/// Returns the exact decimal text of a JSON string or number
+fn decimal_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn currency_to_variant(value: &Value) -> Option<VARIANT> {
+    let (mantissa, scale) = numeric::parse_decimal(&decimal_text(value)?)?;
+    let int64 = numeric::rescale(mantissa, scale, CURRENCY_SCALE)?
+        .try_into()
+        .ok()?;
+
+    Some(variant_from_raw(
+        VT_CY,
+        VARIANT_0_0_0 {
+            cyVal: CY { int64 },
+        },
+    ))
+}
+
+/// Largest number of fractional digits a DECIMAL can hold
+const DECIMAL_MAX_SCALE: u32 = 28;
+
+/// Sign bit of a negative DECIMAL
+const DECIMAL_NEGATIVE: u8 = 0x80;
+
+fn decimal_to_variant(value: &Value) -> Option<VARIANT> {
+    let (mantissa, scale) = numeric::parse_decimal(&decimal_text(value)?)?;
+    let magnitude = mantissa.unsigned_abs();
+
+    // DECIMAL stores a 96-bit magnitude
+    if scale > DECIMAL_MAX_SCALE || magnitude >> 96 != 0 {
+        return None;
+    }
+
+    let decimal = DECIMAL {
+        // The VARIANT type overlays the reserved field of a DECIMAL
+        wReserved: VT_DECIMAL.0,
+        Anonymous1: DECIMAL_0 {
+            Anonymous: DECIMAL_0_0 {
+                scale: scale as u8,
+                sign: if mantissa < 0 { DECIMAL_NEGATIVE } else { 0 },
+            },
+        },
+        Hi32: (magnitude >> 64) as u32,
+        Anonymous2: DECIMAL_1 {
+            Lo64: magnitude as u64,
+        },
+    };
+
+    Some(VARIANT {
+        Anonymous: VARIANT_0 { decVal: decimal },
+    })
+}
+
+fn decimal_to_value(decimal: &DECIMAL) -> Value {
+    unsafe {
+        let magnitude =
+            (u128::from(decimal.Hi32) << 64 | u128::from(decimal.Anonymous2.Lo64)) as i128;
+        let DECIMAL_0_0 { scale, sign } = decimal.Anonymous1.Anonymous;
+        let mantissa = if sign & DECIMAL_NEGATIVE != 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+
+        json!({ "$decimal": numeric::format_decimal(mantissa, scale.into()) })
+    }
+}
+
+/// Names the type of a VARIANT the way type descriptions do, such as `string` or
+/// `array<i32>`
+pub(crate) fn type_name(variant: &VARIANT) -> String {
+    match variant.vt() {
+        VT_EMPTY => "empty".to_string(),
+        vt if vt.0 & VT_ARRAY.0 != 0 => format!("array<{}>", vt_name(VARENUM(vt.0 & !VT_ARRAY.0))),
+        vt => vt_name(vt),
+    }
+}
+
+/// What logs and audit records show in place of secrets
+const REDACTED: &str = "***";
+
+/// Replaces the values marked as secrets with `{"$secret": ...}` by `***`, for logs
+/// and audit records
+pub(crate) fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(object) if object.len() == 1 && object.contains_key("$secret") => {
+            json!(REDACTED)
+        }
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), redact(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(redact).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Converts an argument to the type its parameter is declared with, in the culture of
+/// `lcid`, the way scripting hosts do. Objects, arrays and errors such as the
+/// `{"$missing": true}` of parameters left out are not converted, nor are arguments
+/// that fail to, which the server then reports.
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub(crate) unsafe fn change_type(arg: &mut VARIANT, declared: VARENUM, lcid: u32) {
+    let vt = arg.vt();
+
+    if declared == VT_VARIANT
+        || vt == declared
+        || vt.0 & VT_ARRAY.0 != 0
+        || matches!(vt, VT_DISPATCH | VT_UNKNOWN | VT_ERROR)
+    {
+        return;
+    }
+
+    let mut converted = VARIANT::default();
+
+    match unsafe {
+        VariantChangeTypeEx(
+            &mut converted,
+            &*arg,
+            culture(lcid),
+            VARIANT_ALPHABOOL,
+            declared,
+        )
+    } {
+        Ok(()) => *arg = converted,
+        Err(error) => debug!(
+            "Passing {} as it is, not as the declared {}: {error}",
+            type_name(arg),
+            vt_name(declared)
+        ),
+    }
+}
+
+/// A VT_BYREF VARIANT the server writes to `target` through. Typed values are referred
+/// to as such, like VT_BYREF|VT_I4 for a `long*` parameter, while empty ones refer to
+/// the whole VARIANT, which leaves the type to the server. DECIMALs overlay the whole
+/// VARIANT, so they are referred to by its address. Records cannot be referred to, as
+/// VT_BYREF|VT_RECORD takes the record itself rather than a place to write one to.
+///
+/// # Safety
+///
+/// `target` must outlive the VARIANT returned and stay where it is.
+pub(crate) unsafe fn reference_to(target: &mut VARIANT) -> Result<VARIANT> {
+    let reference = match target.vt() {
+        VT_EMPTY | VT_NULL => {
+            *target = VARIANT::default();
+            variant_from_raw(
+                VARENUM(VT_BYREF.0 | VT_VARIANT.0),
+                VARIANT_0_0_0 { pvarVal: target },
+            )
+        }
+        VT_DECIMAL => variant_from_raw(
+            VARENUM(VT_BYREF.0 | VT_DECIMAL.0),
+            VARIANT_0_0_0 {
+                pdecVal: unsafe { &mut target.Anonymous.decVal },
+            },
+        ),
+        VT_RECORD => {
+            let error = Error::new(E_INVALIDARG, "Records cannot be passed by reference");
+
+            return Err(ComError::from(error));
+        }
+        vt => {
+            let data = unsafe { &mut (*target.Anonymous.Anonymous).Anonymous };
+
+            variant_from_raw(
+                VARENUM(VT_BYREF.0 | vt.0),
+                VARIANT_0_0_0 {
+                    byref: data as *mut _ as *mut c_void,
+                },
+            )
+        }
+    };
+
+    Ok(reference)
+}
+
+/// Fills a new record of the type `info` describes with the fields of a JSON object
+unsafe fn record_to_variant(
+    fields: &Map<String, Value>,
+    info: &IRecordInfo,
+    pointer: &str,
+    conversion: &Conversion,
+) -> Result<VARIANT> {
+    unsafe {
+        let record = info.RecordCreate();
+
+        if record.is_null() {
+            return conversion.unsupported(pointer, "Unable to allocate a record".to_string());
+        }
+
+        // Wrap the record right away so it is destroyed together with the VARIANT on failure
+        let variant = variant_from_raw(
+            VT_RECORD,
+            VARIANT_0_0_0 {
+                Anonymous: ManuallyDrop::new(VARIANT_0_0_0_0 {
+                    pvRecord: record,
+                    pRecInfo: ManuallyDrop::new(Some(info.clone())),
+                }),
+            },
+        );
+
+        for (name, value) in fields {
+            let inner = format!("{pointer}/{}", pointer_token(name));
+            let field = convert(value, &inner, conversion)?;
+
+            // The field is copied into the record
+            if let Err(error) = info.PutField(
+                INVOKE_PROPERTYPUT.0 as u32,
+                record,
+                &HSTRING::from(name),
+                &field,
+            ) {
+                return conversion.unsupported(
+                    &inner,
+                    format!("Unable to set the field '{name}' of the record ({error})"),
+                );
+            }
+        }
+
+        Ok(variant)
+    }
+}
+
+/// Reads the fields of a record into a `{"$record": {...}}` object keyed by field name
+unsafe fn record_to_value(record: &VARIANT_0_0_0_0, lcid: u32, objects: &mut ObjectTable) -> Value {
+    unsafe {
+        let Some(info) = (*record.pRecInfo)
+            .as_ref()
+            .filter(|_| !record.pvRecord.is_null())
+        else {
+            return Value::Null;
+        };
+        let mut count = 0;
+
+        if let Err(error) = info.GetFieldNames(&mut count, std::ptr::null_mut()) {
+            warn!("Unable to read the fields of a record: {error}");
+            return Value::Null;
+        }
+
+        let mut names = vec![BSTR::default(); count as usize];
+
+        if let Err(error) = info.GetFieldNames(&mut count, names.as_mut_ptr()) {
+            warn!("Unable to read the fields of a record: {error}");
+            return Value::Null;
+        }
+
+        let fields: Map<String, Value> = names
+            .iter()
+            .take(count as usize)
+            .map(|name| {
+                let value = match info.GetField(record.pvRecord, PCWSTR(name.as_ptr())) {
+                    Ok(field) => variant_to_value(&field, lcid, objects),
+                    Err(error) => {
+                        warn!("Unable to read the field '{name}' of a record: {error}");
+                        Value::Null
+                    }
+                };
+
+                (name.to_string(), value)
+            })
+            .collect();
+
+        json!({ "$record": fields })
+    }
+}
+
+/// The VARIANT types `{"$vt": ..., "value": ...}` hints may name
+const HINTED_TYPES: &[(&str, VARENUM)] = &[
+    ("VT_I1", VT_I1),
+    ("VT_I2", VT_I2),
+    ("VT_I4", VT_I4),
+    ("VT_I8", VT_I8),
+    ("VT_UI1", VT_UI1),
+    ("VT_UI2", VT_UI2),
+    ("VT_UI4", VT_UI4),
+    ("VT_UI8", VT_UI8),
+    ("VT_INT", VT_INT),
+    ("VT_UINT", VT_UINT),
+    ("VT_R4", VT_R4),
+    ("VT_R8", VT_R8),
+    ("VT_CY", VT_CY),
+    ("VT_DATE", VT_DATE),
+    ("VT_BSTR", VT_BSTR),
+    ("VT_BOOL", VT_BOOL),
+    ("VT_ERROR", VT_ERROR),
+    ("VT_DECIMAL", VT_DECIMAL),
+    ("VT_DISPATCH", VT_DISPATCH),
+    ("VT_UNKNOWN", VT_UNKNOWN),
+];
+
+/// The type a value is hinted to be passed as, written `{"$vt": "VT_R4", "value": 1.5}`
+fn type_hint(object: &Map<String, Value>) -> Option<(&str, &Value)> {
+    match (object.len(), object.get("$vt"), object.get("value")) {
+        (2, Some(Value::String(name)), Some(value)) => Some((name, value)),
+        _ => None,
+    }
+}
+
+/// Whether an argument, or the value it passes by reference, has a type hint, which
+/// the declared type of its parameter then does not override
+pub(crate) fn is_hinted(value: &Value) -> bool {
+    match value {
+        Value::Object(object) if object.len() == 1 => object.get("$byref").is_some_and(is_hinted),
+        Value::Object(object) => type_hint(object).is_some(),
+        _ => false,
+    }
+}
+
+/// Converts a value into the VARIANT type named by its hint, in the culture of the
+/// conversion's locale
+unsafe fn hinted_to_variant(
+    name: &str,
+    value: &Value,
+    pointer: &str,
+    conversion: &Conversion,
+) -> Result<VARIANT> {
+    let Some(&(_, vt)) = HINTED_TYPES.iter().find(|(known, _)| *known == name) else {
+        return conversion.unsupported(
+            &format!("{pointer}/$vt"),
+            format!("Unknown VARIANT type '{name}'"),
+        );
+    };
+    let variant = unsafe { convert(value, &format!("{pointer}/value"), conversion)? };
+
+    if variant.vt() == vt {
+        return Ok(variant);
+    }
+
+    let mut converted = VARIANT::default();
+
+    match unsafe {
+        VariantChangeTypeEx(
+            &mut converted,
+            &variant,
+            culture(conversion.lcid),
+            VARIANT_ALPHABOOL,
+            vt,
+        )
+    } {
+        Ok(()) => Ok(converted),
+        Err(error) => conversion.unsupported(
+            pointer,
+            format!(
+                "Unable to convert {} to {name} ({error})",
+                type_name(&variant)
+            ),
+        ),
+    }
+}
+
+/// Converts a tagged JSON object such as `{"$date": "2024-06-01T10:30:00"}` into the
+/// VARIANT type named by its tag. Returns `None` when the object is not a known tag.
+/// Dates other than ISO 8601 ones and numbers written as strings are parsed in the
+/// culture of the conversion's locale.
+fn tagged_to_variant(
+    object: &Map<String, Value>,
+    pointer: &str,
+    conversion: &Conversion,
+) -> Result<Option<VARIANT>> {
+    if let Some((name, value)) = type_hint(object) {
+        return unsafe { hinted_to_variant(name, value, pointer, conversion) }.map(Some);
+    }
+
+    let Some((tag, value)) = object.iter().next().filter(|_| object.len() == 1) else {
+        return Ok(None);
+    };
+    let inner = format!("{pointer}/{}", pointer_token(tag));
+
+    let variant = match (tag.as_str(), value) {
+        ("$date", Value::String(text)) => {
+            let date = date::parse_iso8601(text).or_else(|| {
+                unsafe { VarDateFromStr(&BSTR::from(text), culture(conversion.lcid), 0) }.ok()
+            });
+
+            match date {
+                Some(date) => variant_from_raw(VT_DATE, VARIANT_0_0_0 { date }),
+                None => conversion.unsupported(&inner, format!("Invalid date '{text}'"))?,
+            }
+        }
+        ("$number", Value::String(text)) => match special_number(text) {
+            Some(number) => VARIANT::from(number),
+            None => match unsafe { VarR8FromStr(&BSTR::from(text), culture(conversion.lcid), 0) } {
+                Ok(number) => VARIANT::from(number),
+                Err(error) => {
+                    conversion.unsupported(&inner, format!("Invalid number '{text}' ({error})"))?
+                }
+            },
+        },
+        ("$currency", value) => match currency_to_variant(value) {
+            Some(variant) => variant,
+            None => conversion.unsupported(
+                &inner,
+                format!(
+                    "Invalid currency value {value} (expected at most {CURRENCY_SCALE} \
+                    decimals)"
+                ),
+            )?,
+        },
+        ("$decimal", value) => match decimal_to_variant(value) {
+            Some(variant) => variant,
+            None => conversion.unsupported(
+                &inner,
+                format!(
+                    "Invalid decimal value {value} (expected at most 96 bits and \
+                    {DECIMAL_MAX_SCALE} decimals)"
+                ),
+            )?,
+        },
+        ("$empty", Value::Bool(true)) => VARIANT::default(),
+        ("$error", value) => match error_code(value) {
+            Some(scode) => variant_from_raw(VT_ERROR, VARIANT_0_0_0 { scode }),
+            None => conversion.unsupported(
+                &inner,
+                format!("Invalid error code {value} (expected an HRESULT like \"0x800A07FA\")"),
+            )?,
+        },
+        // How an optional parameter is left out before others that are passed
+        ("$missing", Value::Bool(true)) => variant_from_raw(
+            VT_ERROR,
+            VARIANT_0_0_0 {
+                scode: DISP_E_PARAMNOTFOUND.0,
+            },
+        ),
+        // The secret itself stays out of the error
+        ("$secret", value) => unsafe { convert(value, &inner, conversion) }.map_err(|_| {
+            ComError::from(Error::new(
+                E_INVALIDARG,
+                format!("Unsupported value at {inner}: the secret has no VARIANT form"),
+            ))
+            .at(&inner)
+        })?,
+        // Only the arguments of calls are passed by reference, values go as they are
+        ("$byref", value) => unsafe { convert(value, &inner, conversion)? },
+        ("$ref", Value::String(name)) => match conversion.objects.get(name) {
+            Some(object) => VARIANT::from(object.clone()),
+            None => match conversion.objects.get_unknown(name) {
+                Some(object) => VARIANT::from(object.clone()),
+                None => conversion.unsupported(&inner, format!("Unknown object '{name}'"))?,
+            },
+        },
+        ("$record", Value::Object(fields)) => match conversion.records.get(pointer) {
+            Some(info) => unsafe { record_to_variant(fields, info, &inner, conversion)? },
+            None => conversion.unsupported(
+                pointer,
+                "No record type is known for the value, records can only be passed to \
+                parameters declared as a structure"
+                    .to_string(),
+            )?,
+        },
+        ("$bstr", Value::String(encoded)) => match BASE64.decode(encoded) {
+            Ok(bytes) => VARIANT::from(unsafe { SysAllocStringByteLen(Some(&bytes)) }),
+            Err(error) => conversion.unsupported(
+                &inner,
+                format!("Invalid $bstr value (invalid base64: {error})"),
+            )?,
+        },
+        ("$bytes", Value::String(encoded)) => {
+            let variant = BASE64
+                .decode(encoded)
+                .map_err(|error| format!("invalid base64: {error}"))
+                .and_then(|bytes| {
+                    unsafe { bytes_to_variant(&bytes) }
+                        .ok_or_else(|| "unable to allocate a SAFEARRAY".to_string())
+                });
+
+            match variant {
+                Ok(variant) => variant,
+                Err(error) => {
+                    conversion.unsupported(&inner, format!("Invalid $bytes value ({error})"))?
+                }
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(variant))
+}
+
+/// Converts the JSON value found at `pointer`, which errors name, into a VARIANT
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub(crate) unsafe fn convert(
+    value: &Value,
+    pointer: &str,
+    conversion: &Conversion,
+) -> Result<VARIANT> {
+    let variant = match value {
+        Value::String(s) => VARIANT::from(BSTR::from(s.as_str())),
+        Value::Number(n) => {
+            // Prioritize integer conversion if possible
+            if let Some(i) = n.as_i64() {
+                // Use VT_I4 where it fits since it is what most servers expect,
+                // and fall back to VT_I8 for larger magnitudes
+                i32::try_from(i).map_or_else(|_| VARIANT::from(i), VARIANT::from)
+            } else if let Some(u) = n.as_u64() {
+                // Only values above i64::MAX end up here
+                VARIANT::from(u)
+            } else if let Some(f) = n.as_f64().filter(|_| n.is_f64()) {
+                // Handle floating-point numbers
+                VARIANT::from(f)
+            } else {
+                // Fallback for numbers that don't fit i64 or f64 (e.g., very large BigInts)
+                conversion.unsupported(pointer, format!("Unsupported number {n}"))?
+            }
+        }
+        Value::Bool(b) => VARIANT::from(*b),
+        // A database-style NULL; use {"$empty": true} for an uninitialized VARIANT
+        Value::Null => variant_from_raw(VT_NULL, VARIANT_0_0_0::default()),
+        Value::Array(items) => unsafe { array_to_variant(items, pointer, conversion)? },
+        Value::Object(object) => match tagged_to_variant(object, pointer, conversion)? {
+            Some(variant) => variant,
+            None => conversion.unsupported(
+                pointer,
+                "JSON objects other than the tagged values have no VARIANT form".to_string(),
+            )?,
+        },
+    };
+
+    Ok(variant)
+}
+
+/// Converts a JSON value into a VARIANT. Objects referenced through `{"$ref": ...}` are
+/// looked up in `objects`. Values without a faithful VARIANT form are passed as
+/// VT_EMPTY.
+///
+/// # Safety
+///
+/// COM must have been initialized on the current thread.
+pub unsafe fn value_to_variant(value: &Value, lcid: u32, objects: &ObjectTable) -> VARIANT {
+    let conversion = Conversion {
+        lcid,
+        objects,
+        strict: false,
+        records: &HashMap::new(),
+    };
+
+    unsafe { convert(value, "", &conversion) }.unwrap_or_default()
+}
+
+/// A BSTR as JSON, embedded NULs included: a string when it holds valid UTF-16,
+/// otherwise its exact content as `{"$bstr": "<base64>"}`, such as for unpaired
+/// surrogates or strings of an odd number of bytes
+fn bstr_to_value(bstr: &BSTR) -> Value {
+    let length = unsafe { SysStringByteLen(bstr) } as usize;
+
+    if length.is_multiple_of(2)
+        && let Ok(text) = String::from_utf16(bstr)
+    {
+        return Value::String(text);
+    }
+
+    // The pointer itself, as BSTR hides the strings it takes for empty
+    let data: *const u8 = unsafe { std::mem::transmute_copy(bstr) };
+    let bytes = match length {
+        0 => &[][..],
+        _ => unsafe { std::slice::from_raw_parts(data, length) },
+    };
+
+    json!({ "$bstr": BASE64.encode(bytes) })
+}
+
+/// Copies raw bytes into a `VT_ARRAY | VT_UI1` SAFEARRAY
+unsafe fn bytes_to_variant(bytes: &[u8]) -> Option<VARIANT> {
+    unsafe {
+        let psa = SafeArrayCreateVector(VT_UI1, 0, bytes.len() as u32);
+
+        if psa.is_null() {
+            return None;
+        }
+
+        let array = variant_from_raw(
+            VARENUM(VT_ARRAY.0 | VT_UI1.0),
+            VARIANT_0_0_0 { parray: psa },
+        );
+        let mut data = std::ptr::null_mut();
+
+        SafeArrayAccessData(psa, &mut data).ok()?;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+        SafeArrayUnaccessData(psa).ok()?;
+
+        Some(array)
+    }
+}
+
+/// Reads the contents of a one-dimensional `VT_UI1` SAFEARRAY
+unsafe fn safearray_to_bytes(psa: *const SAFEARRAY) -> Option<Vec<u8>> {
+    unsafe {
+        let lower = SafeArrayGetLBound(psa, 1).ok()?;
+        let upper = SafeArrayGetUBound(psa, 1).ok()?;
+        let len = (i64::from(upper) - i64::from(lower) + 1).max(0) as usize;
+        let mut data = std::ptr::null_mut();
+
+        SafeArrayAccessData(psa, &mut data).ok()?;
+        let bytes = std::slice::from_raw_parts(data as *const u8, len).to_vec();
+        SafeArrayUnaccessData(psa).ok()?;
+
+        Some(bytes)
+    }
+}
+
+/// Reads the element of a SAFEARRAY at `indices`, given from the leftmost dimension to
+/// the rightmost one
+unsafe fn safearray_element(
+    psa: *const SAFEARRAY,
+    element_vt: VARENUM,
+    indices: &[i32],
+    lcid: u32,
+    objects: &mut ObjectTable,
+) -> Value {
+    unsafe {
+        // VARIANT elements are copied as a whole, and so are DECIMALs, which overlay
+        // the whole VARIANT. Anything else goes into the data union of a VARIANT of
+        // the element type, which then owns the copy.
+        let mut element = match element_vt {
+            VT_VARIANT | VT_DECIMAL => VARIANT::default(),
+            _ => variant_from_raw(element_vt, VARIANT_0_0_0::default()),
+        };
+        let element_ptr: *mut c_void = match element_vt {
+            VT_VARIANT => &mut element as *mut VARIANT as *mut c_void,
+            VT_DECIMAL => &mut element.Anonymous.decVal as *mut DECIMAL as *mut c_void,
+            _ => &mut (*element.Anonymous.Anonymous).Anonymous as *mut VARIANT_0_0_0 as *mut c_void,
+        };
+
+        match SafeArrayGetElement(psa, indices.as_ptr(), element_ptr) {
+            Ok(()) => {
+                // The reserved word of the DECIMAL copied over the type
+                if element_vt == VT_DECIMAL {
+                    (*element.Anonymous.Anonymous).vt = VT_DECIMAL;
+                }
+
+                variant_to_value(&element, lcid, objects)
+            }
+            Err(error) => {
+                warn!("Unable to read array element {indices:?}: {error}");
+                Value::Null
+            }
+        }
+    }
+}
+
+/// Reads the elements of the dimensions of a SAFEARRAY from the one after `indices`
+/// on, as arrays nested in row-major order
+unsafe fn safearray_dimension(
+    psa: *const SAFEARRAY,
+    element_vt: VARENUM,
+    bounds: &[(i32, i32)],
+    indices: &mut Vec<i32>,
+    lcid: u32,
+    objects: &mut ObjectTable,
+) -> Value {
+    let Some(&(lower, upper)) = bounds.get(indices.len()) else {
+        return unsafe { safearray_element(psa, element_vt, indices, lcid, objects) };
+    };
+    let mut items = Vec::new();
+
+    for index in lower..=upper {
+        indices.push(index);
+        items.push(unsafe { safearray_dimension(psa, element_vt, bounds, indices, lcid, objects) });
+        indices.pop();
+    }
+
+    Value::Array(items)
+}
+
+/// Converts a SAFEARRAY with elements of the given type into a JSON array, nesting an
+/// array per row for arrays of more than one dimension, like the values of an Excel
+/// range. The lower bounds of the dimensions are dropped.
+unsafe fn safearray_to_value(
+    psa: *const SAFEARRAY,
+    element_vt: VARENUM,
+    lcid: u32,
+    objects: &mut ObjectTable,
+) -> Value {
+    unsafe {
+        if psa.is_null() {
+            return Value::Null;
+        }
+
+        let dims = SafeArrayGetDim(psa);
+
+        if dims == 0 {
+            return Value::Array(Vec::new());
+        }
+
+        // Records would have to be read into memory of their size
+        if element_vt == VT_RECORD {
+            warn!("Unable to convert a SAFEARRAY of records to JSON");
+            return Value::Null;
+        }
+
+        if element_vt == VT_UI1 && dims == 1 {
+            // Byte arrays are binary payloads rather than lists of numbers
+            return match safearray_to_bytes(psa) {
+                Some(bytes) => json!({ "$bytes": BASE64.encode(bytes) }),
+                None => {
+                    warn!("Unable to read SAFEARRAY of bytes");
+                    Value::Null
+                }
+            };
+        }
+
+        // Dimension 1 is the leftmost one
+        let bounds: Option<Vec<(i32, i32)>> = (1..=dims)
+            .map(|dimension| {
+                Some((
+                    SafeArrayGetLBound(psa, dimension).ok()?,
+                    SafeArrayGetUBound(psa, dimension).ok()?,
+                ))
+            })
+            .collect();
+        let Some(bounds) = bounds else {
+            warn!("Unable to read SAFEARRAY bounds");
+            return Value::Null;
+        };
+
+        safearray_dimension(psa, element_vt, &bounds, &mut Vec::new(), lcid, objects)
+    }
+}
+
+/// Converts a VARIANT returned by COM into the closest matching JSON value.
+/// Returned objects are registered in `objects` and represented by their handle, and
+/// the types JSON lacks a form for are formatted as strings in the culture of `lcid`.
+///
+/// # Safety
+///
+/// `variant` must be a valid VARIANT, with pointers matching its type.
+pub unsafe fn variant_to_value(variant: &VARIANT, lcid: u32, objects: &mut ObjectTable) -> Value {
+    if variant.vt().0 & VT_BYREF.0 != 0 {
+        // Dereference by-ref values into a local copy before converting
+        let mut dereferenced = VARIANT::default();
+
+        return match unsafe { VariantCopyInd(&mut dereferenced, variant) } {
+            Ok(()) => unsafe { variant_to_value(&dereferenced, lcid, objects) },
+            Err(error) => {
+                warn!("Unable to dereference VARIANT: {error}");
+                Value::Null
+            }
+        };
+    }
+
+    unsafe {
+        let data = &variant.Anonymous.Anonymous.Anonymous;
+
+        if variant.vt().0 & VT_ARRAY.0 != 0 {
+            let element_vt = VARENUM(variant.vt().0 & VT_TYPEMASK.0);
+
+            return safearray_to_value(data.parray, element_vt, lcid, objects);
+        }
+
+        match variant.vt() {
+            VT_EMPTY | VT_NULL => Value::Null,
+            VT_BOOL => Value::Bool(data.boolVal.as_bool()),
+            VT_I1 => Value::from(data.cVal),
+            VT_UI1 => Value::from(data.bVal),
+            VT_I2 => Value::from(data.iVal),
+            VT_UI2 => Value::from(data.uiVal),
+            VT_I4 | VT_INT => Value::from(data.lVal),
+            VT_UI4 | VT_UINT => Value::from(data.ulVal),
+            VT_I8 => Value::from(data.llVal),
+            VT_UI8 => Value::from(data.ullVal),
+            // The shortest decimal that reads back as the same float, 0.1 rather than
+            // 0.10000000149011612
+            VT_R4 => number_to_value(data.fltVal.to_string().parse().unwrap_or_default()),
+            VT_R8 => number_to_value(data.dblVal),
+            VT_BSTR => bstr_to_value(&data.bstrVal),
+            VT_DISPATCH => match IDispatch::try_from(variant) {
+                Ok(object) => Value::String(objects.register(object)),
+                Err(_) => Value::Null,
+            },
+            // Objects that turn out to have IDispatch are registered as such
+            VT_UNKNOWN => match IUnknown::try_from(variant) {
+                Ok(object) => Value::String(match object.cast::<IDispatch>() {
+                    Ok(dispatch) => objects.register(dispatch),
+                    Err(_) => objects.register_unknown(object),
+                }),
+                Err(_) => Value::Null,
+            },
+            VT_RECORD => record_to_value(&data.Anonymous, lcid, objects),
+            VT_DATE => match date::format_iso8601(data.date) {
+                Some(text) => json!({ "$date": text }),
+                None => Value::Null,
+            },
+            VT_DECIMAL => decimal_to_value(&variant.Anonymous.decVal),
+            VT_ERROR if data.scode == DISP_E_PARAMNOTFOUND.0 => json!({ "$missing": true }),
+            // Such as the #N/A of an Excel cell, 0x800A07FA
+            VT_ERROR | VT_HRESULT => json!({ "$error": format!("{:#010X}", data.scode as u32) }),
+            VT_VOID => Value::Null,
+            VT_CY => json!({
+                "$currency": numeric::format_decimal(data.cyVal.int64.into(), CURRENCY_SCALE)
+            }),
+            vt => {
+                // Let OLE Automation coerce anything else into a string
+                let mut coerced = VARIANT::default();
+
+                match VariantChangeTypeEx(
+                    &mut coerced,
+                    variant,
+                    culture(lcid),
+                    VAR_CHANGE_FLAGS(0),
+                    VT_BSTR,
+                ) {
+                    Ok(()) => {
+                        Value::String(coerced.Anonymous.Anonymous.Anonymous.bstrVal.to_string())
+                    }
+                    Err(error) => {
+                        warn!(
+                            "Unable to convert VARIANT of type {} to JSON: {error}",
+                            vt_name(vt)
+                        );
+                        Value::Null
+                    }
+                }
+            }
+        }
+    }
+}
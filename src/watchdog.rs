@@ -0,0 +1,92 @@
+//! Watchdog that cancels a step whose COM calls outlive its timeout, so a hung server
+//! cannot hang the CLI forever.
+
+use crate::error::ComError;
+use serde_json::{Value, json};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::error;
+use windows::Win32::Foundation::ERROR_TIMEOUT;
+use windows::Win32::System::Com::CoCancelCall;
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::core::Error;
+
+/// How long a cancelled call gets to return before the process is aborted
+const ABORT_GRACE: Duration = Duration::from_secs(5);
+
+type AbortHandler = Box<dyn Fn(&Value) + Send + Sync>;
+
+static ABORT_HANDLER: OnceLock<AbortHandler> = OnceLock::new();
+
+/// Sets how the result of a request is written when the process has to end because one
+/// of its calls could not be cancelled. Without a handler only an error message is
+/// written, to stderr. Only the first handler set is used.
+pub fn set_abort_handler(handler: impl Fn(&Value) + Send + Sync + 'static) {
+    let _ = ABORT_HANDLER.set(Box::new(handler));
+}
+
+/// A timer armed on the thread making the calls
+pub struct Watchdog {
+    done: Sender<()>,
+    thread: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    /// Starts watching the calls the current thread makes from now on. Call
+    /// cancellation must have been enabled on the thread with `CoEnableCallCancellation`.
+    pub fn arm(timeout: Duration) -> Self {
+        let thread_id = unsafe { GetCurrentThreadId() };
+        let (done, receiver) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            if receiver.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                return false;
+            }
+
+            // Makes a pending call to an out-of-process server fail with
+            // RPC_E_CALL_CANCELED. In-process calls cannot be interrupted this way.
+            unsafe {
+                let _ = CoCancelCall(thread_id, 0);
+            }
+
+            if receiver.recv_timeout(ABORT_GRACE) == Err(RecvTimeoutError::Timeout) {
+                abort(timeout);
+            }
+
+            true
+        });
+
+        Self { done, thread }
+    }
+
+    /// Stops the timer, returning whether it expired
+    pub fn disarm(self) -> bool {
+        drop(self.done);
+
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+/// The error reported for a step that timed out
+pub fn timeout_error(timeout: Duration) -> ComError {
+    Error::new(
+        ERROR_TIMEOUT.to_hresult(),
+        format!("Timed out after {} ms", timeout.as_millis()),
+    )
+    .into()
+}
+
+/// Ends the process when a call could not be cancelled, since the thread making it is
+/// stuck inside the server
+fn abort(timeout: Duration) -> ! {
+    let error = timeout_error(timeout);
+
+    error!("The call could not be cancelled, aborting");
+
+    if let Some(handler) = ABORT_HANDLER.get() {
+        handler(&json!({ "ok": false, "error": error.report(None) }));
+    }
+
+    std::process::exit(error.exit_status() as i32);
+}
@@ -0,0 +1,225 @@
+//! Callbacks of subscriptions with a `callback_url`, to which their events are POSTed
+//! as JSON, so that services not connected to the session can react to them. Events
+//! are delivered in the order they were fired, from a thread of their own so that a
+//! slow service does not hold up the steps.
+
+use crate::error::Result;
+use serde_json::Value;
+use std::ffi::c_void;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use tracing::{debug, warn};
+use windows::Win32::Foundation::E_INVALIDARG;
+use windows::Win32::Networking::WinHttp::{
+    URL_COMPONENTS, WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, WINHTTP_FLAG_SECURE,
+    WINHTTP_INTERNET_SCHEME_HTTPS, WINHTTP_OPEN_REQUEST_FLAGS, WINHTTP_QUERY_FLAG_NUMBER,
+    WINHTTP_QUERY_STATUS_CODE, WinHttpCloseHandle, WinHttpConnect, WinHttpCrackUrl, WinHttpOpen,
+    WinHttpOpenRequest, WinHttpQueryHeaders, WinHttpReceiveResponse, WinHttpSendRequest,
+    WinHttpSetTimeouts,
+};
+use windows::core::{Error, HSTRING, PCWSTR, w};
+
+/// How long a service gets to be resolved, connected to, sent an event and to
+/// answer, each
+const TIMEOUT_MS: i32 = 10_000;
+
+/// Where the events of a subscription are POSTed
+#[derive(Clone)]
+pub(crate) struct Endpoint {
+    url: String,
+    host: String,
+    port: u16,
+    /// Path and query of the URL
+    path: String,
+    secure: bool,
+}
+
+impl Endpoint {
+    /// Parses an `http://` or `https://` URL
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let wide: Vec<u16> = url.encode_utf16().collect();
+        // Lengths of -1 ask for the parts as pointers into the URL
+        let mut components = URL_COMPONENTS {
+            dwStructSize: size_of::<URL_COMPONENTS>() as u32,
+            dwSchemeLength: u32::MAX,
+            dwHostNameLength: u32::MAX,
+            dwUrlPathLength: u32::MAX,
+            dwExtraInfoLength: u32::MAX,
+            ..Default::default()
+        };
+
+        unsafe { WinHttpCrackUrl(&wide, 0, &mut components) }.map_err(|error| {
+            Error::new(
+                E_INVALIDARG,
+                format!("Invalid callback URL '{url}', expected http:// or https://: {error}"),
+            )
+        })?;
+
+        let part = |text: windows::core::PWSTR, length: u32| match text.is_null() {
+            true => String::new(),
+            false => String::from_utf16_lossy(unsafe {
+                std::slice::from_raw_parts(text.0, length as usize)
+            }),
+        };
+        let path = part(components.lpszUrlPath, components.dwUrlPathLength)
+            + &part(components.lpszExtraInfo, components.dwExtraInfoLength);
+
+        Ok(Self {
+            url: url.to_string(),
+            host: part(components.lpszHostName, components.dwHostNameLength),
+            port: components.nPort,
+            path: if path.is_empty() {
+                "/".to_string()
+            } else {
+                path
+            },
+            secure: components.nScheme == WINHTTP_INTERNET_SCHEME_HTTPS,
+        })
+    }
+}
+
+/// A WinHTTP handle, closed when dropped
+struct Handle(*mut c_void);
+
+impl Handle {
+    fn new(handle: *mut c_void) -> windows::core::Result<Self> {
+        match handle.is_null() {
+            true => Err(Error::from_win32()),
+            false => Ok(Self(handle)),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = WinHttpCloseHandle(self.0);
+        }
+    }
+}
+
+/// POSTs a JSON body and returns the status code of the response
+fn post(session: &Handle, endpoint: &Endpoint, body: &str) -> windows::core::Result<u32> {
+    let flags = match endpoint.secure {
+        true => WINHTTP_FLAG_SECURE,
+        false => WINHTTP_OPEN_REQUEST_FLAGS(0),
+    };
+    let headers: Vec<u16> = "Content-Type: application/json\r\n"
+        .encode_utf16()
+        .collect();
+    let length = body.len() as u32;
+
+    unsafe {
+        let connection = Handle::new(WinHttpConnect(
+            session.0,
+            &HSTRING::from(&endpoint.host),
+            endpoint.port,
+            0,
+        ))?;
+        let request = Handle::new(WinHttpOpenRequest(
+            connection.0,
+            w!("POST"),
+            &HSTRING::from(&endpoint.path),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            std::ptr::null(),
+            flags,
+        ))?;
+
+        WinHttpSendRequest(
+            request.0,
+            Some(&headers),
+            Some(body.as_ptr() as *const c_void),
+            length,
+            length,
+            0,
+        )?;
+        WinHttpReceiveResponse(request.0, std::ptr::null_mut())?;
+
+        let mut status = 0u32;
+        let mut size = size_of::<u32>() as u32;
+
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            PCWSTR::null(),
+            Some(&mut status as *mut u32 as *mut c_void),
+            &mut size,
+            std::ptr::null_mut(),
+        )?;
+
+        Ok(status)
+    }
+}
+
+/// Delivers events to the endpoints of their subscriptions until the session ends
+fn deliver(events: mpsc::Receiver<(Endpoint, Value)>) {
+    let session = unsafe {
+        Handle::new(WinHttpOpen(
+            w!("win32-com-cli"),
+            WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            0,
+        ))
+    };
+    let session = match session {
+        Ok(session) => session,
+        Err(error) => {
+            warn!("Failed to open a WinHTTP session, events will not be POSTed: {error}");
+            return;
+        }
+    };
+
+    unsafe {
+        let _ = WinHttpSetTimeouts(session.0, TIMEOUT_MS, TIMEOUT_MS, TIMEOUT_MS, TIMEOUT_MS);
+    }
+
+    for (endpoint, event) in events {
+        match post(&session, &endpoint, &event.to_string()) {
+            Ok(status) if (200..300).contains(&status) => {
+                debug!("Posted event {} to {}", event["event"], endpoint.url);
+            }
+            Ok(status) => warn!(
+                "{} answered the event {} with status {status}",
+                endpoint.url, event["event"]
+            ),
+            Err(error) => warn!(
+                "Failed to post the event {} to {}: {error}",
+                event["event"], endpoint.url
+            ),
+        }
+    }
+}
+
+/// The thread POSTing the events of a session, started with its first event. Events
+/// still waiting are delivered before the session ends.
+#[derive(Default)]
+pub(crate) struct Webhooks {
+    sender: Option<Sender<(Endpoint, Value)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Webhooks {
+    /// Queues an event for delivery to an endpoint
+    pub(crate) fn post(&mut self, endpoint: &Endpoint, event: &Value) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+
+            self.thread = Some(thread::spawn(move || deliver(receiver)));
+            sender
+        });
+
+        let _ = sender.send((endpoint.clone(), event.clone()));
+    }
+}
+
+impl Drop for Webhooks {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
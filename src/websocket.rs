@@ -0,0 +1,97 @@
+//! WebSocket transport for serve mode. Every text or binary message carries exactly one
+//! JSON-RPC message, and the connection stays open in both directions so that the
+//! server pushes the events fired while the client is idle as soon as they are. Every
+//! client gets its own thread and session.
+
+use crate::serve::{Connection, IDLE_INTERVAL, Received, serve};
+use serde_json::Value;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use tracing::warn;
+use tungstenite::{Error, Message, WebSocket};
+use win32_com_cli::session::RunOptions;
+
+struct WebSocketConnection {
+    socket: WebSocket<TcpStream>,
+}
+
+impl Connection for WebSocketConnection {
+    fn receive(&mut self) -> Option<Received> {
+        loop {
+            // A read timing out keeps what it read of a message for the next one
+            let message = match self.socket.read() {
+                Ok(message) => message,
+                Err(Error::Io(error))
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Some(Received::Idle);
+                }
+                // The client closed the connection, or it broke
+                Err(_) => return None,
+            };
+            let parsed = match &message {
+                Message::Text(text) => serde_json::from_str(text),
+                Message::Binary(bytes) => serde_json::from_slice(bytes),
+                Message::Close(_) => return None,
+                // Pings are answered by the socket itself
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+            };
+
+            return Some(Received::Message(parsed.map_err(|error| error.to_string())));
+        }
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        self.socket
+            .send(Message::text(serde_json::to_string(message)?))
+            .map_err(io::Error::other)
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        // Tells a client still connected that the session is over
+        let _ = self.socket.close(None);
+        let _ = self.socket.flush();
+    }
+}
+
+/// Accepts WebSocket clients on a TCP address such as `127.0.0.1:7862` until the
+/// process is ended. There is no authentication, so the address should be a loopback
+/// one.
+pub fn listen(address: &str, options: &RunOptions) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to accept a client: {error}");
+                continue;
+            }
+        };
+        let options = options.clone();
+
+        let _ = stream.set_nodelay(true);
+
+        // The handshake happens on the client's thread, so a slow client cannot hold up
+        // the others
+        thread::spawn(move || match tungstenite::accept(stream) {
+            Ok(socket) => {
+                // Lets the session dispatch events between the messages of the client
+                if let Err(error) = socket.get_ref().set_read_timeout(Some(IDLE_INTERVAL)) {
+                    warn!("Failed to set the read timeout of a client: {error}");
+                }
+
+                serve(&mut WebSocketConnection { socket }, &options)
+            }
+            Err(error) => warn!("Failed the WebSocket handshake: {error}"),
+        });
+    }
+
+    Ok(())
+}
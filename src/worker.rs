@@ -0,0 +1,458 @@
+//! `--isolate`, which runs requests and sessions in worker processes, so that an
+//! in-process server crashing or leaking takes its worker down rather than the CLI or
+//! the server answering the other clients. Every request gets a worker of its own that
+//! ends with it, and every session of `--serve` and `--listen` one that lives as long
+//! as the session, started again for its next step after it crashed.
+//!
+//! Session workers are run with the hidden `--worker` flag. They read the options of
+//! the session and then one step per line on stdin, and write the events fired and
+//! one reply per step on stdout, all as JSON.
+
+use crate::format::Format;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+use win32_com_cli::error::{ErrorReport, ExitStatus};
+use win32_com_cli::request::Output;
+use win32_com_cli::session::{RunOptions, Step};
+use win32_com_cli::{ComError, ComSession, set_abort_handler};
+
+/// Code of the steps and requests whose worker crashed
+pub const WORKER_CRASHED: &str = "WORKER_CRASHED";
+
+/// Code of the steps and requests for which no worker could be started
+pub const WORKER_UNAVAILABLE: &str = "WORKER_UNAVAILABLE";
+
+/// How long an idle worker waits for a step before dispatching the events fired
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Arguments workers are started with, set once isolation is turned on
+static ARGUMENTS: OnceLock<Vec<OsString>> = OnceLock::new();
+
+/// Runs requests and sessions in workers from now on, which are started with the
+/// arguments carried over from the command line of the CLI, such as its verbosity
+pub fn isolate(arguments: Vec<OsString>) {
+    let _ = ARGUMENTS.set(arguments);
+}
+
+/// Whether requests and sessions run in workers
+pub fn isolated() -> bool {
+    ARGUMENTS.get().is_some()
+}
+
+/// How a step failed, whether it ran in this process or in a worker
+pub struct StepFailure {
+    pub status: ExitStatus,
+    /// The error as written for people, with the hint of its HRESULT
+    pub message: String,
+    pub report: Box<ErrorReport>,
+}
+
+impl From<ComError> for StepFailure {
+    fn from(error: ComError) -> Self {
+        Self {
+            status: error.exit_status(),
+            message: error.to_string(),
+            report: Box::new(error.report(None)),
+        }
+    }
+}
+
+impl StepFailure {
+    fn worker(code: &str, message: String, hint: &str) -> Self {
+        Self {
+            status: ExitStatus::Failure,
+            report: Box::new(ErrorReport {
+                code: code.to_string(),
+                hint: Some(hint.to_string()),
+                ..ErrorReport::invalid_request(message.clone())
+            }),
+            message,
+        }
+    }
+
+    fn unavailable(error: io::Error) -> Self {
+        Self::worker(
+            WORKER_UNAVAILABLE,
+            format!("Failed to start a worker process: {error}"),
+            "Check that the executable of the CLI can still be started.",
+        )
+    }
+
+    fn crashed(child: &mut Child) -> Self {
+        let message = match child.wait().ok().and_then(|status| status.code()) {
+            Some(code) => {
+                format!(
+                    "The worker process crashed with exit code {:#010X}",
+                    code as u32
+                )
+            }
+            None => "The worker process crashed".to_string(),
+        };
+
+        Self::worker(
+            WORKER_CRASHED,
+            message,
+            "An in-process server probably crashed its worker. The objects of the session \
+            are lost, and the step may have been partly carried out.",
+        )
+    }
+}
+
+/// Starts the executable of the CLI with the arguments of workers followed by `mode`
+fn spawn(mode: &[&str]) -> io::Result<Child> {
+    Command::new(std::env::current_exe()?)
+        .args(ARGUMENTS.get().into_iter().flatten())
+        .args(mode)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+}
+
+/// The result of a request that ended without one, keeping its version
+fn failed(request: &Value, failure: StepFailure) -> Output {
+    let mut output = json!({ "ok": false, "error": failure.report });
+
+    if let Some(version) = request.get("version").and_then(Value::as_str) {
+        output["version"] = json!(version);
+    }
+
+    (output, failure.status)
+}
+
+/// Runs a request, or plans it with `validate`, in a worker of its own. The events it
+/// reports are written to stdout in `format` as they arrive, like those of requests
+/// run in this process.
+pub fn run_request(request: Value, validate: bool, format: Format) -> Output {
+    let mode: &[&str] = match validate {
+        false => &["run", "--stdin"],
+        true => &["run", "--stdin", "--validate"],
+    };
+    let mut child = match spawn(mode) {
+        Ok(child) => child,
+        Err(error) => return failed(&request, StepFailure::unavailable(error)),
+    };
+
+    // A worker crashing before it read the request is noticed by its missing result
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{request}");
+    }
+
+    let mut result = None;
+
+    for line in BufReader::new(child.stdout.take().expect("stdout is piped")).lines() {
+        let Ok(document) = line.map(|line| serde_json::from_str::<Value>(&line)) else {
+            break;
+        };
+
+        match document {
+            // Results are told from events by their `ok`
+            Ok(document) if document.get("ok").is_some() => result = Some(document),
+            Ok(event) => {
+                let _ = format.write(&mut io::stdout().lock(), &event);
+            }
+            Err(error) => warn!("Ignoring a line the worker wrote that is not JSON: {error}"),
+        }
+    }
+
+    let Some(result) = result else {
+        return failed(&request, StepFailure::crashed(&mut child));
+    };
+    let status = child.wait().ok().and_then(|status| status.code());
+    let status = status.and_then(ExitStatus::from_code).unwrap_or_else(|| {
+        warn!("The worker process crashed after writing the result of the request");
+        ExitStatus::Failure
+    });
+
+    (result, status)
+}
+
+/// What a worker writes on stdout
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Event {
+        event: Value,
+    },
+    Reply {
+        ok: bool,
+        #[serde(default)]
+        result: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure: Option<Failure>,
+    },
+}
+
+/// A step that failed in a worker
+#[derive(Serialize, Deserialize)]
+struct Failure {
+    status: u8,
+    message: String,
+    error: Box<ErrorReport>,
+}
+
+/// A worker running the steps of a session
+pub struct Worker {
+    child: Child,
+    /// Closed when the worker is dropped, which ends it
+    stdin: Option<ChildStdin>,
+    /// The messages the worker writes, read on a thread of their own so that the events
+    /// it fires while idle are taken without waiting for a reply
+    messages: Receiver<Message>,
+}
+
+impl Worker {
+    fn start(options: &RunOptions) -> io::Result<Self> {
+        let mut child = spawn(&["--worker"])?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let (sender, messages) = mpsc::channel();
+
+        writeln!(stdin, "{}", serde_json::to_string(options)?)?;
+
+        // Ends with the output of the worker
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => {
+                        warn!("Ignoring a line the worker wrote that is not JSON: {error}")
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin: Some(stdin),
+            messages,
+        })
+    }
+
+    /// Takes the events the worker fired since its last reply
+    fn idle_events(&mut self) -> Vec<Value> {
+        self.messages
+            .try_iter()
+            .filter_map(|message| match message {
+                Message::Event { event } => Some(event),
+                Message::Reply { .. } => {
+                    warn!("Ignoring a reply of the worker to no step");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Runs a step, collecting the events fired until its reply, or `None` once the
+    /// worker is gone
+    fn execute(
+        &mut self,
+        step: &Step,
+        events: &mut Vec<Value>,
+    ) -> Option<std::result::Result<Value, StepFailure>> {
+        let step = serde_json::to_string(step).expect("Failed to serialize the step");
+
+        writeln!(self.stdin.as_mut()?, "{step}").ok()?;
+
+        // Events fired while idle come before those of the step
+        events.extend(self.idle_events());
+
+        loop {
+            match self.messages.recv().ok()? {
+                Message::Event { event } => events.push(event),
+                Message::Reply {
+                    ok: true, result, ..
+                } => return Some(Ok(result)),
+                Message::Reply { failure, .. } => {
+                    let failure = failure?;
+
+                    return Some(Err(StepFailure {
+                        status: ExitStatus::from_code(failure.status.into())
+                            .unwrap_or(ExitStatus::Failure),
+                        message: failure.message,
+                        report: failure.error,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // The worker releases the objects of the session once its input ends
+        drop(self.stdin.take());
+
+        let _ = self.child.wait();
+    }
+}
+
+/// The objects of a client of the servers, owned by this process or, with
+/// `--isolate`, by a worker
+pub enum Session {
+    InProcess(ComSession),
+    Isolated {
+        options: RunOptions,
+        /// `None` until the first step, and again after the worker crashed
+        worker: Option<Worker>,
+        events: Vec<Value>,
+    },
+}
+
+impl Session {
+    pub fn open(options: &RunOptions) -> Self {
+        match isolated() {
+            false => Self::InProcess(ComSession::new(options)),
+            true => Self::Isolated {
+                options: options.clone(),
+                worker: None,
+                events: Vec::new(),
+            },
+        }
+    }
+
+    /// Runs one step of the session and returns its result
+    pub fn execute(&mut self, step: &Step) -> std::result::Result<Value, StepFailure> {
+        let (options, worker, events) = match self {
+            Self::InProcess(session) => return session.execute(step).map_err(StepFailure::from),
+            Self::Isolated {
+                options,
+                worker,
+                events,
+            } => (options, worker, events),
+        };
+        let running = match worker {
+            Some(worker) => worker,
+            None => worker.insert(Worker::start(options).map_err(StepFailure::unavailable)?),
+        };
+
+        match running.execute(step, events) {
+            Some(outcome) => outcome,
+            None => {
+                let mut crashed = worker.take().expect("The worker is running");
+                let failure = StepFailure::crashed(&mut crashed.child);
+
+                warn!("{}", failure.message);
+                Err(failure)
+            }
+        }
+    }
+
+    /// Takes the events fired since the last time by the objects subscribed to
+    pub fn take_events(&mut self) -> Vec<Value> {
+        match self {
+            Self::InProcess(session) => session.take_events(),
+            Self::Isolated { worker, events, .. } => {
+                if let Some(worker) = worker {
+                    events.extend(worker.idle_events());
+                }
+
+                std::mem::take(events)
+            }
+        }
+    }
+}
+
+/// Writes a message of a worker to its stdout, which only the worker writes to
+fn send(message: &Message) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    serde_json::to_writer(&mut stdout, message)?;
+    writeln!(stdout)?;
+    stdout.flush()
+}
+
+/// Runs the steps of a session as a worker until its input ends. Events are dispatched
+/// while idle too, for the callbacks of their subscriptions.
+pub fn serve() -> ExitStatus {
+    // A step whose call could not be cancelled still gets its reply
+    set_abort_handler(|result| {
+        let _ = send(&Message::Reply {
+            ok: false,
+            result: Value::Null,
+            failure: serde_json::from_value(result["error"].clone()).ok().map(
+                |error: Box<ErrorReport>| Failure {
+                    status: ExitStatus::Timeout as u8,
+                    message: error.message.clone(),
+                    error,
+                },
+            ),
+        });
+    });
+
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if line.is_err() || sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let options = match receiver
+        .recv()
+        .map(|line| serde_json::from_str(&line.unwrap_or_default()))
+    {
+        Ok(Ok(options)) => options,
+        _ => return ExitStatus::InvalidRequest,
+    };
+    let mut session = ComSession::new(&options);
+
+    loop {
+        let reply = match receiver.recv_timeout(IDLE_INTERVAL) {
+            Ok(Ok(line)) => Some(match serde_json::from_str::<Step>(&line) {
+                Ok(step) => session.execute(&step).map_err(StepFailure::from),
+                Err(error) => Err(StepFailure {
+                    status: ExitStatus::InvalidRequest,
+                    message: error.to_string(),
+                    report: Box::new(ErrorReport::invalid_request(error.to_string())),
+                }),
+            }),
+            Err(RecvTimeoutError::Timeout) => None,
+            Ok(Err(_)) | Err(RecvTimeoutError::Disconnected) => return ExitStatus::Success,
+        };
+        let mut messages: Vec<Message> = session
+            .take_events()
+            .into_iter()
+            .map(|event| Message::Event { event })
+            .collect();
+
+        messages.extend(reply.map(|reply| match reply {
+            Ok(result) => Message::Reply {
+                ok: true,
+                result,
+                failure: None,
+            },
+            Err(failure) => Message::Reply {
+                ok: false,
+                result: Value::Null,
+                failure: Some(Failure {
+                    status: failure.status as u8,
+                    message: failure.message,
+                    error: failure.report,
+                }),
+            },
+        }));
+
+        if messages.iter().any(|message| send(message).is_err()) {
+            return ExitStatus::Failure;
+        }
+    }
+}